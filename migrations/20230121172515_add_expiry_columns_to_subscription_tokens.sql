@@ -0,0 +1,5 @@
+ALTER TABLE subscription_tokens
+ADD COLUMN created_at timestamptz NOT NULL DEFAULT NOW();
+
+ALTER TABLE subscription_tokens
+ADD COLUMN consumed_at timestamptz NULL;
@@ -0,0 +1,11 @@
+CREATE TABLE email_outbox (
+  email_id uuid NOT NULL,
+  recipient TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  n_retries smallint NOT NULL DEFAULT 0,
+  execute_after timestamptz NOT NULL DEFAULT NOW(),
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(email_id)
+);
@@ -0,0 +1,32 @@
+CREATE TABLE lists (
+  list_id uuid NOT NULL,
+  slug TEXT NOT NULL UNIQUE,
+  name TEXT NOT NULL,
+  is_default BOOLEAN NOT NULL DEFAULT false,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(list_id)
+);
+-- At most one list receives the signups that do not pick a list.
+CREATE UNIQUE INDEX lists_single_default ON lists (is_default) WHERE is_default;
+
+CREATE TABLE list_memberships (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  status TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(subscriber_id, list_id)
+);
+
+CREATE TABLE newsletter_issue_lists (
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+  list_id uuid NOT NULL REFERENCES lists (list_id),
+  PRIMARY KEY(newsletter_issue_id, list_id)
+);
+
+INSERT INTO lists (list_id, slug, name, is_default)
+VALUES ('553c872a-33b6-438b-a8bf-298a17836801', 'newsletter', 'Newsletter', true);
+
+-- Everybody who subscribed so far did so to the one and only newsletter.
+INSERT INTO list_memberships (subscriber_id, list_id, status)
+SELECT id, '553c872a-33b6-438b-a8bf-298a17836801', status
+FROM subscriptions;
@@ -0,0 +1,39 @@
+-- Every status a subscriber went through, oldest first.
+CREATE TABLE subscription_status_changes (
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  status TEXT NOT NULL,
+  changed_at timestamptz NOT NULL DEFAULT clock_timestamp()
+);
+CREATE INDEX subscription_status_changes_subscriber_id
+  ON subscription_status_changes (subscriber_id, changed_at);
+
+-- Recorded by the database, so that no code path changing a status can forget it.
+CREATE FUNCTION record_subscription_status_change() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO subscription_status_changes (subscriber_id, status)
+  VALUES (NEW.id, NEW.status);
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER subscription_created
+  AFTER INSERT ON subscriptions
+  FOR EACH ROW EXECUTE FUNCTION record_subscription_status_change();
+CREATE TRIGGER subscription_status_changed
+  AFTER UPDATE OF status ON subscriptions
+  FOR EACH ROW WHEN (OLD.status IS DISTINCT FROM NEW.status)
+  EXECUTE FUNCTION record_subscription_status_change();
+
+-- The past is unknown: start each history with the current status.
+INSERT INTO subscription_status_changes (subscriber_id, status, changed_at)
+SELECT id, status, subscribed_at
+FROM subscriptions;
+
+-- The issues that reached each subscriber.
+CREATE TABLE issue_deliveries (
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  delivered_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(newsletter_issue_id, subscriber_id)
+);
+CREATE INDEX issue_deliveries_subscriber_id ON issue_deliveries (subscriber_id);
@@ -0,0 +1,16 @@
+-- How subscribers who never went through the double opt-in agreed to receive
+-- the newsletter, e.g. "signup sheet at the 2022 conference".
+ALTER TABLE subscriptions ADD COLUMN consent_source TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN consented_at timestamptz NULL;
+
+CREATE TABLE subscriber_imports (
+  import_id uuid NOT NULL,
+  n_rows INTEGER NOT NULL,
+  n_created INTEGER NOT NULL,
+  n_updated INTEGER NOT NULL,
+  n_rejected INTEGER NOT NULL,
+  -- CSV with the rows that could not be imported, and why.
+  error_report TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(import_id)
+);
@@ -0,0 +1,8 @@
+-- What is left of the subscribers erased at their request: a SHA-256 of the
+-- canonical form of their address, so that they are not imported again by
+-- mistake under any spelling of it.
+CREATE TABLE erased_subscribers (
+  email_hash TEXT NOT NULL,
+  erased_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(email_hash)
+);
@@ -0,0 +1,18 @@
+-- What the email provider reported about the emails it sent.
+CREATE TABLE delivery_events (
+  event_id uuid NOT NULL,
+  event_type TEXT NOT NULL,
+  -- The identifier of the event at the provider, when it has one: providers
+  -- retry their webhooks, and an event must only count once.
+  provider_event_id TEXT NULL,
+  message_id TEXT NULL,
+  recipient TEXT NOT NULL,
+  bounce_type TEXT NULL,
+  occurred_at timestamptz NOT NULL,
+  received_at timestamptz NOT NULL DEFAULT NOW(),
+  payload JSONB NOT NULL,
+  PRIMARY KEY(event_id)
+);
+CREATE UNIQUE INDEX delivery_events_provider_event_id
+  ON delivery_events (event_type, provider_event_id);
+CREATE INDEX delivery_events_recipient ON delivery_events (lower(recipient));
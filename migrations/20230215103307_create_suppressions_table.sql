@@ -0,0 +1,11 @@
+-- Addresses that must never be emailed, whatever their subscription status.
+CREATE TABLE suppressions (
+  suppression_id uuid NOT NULL,
+  email TEXT NOT NULL,
+  reason TEXT NOT NULL,
+  -- Who or what added the address.
+  source TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  PRIMARY KEY(suppression_id)
+);
+CREATE UNIQUE INDEX suppressions_email ON suppressions (lower(email));
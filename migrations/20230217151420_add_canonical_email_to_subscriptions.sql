@@ -0,0 +1,26 @@
+-- What identifies a subscriber: `email` keeps the address as they typed it.
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT UNIQUE;
+-- Best effort for the existing addresses: internationalized domains are only
+-- lower-cased. When several of them share a canonical form, the oldest one
+-- gets it and the others are left without one, for an admin to merge.
+WITH canonical AS (
+  SELECT
+    id,
+    canonical_email,
+    row_number() OVER (PARTITION BY canonical_email ORDER BY subscribed_at, id) AS rank
+  FROM (
+    SELECT
+      id,
+      subscribed_at,
+      CASE
+        WHEN split_part(lower(email), '@', 2) IN ('gmail.com', 'googlemail.com')
+          THEN replace(split_part(split_part(lower(email), '@', 1), '+', 1), '.', '') || '@gmail.com'
+        ELSE lower(email)
+      END AS canonical_email
+    FROM subscriptions
+  ) AS s
+)
+UPDATE subscriptions
+SET canonical_email = canonical.canonical_email
+FROM canonical
+WHERE subscriptions.id = canonical.id AND canonical.rank = 1;
@@ -0,0 +1,2 @@
+-- The language subscribers are written to in, as a BCP 47 tag.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
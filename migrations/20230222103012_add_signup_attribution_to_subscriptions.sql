@@ -0,0 +1,9 @@
+-- Where subscribers signed up from. Reported by the signup form and the
+-- browser, unverified: only meant for aggregate counts.
+ALTER TABLE subscriptions ADD COLUMN signup_source TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_source TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_medium TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_campaign TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_term TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN utm_content TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN referrer TEXT NULL;
@@ -0,0 +1,21 @@
+-- Issues are written as drafts, marked as ready once reviewed, then sent:
+-- they are `sending` while deliveries are queued and `sent` once none are left.
+ALTER TABLE newsletter_issues ADD COLUMN status TEXT NOT NULL DEFAULT 'draft'
+  CHECK (status IN ('draft', 'ready', 'sending', 'sent'));
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NOT NULL DEFAULT NOW();
+ALTER TABLE newsletter_issues ADD COLUMN updated_at timestamptz NOT NULL DEFAULT NOW();
+-- Drafts have not been published yet.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+
+-- Every issue so far was published as soon as it was written.
+UPDATE newsletter_issues i
+SET
+  status = CASE
+    WHEN EXISTS (
+      SELECT 1 FROM issue_delivery_queue q
+      WHERE q.newsletter_issue_id = i.newsletter_issue_id
+    ) THEN 'sending'
+    ELSE 'sent'
+  END,
+  created_at = published_at::timestamptz,
+  updated_at = published_at::timestamptz;
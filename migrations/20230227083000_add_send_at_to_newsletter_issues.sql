@@ -0,0 +1,9 @@
+-- Ready issues can be scheduled: the delivery workers fan them out at `send_at`.
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+  CHECK (status IN ('draft', 'ready', 'scheduled', 'sending', 'sent'));
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_send_at_check
+  CHECK (status <> 'scheduled' OR send_at IS NOT NULL);
+-- The workers look for the scheduled issues that are due.
+CREATE INDEX newsletter_issues_send_at ON newsletter_issues (send_at) WHERE status = 'scheduled';
@@ -0,0 +1,3 @@
+-- The source of issues written in Markdown, from which both bodies are derived.
+-- Issues whose bodies were written by hand have none.
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
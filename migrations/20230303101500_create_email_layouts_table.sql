@@ -0,0 +1,14 @@
+-- Admin-managed templates wrapping the bodies of issues, with the footer
+-- every issue must carry.
+CREATE TABLE email_layouts (
+  layout_id uuid PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  html_template TEXT NOT NULL,
+  text_template TEXT NOT NULL,
+  postal_address TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT NOW(),
+  updated_at timestamptz NOT NULL DEFAULT NOW()
+);
+-- Issues without a layout are sent as they were written.
+ALTER TABLE newsletter_issues
+  ADD COLUMN layout_id uuid NULL REFERENCES email_layouts (layout_id);
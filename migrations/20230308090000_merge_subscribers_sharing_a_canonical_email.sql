@@ -0,0 +1,80 @@
+-- Fold a subscriber into another one reaching the same mailbox: what the
+-- duplicate owned moves over to the keeper, then the duplicate goes.
+CREATE FUNCTION merge_subscribers(keeper_id uuid, duplicate_id uuid) RETURNS void AS $$
+BEGIN
+  -- The latest decision of the owner prevails, e.g. unsubscribing under
+  -- another spelling of the address; a pending signup is no decision.
+  UPDATE subscriptions
+  SET
+    status = duplicate.status,
+    consent_source = duplicate.consent_source,
+    consented_at = duplicate.consented_at
+  FROM subscriptions AS duplicate
+  WHERE
+    subscriptions.id = keeper_id
+    AND duplicate.id = duplicate_id
+    AND duplicate.status <> 'pending_confirmation'
+    AND (
+      subscriptions.status = 'pending_confirmation'
+      OR (SELECT max(changed_at) FROM subscription_status_changes WHERE subscriber_id = duplicate_id)
+        > (SELECT max(changed_at) FROM subscription_status_changes WHERE subscriber_id = keeper_id)
+    );
+  UPDATE subscriptions
+  SET
+    subscribed_at = least(subscriptions.subscribed_at, duplicate.subscribed_at),
+    attributes = duplicate.attributes || subscriptions.attributes
+  FROM subscriptions AS duplicate
+  WHERE subscriptions.id = keeper_id AND duplicate.id = duplicate_id;
+
+  UPDATE subscription_tokens SET subscriber_id = keeper_id WHERE subscriber_id = duplicate_id;
+  UPDATE subscription_status_changes SET subscriber_id = keeper_id WHERE subscriber_id = duplicate_id;
+  -- The keeper's membership of a list they both belong to stands.
+  INSERT INTO list_memberships (subscriber_id, list_id, status, created_at)
+  SELECT keeper_id, list_id, status, created_at
+  FROM list_memberships
+  WHERE subscriber_id = duplicate_id
+  ON CONFLICT DO NOTHING;
+  DELETE FROM list_memberships WHERE subscriber_id = duplicate_id;
+  INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id, delivered_at)
+  SELECT newsletter_issue_id, keeper_id, delivered_at
+  FROM issue_deliveries
+  WHERE subscriber_id = duplicate_id
+  ON CONFLICT DO NOTHING;
+
+  DELETE FROM subscriptions WHERE id = duplicate_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- The subscribers left without a canonical form are merged into the one
+-- holding it. This approximates the default provider rules: run
+-- `zero2prod canonicalize-subscribers` if they are configured otherwise.
+DO $$
+DECLARE
+  duplicate RECORD;
+  keeper_id uuid;
+BEGIN
+  FOR duplicate IN
+    SELECT
+      id,
+      CASE
+        WHEN split_part(lower(email), '@', 2) IN ('gmail.com', 'googlemail.com')
+          THEN replace(split_part(split_part(lower(email), '@', 1), '+', 1), '.', '') || '@gmail.com'
+        ELSE lower(email)
+      END AS canonical_email
+    FROM subscriptions
+    WHERE canonical_email IS NULL
+    ORDER BY subscribed_at, id
+  LOOP
+    SELECT id INTO keeper_id FROM subscriptions WHERE canonical_email = duplicate.canonical_email;
+    IF keeper_id IS NULL THEN
+      UPDATE subscriptions SET canonical_email = duplicate.canonical_email WHERE id = duplicate.id;
+    ELSE
+      PERFORM merge_subscribers(keeper_id, duplicate.id);
+    END IF;
+  END LOOP;
+END;
+$$;
+
+ALTER TABLE subscriptions ALTER COLUMN canonical_email SET NOT NULL;
+-- Spellings of an address are told apart by their canonical form only.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
@@ -0,0 +1,20 @@
+-- A suppressed mailbox is not emailed under any spelling of its address,
+-- matched through its canonical form as subscribers are.
+ALTER TABLE suppressions ADD COLUMN canonical_email TEXT;
+-- This approximates the default provider rules: run
+-- `zero2prod canonicalize-subscribers` if they are configured otherwise.
+UPDATE suppressions
+SET canonical_email = CASE
+  WHEN split_part(lower(email), '@', 2) IN ('gmail.com', 'googlemail.com')
+    THEN replace(split_part(split_part(lower(email), '@', 1), '+', 1), '.', '') || '@gmail.com'
+  ELSE lower(email)
+END;
+-- A mailbox suppressed under several spellings keeps its oldest suppression.
+DELETE FROM suppressions
+USING suppressions AS older
+WHERE
+  suppressions.canonical_email = older.canonical_email
+  AND (older.created_at, older.suppression_id) < (suppressions.created_at, suppressions.suppression_id);
+ALTER TABLE suppressions ALTER COLUMN canonical_email SET NOT NULL;
+DROP INDEX suppressions_email;
+CREATE UNIQUE INDEX suppressions_canonical_email ON suppressions (canonical_email);
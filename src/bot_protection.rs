@@ -0,0 +1,399 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    configuration::{BotProtectionSettings, RateLimit},
+    rate_limit::{RateLimitDecision, RateLimiter},
+};
+
+/// Self-contained checks against scripted signups, performed on every
+/// submission of the subscribe form:
+/// - a honeypot field, hidden to humans, that naive bots fill in;
+/// - a signed token issued with the form, to reject submissions faster than
+///   a human could type or forms kept around for too long. Each token is
+///   accepted once, so that a solved form cannot be replayed;
+/// - optionally, a hashcash-style proof-of-work bound to that token and to
+///   the submitted email address.
+pub struct BotProtection {
+    enabled: bool,
+    hmac_secret: Secret<String>,
+    min_submit_seconds: i64,
+    max_form_age_seconds: i64,
+    proof_of_work_difficulty: u8,
+}
+
+/// The fields the subscribe form submits for the checks.
+pub struct BotCheck<'a> {
+    pub honeypot: &'a str,
+    pub email: &'a str,
+    pub form_token: Option<&'a str>,
+    pub pow_nonce: Option<&'a str>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BotCheckError {
+    #[error("The honeypot field was filled in.")]
+    HoneypotFilled,
+    #[error("The form token is missing.")]
+    MissingToken,
+    #[error("The form token is invalid.")]
+    InvalidToken,
+    #[error("The form was submitted too quickly.")]
+    TooFast,
+    #[error("The form has expired.")]
+    FormExpired,
+    #[error("The proof of work is missing or invalid.")]
+    InvalidProofOfWork,
+    #[error("The form has already been submitted.")]
+    ReplayedToken,
+}
+
+impl BotProtection {
+    pub fn new(settings: &BotProtectionSettings, hmac_secret: Secret<String>) -> Self {
+        Self {
+            enabled: settings.enabled,
+            hmac_secret,
+            min_submit_seconds: settings.min_submit_seconds as i64,
+            max_form_age_seconds: settings.max_form_age_seconds as i64,
+            proof_of_work_difficulty: settings.proof_of_work_difficulty,
+        }
+    }
+
+    /// Number of leading zero bits required from the proof-of-work hash; 0 disables it.
+    pub fn proof_of_work_difficulty(&self) -> u8 {
+        self.proof_of_work_difficulty
+    }
+
+    /// The token to embed in the form when serving it:
+    /// `<issued_at>.<form_id>.<signature>`. The random form id tells apart
+    /// the forms served in the same second.
+    pub fn issue_form_token(&self) -> String {
+        let issued_at = Utc::now().timestamp();
+        let form_id = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+        format!(
+            "{}.{}.{}",
+            issued_at,
+            form_id,
+            self.sign(issued_at, &form_id)
+        )
+    }
+
+    pub fn check(&self, submission: &BotCheck<'_>) -> Result<(), BotCheckError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if !submission.honeypot.is_empty() {
+            return Err(BotCheckError::HoneypotFilled);
+        }
+
+        let form_token = submission.form_token.ok_or(BotCheckError::MissingToken)?;
+        let issued_at = self.verify_form_token(form_token)?;
+        let age = Utc::now().timestamp() - issued_at;
+        if age < self.min_submit_seconds {
+            return Err(BotCheckError::TooFast);
+        }
+        if age > self.max_form_age_seconds {
+            return Err(BotCheckError::FormExpired);
+        }
+
+        if self.proof_of_work_difficulty > 0 {
+            let nonce = submission
+                .pow_nonce
+                .ok_or(BotCheckError::InvalidProofOfWork)?;
+            if !is_valid_proof_of_work(
+                form_token,
+                submission.email,
+                nonce,
+                self.proof_of_work_difficulty,
+            ) {
+                return Err(BotCheckError::InvalidProofOfWork);
+            }
+        }
+        Ok(())
+    }
+
+    /// Record the token of an accepted submission, rejecting it if it was
+    /// seen before. Tokens are remembered for as long as they are valid.
+    ///
+    /// Like the rate limiter, this fails open: if the store is unavailable
+    /// the submission goes through.
+    pub async fn claim_form_token(
+        &self,
+        form_token: Option<&str>,
+        rate_limiter: &RateLimiter,
+    ) -> Result<(), BotCheckError> {
+        let form_token = match form_token {
+            Some(form_token) if self.enabled => form_token,
+            _ => return Ok(()),
+        };
+        let once = RateLimit {
+            max_requests: 1,
+            window_seconds: self.max_form_age_seconds.max(1) as u64,
+        };
+        match rate_limiter
+            .hit(&format!("bot_protection:form_token:{}", form_token), once)
+            .await
+        {
+            Ok(RateLimitDecision::Allowed) => Ok(()),
+            Ok(RateLimitDecision::Limited { .. }) => Err(BotCheckError::ReplayedToken),
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to record a form token. Letting the submission through.",
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns when the form was issued.
+    fn verify_form_token(&self, form_token: &str) -> Result<i64, BotCheckError> {
+        let mut parts = form_token.split('.');
+        let (issued_at, form_id, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(issued_at), Some(form_id), Some(signature)) if parts.next().is_none() => {
+                (issued_at, form_id, signature)
+            }
+            _ => return Err(BotCheckError::InvalidToken),
+        };
+        let issued_at: i64 = issued_at.parse().map_err(|_| BotCheckError::InvalidToken)?;
+        let signature = hex::decode(signature).map_err(|_| BotCheckError::InvalidToken)?;
+        self.mac(issued_at, form_id)
+            .verify_slice(&signature)
+            .map_err(|_| BotCheckError::InvalidToken)?;
+        Ok(issued_at)
+    }
+
+    fn sign(&self, issued_at: i64, form_id: &str) -> String {
+        hex::encode(self.mac(issued_at, form_id).finalize().into_bytes())
+    }
+
+    fn mac(&self, issued_at: i64, form_id: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        // The secret signs other things too: scope the tag to this use case.
+        mac.update(b"subscribe-form:");
+        mac.update(format!("{}.{}", issued_at, form_id).as_bytes());
+        mac
+    }
+}
+
+/// Whether `SHA-256("<form_token>:<email>:<nonce>")` starts with `difficulty`
+/// zero bits. The email is trimmed, as the browser solving the challenge does.
+pub fn is_valid_proof_of_work(form_token: &str, email: &str, nonce: &str, difficulty: u8) -> bool {
+    let digest = Sha256::digest(format!("{}:{}:{}", form_token, email.trim(), nonce).as_bytes());
+    let mut remaining = u32::from(difficulty);
+    for byte in digest {
+        if remaining == 0 {
+            break;
+        }
+        let needed = remaining.min(8);
+        if byte.leading_zeros() < needed {
+            return false;
+        }
+        remaining -= needed;
+    }
+    remaining == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_proof_of_work, BotCheck, BotCheckError, BotProtection};
+    use crate::configuration::BotProtectionSettings;
+    use claims::{assert_matches, assert_ok};
+    use secrecy::Secret;
+    use sha2::Digest;
+
+    fn bot_protection(min_submit_seconds: u64, proof_of_work_difficulty: u8) -> BotProtection {
+        BotProtection::new(
+            &BotProtectionSettings {
+                enabled: true,
+                min_submit_seconds,
+                max_form_age_seconds: 3600,
+                proof_of_work_difficulty,
+            },
+            Secret::new("a-very-long-and-very-secret-key".into()),
+        )
+    }
+
+    const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+    fn submission<'a>(form_token: Option<&'a str>, pow_nonce: Option<&'a str>) -> BotCheck<'a> {
+        BotCheck {
+            honeypot: "",
+            email: EMAIL,
+            form_token,
+            pow_nonce,
+        }
+    }
+
+    fn solve(form_token: &str, email: &str, difficulty: u8) -> String {
+        (0u64..)
+            .map(|n| n.to_string())
+            .find(|nonce| is_valid_proof_of_work(form_token, email, nonce, difficulty))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_fresh_token_is_accepted_when_no_delay_is_required() {
+        let protection = bot_protection(0, 0);
+        let token = protection.issue_form_token();
+        assert_ok!(protection.check(&submission(Some(&token), None)));
+    }
+
+    #[test]
+    fn a_filled_honeypot_is_rejected() {
+        let protection = bot_protection(0, 0);
+        let token = protection.issue_form_token();
+        let check = BotCheck {
+            honeypot: "https://spam.example.com",
+            email: EMAIL,
+            form_token: Some(&token),
+            pow_nonce: None,
+        };
+        assert_matches!(protection.check(&check), Err(BotCheckError::HoneypotFilled));
+    }
+
+    #[test]
+    fn a_missing_token_is_rejected() {
+        let protection = bot_protection(0, 0);
+        assert_matches!(
+            protection.check(&submission(None, None)),
+            Err(BotCheckError::MissingToken)
+        );
+    }
+
+    #[test]
+    fn a_forged_token_is_rejected() {
+        let protection = bot_protection(0, 0);
+        let token = protection.issue_form_token();
+        let (_, rest) = token.split_once('.').unwrap();
+        let (form_id, signature) = rest.split_once('.').unwrap();
+        for forged in [
+            format!("0.{}.{}", form_id, signature),
+            format!("{}.{}", chrono::Utc::now().timestamp(), signature),
+            format!("{}.0.{}", chrono::Utc::now().timestamp(), signature),
+        ] {
+            assert_matches!(
+                protection.check(&submission(Some(&forged), None)),
+                Err(BotCheckError::InvalidToken)
+            );
+        }
+        assert_matches!(
+            protection.check(&submission(Some("not-a-token"), None)),
+            Err(BotCheckError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn a_token_issued_by_another_secret_is_rejected() {
+        let other = BotProtection::new(
+            &BotProtectionSettings {
+                enabled: true,
+                min_submit_seconds: 0,
+                max_form_age_seconds: 3600,
+                proof_of_work_difficulty: 0,
+            },
+            Secret::new("another-secret".into()),
+        );
+        let token = other.issue_form_token();
+        assert_matches!(
+            bot_protection(0, 0).check(&submission(Some(&token), None)),
+            Err(BotCheckError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn submissions_faster_than_the_minimum_delay_are_rejected() {
+        let protection = bot_protection(60, 0);
+        let token = protection.issue_form_token();
+        assert_matches!(
+            protection.check(&submission(Some(&token), None)),
+            Err(BotCheckError::TooFast)
+        );
+    }
+
+    #[test]
+    fn old_forms_are_rejected() {
+        let protection = bot_protection(0, 0);
+        let issued_at = chrono::Utc::now().timestamp() - 7200;
+        let token = format!("{}.id.{}", issued_at, protection.sign(issued_at, "id"));
+        assert_matches!(
+            protection.check(&submission(Some(&token), None)),
+            Err(BotCheckError::FormExpired)
+        );
+    }
+
+    #[test]
+    fn a_valid_proof_of_work_is_accepted() {
+        let protection = bot_protection(0, 12);
+        let token = protection.issue_form_token();
+        let nonce = solve(&token, EMAIL, 12);
+        assert_ok!(protection.check(&submission(Some(&token), Some(&nonce))));
+    }
+
+    #[test]
+    fn a_proof_of_work_solved_for_another_email_is_rejected() {
+        let protection = bot_protection(0, 12);
+        let token = protection.issue_form_token();
+        let nonce = (0u64..)
+            .map(|n| n.to_string())
+            .find(|nonce| {
+                is_valid_proof_of_work(&token, "someone.else@gmail.com", nonce, 12)
+                    && !is_valid_proof_of_work(&token, EMAIL, nonce, 12)
+            })
+            .unwrap();
+        assert_matches!(
+            protection.check(&submission(Some(&token), Some(&nonce))),
+            Err(BotCheckError::InvalidProofOfWork)
+        );
+    }
+
+    #[test]
+    fn a_missing_or_wrong_proof_of_work_is_rejected() {
+        let protection = bot_protection(0, 12);
+        let token = protection.issue_form_token();
+        assert_matches!(
+            protection.check(&submission(Some(&token), None)),
+            Err(BotCheckError::InvalidProofOfWork)
+        );
+        let nonce = (0u64..)
+            .map(|n| n.to_string())
+            .find(|nonce| !is_valid_proof_of_work(&token, EMAIL, nonce, 12))
+            .unwrap();
+        assert_matches!(
+            protection.check(&submission(Some(&token), Some(&nonce))),
+            Err(BotCheckError::InvalidProofOfWork)
+        );
+    }
+
+    #[test]
+    fn leading_zero_bits_are_counted_across_bytes() {
+        let nonce = solve("token", EMAIL, 10);
+        let digest = sha2::Sha256::digest(format!("token:{}:{}", EMAIL, nonce).as_bytes());
+        assert_eq!(digest[0], 0);
+        assert!(digest[1] < 0b0100_0000);
+    }
+
+    #[test]
+    fn disabled_protection_accepts_anything() {
+        let protection = BotProtection::new(
+            &BotProtectionSettings {
+                enabled: false,
+                min_submit_seconds: 60,
+                max_form_age_seconds: 3600,
+                proof_of_work_difficulty: 20,
+            },
+            Secret::new("a-very-long-and-very-secret-key".into()),
+        );
+        assert_ok!(protection.check(&BotCheck {
+            honeypot: "filled",
+            email: EMAIL,
+            form_token: None,
+            pow_nonce: None,
+        }));
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
@@ -7,7 +7,10 @@ use sqlx::{
     ConnectOptions,
 };
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    domain::{CanonicalEmailRules, ProviderRule, SubscriberEmail},
+    email_client::EmailClient,
+};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
@@ -15,6 +18,103 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
     pub redis_uri: Secret<String>,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_verification: EmailVerificationSettings,
+    pub webhooks: WebhookSettings,
+    pub canonical_emails: CanonicalEmailSettings,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct CanonicalEmailSettings {
+    /// Providers whose dots, suffixes and alias domains are folded away.
+    #[serde(default)]
+    pub provider_rules: Vec<ProviderRule>,
+}
+
+impl CanonicalEmailSettings {
+    pub fn rules(&self) -> CanonicalEmailRules {
+        CanonicalEmailRules::new(self.provider_rules.clone())
+    }
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub postmark: WebhookCredentials,
+}
+
+/// Basic auth credentials, set in the URL of the webhook at the provider.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookCredentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailVerificationSettings {
+    /// Reject addresses on domains that do not receive email.
+    pub check_mail_servers: bool,
+    pub resolver: MailDomainResolverKind,
+    /// Mail servers of each domain known to the `static` resolver.
+    #[serde(default)]
+    pub static_mail_servers: HashMap<String, Vec<String>>,
+    /// Throwaway inbox providers; their subdomains are rejected too.
+    #[serde(default)]
+    pub disposable_domains: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MailDomainResolverKind {
+    Dns,
+    /// Answers from `static_mail_servers`, without network access: meant for tests.
+    Static,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    pub enabled: bool,
+    /// Humans take a few seconds to fill the subscribe form in.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_submit_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: u64,
+    /// Leading zero bits required from the proof-of-work hash; 0 disables it.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub proof_of_work_difficulty: u8,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackend,
+    /// Only enable behind a proxy that sets `Forwarded`/`X-Forwarded-For`:
+    /// clients can forge these headers otherwise.
+    pub trust_proxy_headers: bool,
+    pub per_ip: RateLimit,
+    pub per_email: RateLimit,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    Redis,
+    /// Counters are local to the process: meant for tests.
+    InMemory,
+}
+
+/// At most `max_requests` in each window of `window_seconds`.
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+pub struct RateLimit {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+}
+
+impl RateLimit {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_seconds)
+    }
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -63,6 +163,13 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    pub subscription_token_ttl_hours: u64,
+}
+
+impl ApplicationSettings {
+    pub fn subscription_token_ttl(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_ttl_hours as i64)
+    }
 }
 
 impl DatabaseSettings {
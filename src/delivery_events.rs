@@ -0,0 +1,269 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{types::Json, PgPool};
+use uuid::Uuid;
+
+/// The bounce types after which an address will never receive anything again.
+const HARD_BOUNCE_TYPES: [&str; 3] = ["HardBounce", "BadEmailAddress", "ManuallyDeactivated"];
+
+/// A Postmark webhook payload, told apart by its `RecordType`.
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "RecordType")]
+pub enum PostmarkEvent {
+    Bounce(PostmarkBounce),
+    SpamComplaint(PostmarkBounce),
+    Delivery(PostmarkDelivery),
+    Open(PostmarkOpen),
+    /// Clicks, subscription changes, and whatever Postmark adds next.
+    #[serde(other)]
+    Other,
+}
+
+/// Bounces and spam complaints share their shape.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkBounce {
+    #[serde(rename = "ID")]
+    pub id: i64,
+    #[serde(rename = "Type")]
+    pub bounce_type: String,
+    #[serde(rename = "MessageID")]
+    pub message_id: Option<String>,
+    pub email: String,
+    pub bounced_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkDelivery {
+    #[serde(rename = "MessageID")]
+    pub message_id: String,
+    pub recipient: String,
+    pub delivered_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkOpen {
+    #[serde(rename = "MessageID")]
+    pub message_id: String,
+    pub recipient: String,
+    pub received_at: DateTime<Utc>,
+}
+
+/// A provider event, as stored in `delivery_events`.
+#[derive(Debug, PartialEq)]
+pub struct DeliveryEvent {
+    pub event_type: &'static str,
+    pub provider_event_id: Option<String>,
+    pub message_id: Option<String>,
+    pub recipient: String,
+    pub bounce_type: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl DeliveryEvent {
+    /// `None` for the record types that are not tracked.
+    pub fn from_postmark(event: PostmarkEvent) -> Option<Self> {
+        let event = match event {
+            PostmarkEvent::Bounce(bounce) => Self::from_bounce("bounce", bounce),
+            PostmarkEvent::SpamComplaint(complaint) => {
+                Self::from_bounce("spam_complaint", complaint)
+            }
+            // A message is delivered once: its id identifies the event.
+            PostmarkEvent::Delivery(delivery) => Self {
+                event_type: "delivery",
+                provider_event_id: Some(delivery.message_id.clone()),
+                message_id: Some(delivery.message_id),
+                recipient: delivery.recipient,
+                bounce_type: None,
+                occurred_at: delivery.delivered_at,
+            },
+            // Every open is recorded.
+            PostmarkEvent::Open(open) => Self {
+                event_type: "open",
+                provider_event_id: None,
+                message_id: Some(open.message_id),
+                recipient: open.recipient,
+                bounce_type: None,
+                occurred_at: open.received_at,
+            },
+            PostmarkEvent::Other => return None,
+        };
+        Some(event)
+    }
+
+    fn from_bounce(event_type: &'static str, bounce: PostmarkBounce) -> Self {
+        Self {
+            event_type,
+            provider_event_id: Some(bounce.id.to_string()),
+            message_id: bounce.message_id,
+            recipient: bounce.email,
+            bounce_type: Some(bounce.bounce_type),
+            occurred_at: bounce.bounced_at,
+        }
+    }
+
+    /// The status the recipient must be moved to, if they must not be sent
+    /// anything anymore.
+    pub fn suppressed_status(&self) -> Option<&'static str> {
+        let bounce_type = self.bounce_type.as_deref();
+        match self.event_type {
+            "spam_complaint" => Some("complained"),
+            // Postmark reports some complaints as bounces.
+            "bounce" if bounce_type == Some("SpamComplaint") => Some("complained"),
+            "bounce" if bounce_type.is_some_and(|t| HARD_BOUNCE_TYPES.contains(&t)) => {
+                Some("bounced")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Store the event and stop sending to the recipient if it calls for it.
+///
+/// Returns `false` if the event had already been recorded.
+#[tracing::instrument(name = "Record a delivery event", skip(db_pool, payload))]
+pub async fn record_delivery_event(
+    db_pool: &PgPool,
+    event: &DeliveryEvent,
+    payload: &serde_json::Value,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO delivery_events (
+            event_id, event_type, provider_event_id, message_id, recipient,
+            bounce_type, occurred_at, payload
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (event_type, provider_event_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.event_type,
+        event.provider_event_id,
+        event.message_id,
+        event.recipient,
+        event.bounce_type,
+        event.occurred_at,
+        Json(payload) as _,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store a delivery event")?
+    .rows_affected();
+    if n_inserted == 0 {
+        return Ok(false);
+    }
+
+    if let Some(status) = event.suppressed_status() {
+        // Unsubscribed subscribers keep their status: they chose it.
+        let n_suppressed = sqlx::query!(
+            r#"
+            UPDATE subscriptions SET status = $2
+            WHERE lower(email) = lower($1)
+                AND status IN ('pending_confirmation', 'confirmed')
+            "#,
+            event.recipient,
+            status,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to stop sending to a recipient")?
+        .rows_affected();
+        if n_suppressed > 0 {
+            tracing::info!(status, "Stopped sending to a subscriber");
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to record a delivery event")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeliveryEvent, PostmarkEvent};
+    use claims::{assert_none, assert_some_eq};
+
+    fn parse(payload: serde_json::Value) -> Option<DeliveryEvent> {
+        DeliveryEvent::from_postmark(serde_json::from_value::<PostmarkEvent>(payload).unwrap())
+    }
+
+    fn bounce(bounce_type: &str) -> serde_json::Value {
+        serde_json::json!({
+            "RecordType": "Bounce",
+            "ID": 4323372036854775807_i64,
+            "Type": bounce_type,
+            "TypeCode": 1,
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": "ursula@gmail.com",
+            "BouncedAt": "2023-02-13T16:33:54.9070259Z",
+            "Inactive": true
+        })
+    }
+
+    #[test]
+    fn hard_bounces_suppress_the_recipient() {
+        let event = parse(bounce("HardBounce")).unwrap();
+        assert_eq!(event.recipient, "ursula@gmail.com");
+        assert_eq!(
+            event.provider_event_id.as_deref(),
+            Some("4323372036854775807")
+        );
+        assert_some_eq!(event.suppressed_status(), "bounced");
+    }
+
+    #[test]
+    fn soft_bounces_do_not_suppress_the_recipient() {
+        for bounce_type in ["SoftBounce", "Transient", "AutoResponder", "DnsError"] {
+            assert_none!(parse(bounce(bounce_type)).unwrap().suppressed_status());
+        }
+    }
+
+    #[test]
+    fn spam_complaints_suppress_the_recipient() {
+        let mut payload = bounce("SpamComplaint");
+        payload["RecordType"] = "SpamComplaint".into();
+        let event = parse(payload).unwrap();
+        assert_eq!(event.event_type, "spam_complaint");
+        assert_some_eq!(event.suppressed_status(), "complained");
+    }
+
+    #[test]
+    fn deliveries_and_opens_are_parsed() {
+        let delivery = parse(serde_json::json!({
+            "RecordType": "Delivery",
+            "ServerID": 23,
+            "MessageStream": "outbound",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": "ursula@gmail.com",
+            "DeliveredAt": "2023-02-13T16:33:54.9070259Z",
+            "Details": "Test delivery webhook details"
+        }))
+        .unwrap();
+        assert_eq!(delivery.event_type, "delivery");
+        assert_none!(delivery.suppressed_status());
+
+        let open = parse(serde_json::json!({
+            "RecordType": "Open",
+            "FirstOpen": true,
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Recipient": "ursula@gmail.com",
+            "ReceivedAt": "2023-02-13T16:33:54.9070259Z"
+        }))
+        .unwrap();
+        assert_eq!(open.event_type, "open");
+        assert_none!(open.provider_event_id);
+    }
+
+    #[test]
+    fn other_record_types_are_ignored() {
+        assert_none!(parse(serde_json::json!({ "RecordType": "Click" })));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::markdown::{render_markdown, RenderedMarkdown};
+
+/// The bodies of a newsletter issue. Issues written in Markdown keep their
+/// source, to be edited again: both bodies are derived from it.
+#[derive(Debug)]
+pub struct IssueContent {
+    pub markdown_content: Option<String>,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+impl IssueContent {
+    /// Either the Markdown source or the bodies written by hand are given,
+    /// so that the bodies never drift apart from the source.
+    pub fn parse(
+        markdown_content: String,
+        html_content: String,
+        text_content: String,
+    ) -> Result<Self, String> {
+        if markdown_content.trim().is_empty() {
+            return Ok(Self {
+                markdown_content: None,
+                html_content,
+                text_content,
+            });
+        }
+        if !html_content.trim().is_empty() || !text_content.trim().is_empty() {
+            return Err("Write the issue either in Markdown or as HTML and text, not both.".into());
+        }
+        let RenderedMarkdown {
+            html_content,
+            text_content,
+        } = render_markdown(&markdown_content)?;
+        Ok(Self {
+            markdown_content: Some(markdown_content),
+            html_content,
+            text_content,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueContent;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn both_bodies_are_derived_from_markdown() {
+        let content = assert_ok!(IssueContent::parse(
+            "Hello *world*".into(),
+            "".into(),
+            " ".into()
+        ));
+        assert_eq!(content.markdown_content.as_deref(), Some("Hello *world*"));
+        assert_eq!(content.html_content, "<p>Hello <em>world</em></p>\n");
+        assert_eq!(content.text_content, "Hello _world_");
+    }
+
+    #[test]
+    fn bodies_written_by_hand_are_kept_as_they_are() {
+        let content = assert_ok!(IssueContent::parse(
+            "".into(),
+            "<p>Hello</p>".into(),
+            "Hello".into()
+        ));
+        assert_eq!(content.markdown_content, None);
+        assert_eq!(content.html_content, "<p>Hello</p>");
+        assert_eq!(content.text_content, "Hello");
+    }
+
+    #[test]
+    fn markdown_and_bodies_written_by_hand_are_rejected() {
+        assert_err!(IssueContent::parse(
+            "Hello".into(),
+            "<p>Hello</p>".into(),
+            "".into()
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+/// A BCP 47 language tag, such as `fr` or `pt-BR`, in its conventional case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale(String);
+
+impl Locale {
+    pub const DEFAULT: &'static str = "en";
+
+    /// Only the shape of the tag is checked: whether there are translations
+    /// for it is decided when rendering.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("{} is not a valid locale.", s);
+        let mut subtags = s.trim().split(['-', '_']);
+        let language = subtags.next().unwrap_or_default();
+        if !(2..=8).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            return Err(invalid());
+        }
+        let mut tag = language.to_ascii_lowercase();
+        for subtag in subtags {
+            if !(1..=8).contains(&subtag.len())
+                || !subtag.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                return Err(invalid());
+            }
+            tag.push('-');
+            match subtag.len() {
+                // Regions, such as `BR`
+                2 => tag.push_str(&subtag.to_ascii_uppercase()),
+                // Scripts, such as `Hant`
+                4 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    tag.push_str(&subtag[..1].to_ascii_uppercase());
+                    tag.push_str(&subtag[1..].to_ascii_lowercase());
+                }
+                _ => tag.push_str(&subtag.to_ascii_lowercase()),
+            }
+        }
+        Ok(Self(tag))
+    }
+
+    /// The primary language subtag, such as `pt` for `pt-BR`.
+    pub fn language(&self) -> &str {
+        self.0.split('-').next().unwrap_or_default()
+    }
+
+    /// From the most to the least specific: `zh-Hant-TW`, `zh-Hant`, `zh`.
+    pub fn fallback_chain(&self) -> impl Iterator<Item = &str> {
+        let tag = self.0.as_str();
+        std::iter::successors(Some(tag), |tag| {
+            tag.rsplit_once('-').map(|(parent, _)| parent)
+        })
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self(Self::DEFAULT.into())
+    }
+}
+
+impl std::fmt::Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl AsRef<str> for Locale {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Locale;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_ok_eq!(Locale::parse("FR"), Locale("fr".into()));
+        assert_ok_eq!(Locale::parse("pt_br"), Locale("pt-BR".into()));
+        assert_ok_eq!(Locale::parse("zh-hant-tw"), Locale("zh-Hant-TW".into()));
+        assert_ok_eq!(Locale::parse("es-419"), Locale("es-419".into()));
+    }
+
+    #[test]
+    fn malformed_tags_are_rejected() {
+        for tag in ["", "f", "french!", "fr-", "en--US", "*", "fr-toolongsubtag"] {
+            assert_err!(Locale::parse(tag));
+        }
+    }
+
+    #[test]
+    fn the_fallback_chain_drops_one_subtag_at_a_time() {
+        let locale = Locale::parse("zh-Hant-TW").unwrap();
+        assert_eq!(
+            locale.fallback_chain().collect::<Vec<_>>(),
+            vec!["zh-Hant-TW", "zh-Hant", "zh"]
+        );
+        assert_eq!(locale.language(), "zh");
+    }
+}
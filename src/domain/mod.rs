@@ -1,7 +1,17 @@
+mod issue_content;
+mod locale;
 mod new_subscriber;
+mod send_at;
+mod signup_source;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
 
-pub use new_subscriber::NewSubscriber;
-pub use subscriber_email::SubscriberEmail;
+pub use issue_content::IssueContent;
+pub use locale::Locale;
+pub use new_subscriber::{FieldError, NewSubscriber};
+pub use send_at::SendAt;
+pub use signup_source::SignupSource;
+pub use subscriber_attributes::SubscriberAttributes;
+pub use subscriber_email::{CanonicalEmailRules, ProviderRule, SubscriberEmail};
 pub use subscriber_name::SubscriberName;
@@ -1,6 +1,111 @@
-use super::{SubscriberEmail, SubscriberName};
+use super::{Locale, SignupSource, SubscriberAttributes, SubscriberEmail, SubscriberName};
 
+#[derive(Debug)]
 pub struct NewSubscriber {
     pub email: SubscriberEmail,
     pub name: SubscriberName,
+    pub attributes: SubscriberAttributes,
+    /// The language of the emails and pages they are sent.
+    pub locale: Locale,
+    pub signup_source: SignupSource,
+}
+
+/// A field of a subscription request that failed validation.
+#[derive(Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+impl NewSubscriber {
+    /// Validate every field, reporting all the invalid ones at once.
+    pub fn parse(email: String, name: String) -> Result<Self, Vec<FieldError>> {
+        Self::parse_with_details(email, name, None, None)
+    }
+
+    pub fn parse_with_details(
+        email: String,
+        name: String,
+        attributes: Option<serde_json::Value>,
+        locale: Option<String>,
+    ) -> Result<Self, Vec<FieldError>> {
+        let attributes = attributes
+            .map(SubscriberAttributes::parse)
+            .unwrap_or_else(|| Ok(SubscriberAttributes::default()));
+        let locale = locale
+            .map(|locale| Locale::parse(&locale))
+            .unwrap_or_else(|| Ok(Locale::default()));
+        match (
+            SubscriberEmail::parse(email),
+            SubscriberName::parse(name),
+            attributes,
+            locale,
+        ) {
+            (Ok(email), Ok(name), Ok(attributes), Ok(locale)) => Ok(Self {
+                email,
+                name,
+                attributes,
+                locale,
+                signup_source: SignupSource::default(),
+            }),
+            (email, name, attributes, locale) => Err([
+                ("email", email.err()),
+                ("name", name.err()),
+                ("attributes", attributes.err()),
+                ("locale", locale.err()),
+            ]
+            .into_iter()
+            .filter_map(|(field, message)| message.map(|message| FieldError { field, message }))
+            .collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NewSubscriber;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn valid_fields_are_parsed_successfully() {
+        assert_ok!(NewSubscriber::parse(
+            "ursula_le_guin@gmail.com".into(),
+            "Ursula Le Guin".into()
+        ));
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let errors = assert_err!(NewSubscriber::parse("".into(), "".into()));
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["email", "name"]);
+    }
+
+    #[test]
+    fn only_the_invalid_field_is_reported() {
+        let errors = assert_err!(NewSubscriber::parse(
+            "definitely-not-an-email".into(),
+            "Ursula Le Guin".into()
+        ));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, "email");
+    }
+
+    #[test]
+    fn invalid_attributes_are_reported_with_the_other_fields() {
+        let errors = assert_err!(NewSubscriber::parse_with_details(
+            "".into(),
+            "Ursula Le Guin".into(),
+            Some(serde_json::json!(["not", "an", "object"])),
+            Some("not a locale".into())
+        ));
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["email", "attributes", "locale"]);
+    }
 }
@@ -0,0 +1,86 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+/// When a scheduled issue goes out, necessarily in the future.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendAt(DateTime<Utc>);
+
+impl SendAt {
+    /// Date and time inputs send no time zone: those are taken as UTC.
+    /// Timestamps with an offset, such as `2023-02-27T09:00:00+01:00`, are
+    /// accepted too.
+    pub fn parse(s: &str, now: DateTime<Utc>) -> Result<Self, String> {
+        let s = s.trim();
+        let send_at = DateTime::parse_from_rfc3339(s)
+            .map(|send_at| send_at.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+                    .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S"))
+                    .map(|send_at| DateTime::<Utc>::from_utc(send_at, Utc))
+            })
+            .map_err(|_| {
+                format!(
+                    "{} is not a valid send time: use a date and time such as 2023-02-27T09:00.",
+                    s
+                )
+            })?;
+        if send_at <= now {
+            return Err("The send time must be in the future.".into());
+        }
+        Ok(Self(send_at))
+    }
+}
+
+impl AsRef<DateTime<Utc>> for SendAt {
+    fn as_ref(&self) -> &DateTime<Utc> {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SendAt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} UTC", self.0.format("%Y-%m-%d %H:%M"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SendAt;
+    use chrono::{DateTime, TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 2, 24, 17, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn date_and_time_inputs_are_read_as_utc() {
+        let send_at = assert_ok!(SendAt::parse("2023-02-27T09:00", now()));
+        assert_eq!(
+            send_at.as_ref(),
+            &Utc.with_ymd_and_hms(2023, 2, 27, 9, 0, 0).unwrap()
+        );
+        assert_ok!(SendAt::parse("2023-02-27T09:00:30", now()));
+    }
+
+    #[test]
+    fn offsets_are_taken_into_account() {
+        let send_at = assert_ok!(SendAt::parse("2023-02-27T09:00:00+01:00", now()));
+        assert_eq!(
+            send_at.as_ref(),
+            &Utc.with_ymd_and_hms(2023, 2, 27, 8, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn past_times_are_rejected() {
+        assert_err!(SendAt::parse("2023-02-24T17:30", now()));
+        assert_err!(SendAt::parse("2022-12-31T23:59", now()));
+    }
+
+    #[test]
+    fn malformed_times_are_rejected() {
+        for s in ["", "Monday 9am", "2023-02-30T09:00", "27/02/2023 09:00"] {
+            assert_err!(SendAt::parse(s, now()));
+        }
+    }
+}
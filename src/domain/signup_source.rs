@@ -0,0 +1,156 @@
+/// Where a subscriber signed up from: the campaign that brought them, as
+/// `utm_*` parameters, the embed or page that hosted the form, and the page
+/// they came from.
+///
+/// Readers and third-party pages report it as they please: it is good for
+/// aggregate counts, not to be relied upon for a single subscriber.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct SignupSource {
+    pub source: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+    pub referrer: Option<String>,
+}
+
+impl SignupSource {
+    const MAX_LENGTH: usize = 256;
+    const MAX_REFERRER_LENGTH: usize = 2048;
+
+    /// The attribution carried by a query string, e.g. of a campaign link.
+    ///
+    /// Links get mangled as they are shared: a repeated parameter keeps its
+    /// first value, and values which do not decode are ignored.
+    pub fn from_query(query: &str) -> Self {
+        let mut signup_source = Self::default();
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let (Some(key), Some(value)) = (decode(key), decode(value)) else {
+                continue;
+            };
+            let field = match key.as_str() {
+                "source" => &mut signup_source.source,
+                "utm_source" => &mut signup_source.utm_source,
+                "utm_medium" => &mut signup_source.utm_medium,
+                "utm_campaign" => &mut signup_source.utm_campaign,
+                "utm_term" => &mut signup_source.utm_term,
+                "utm_content" => &mut signup_source.utm_content,
+                "referrer" => &mut signup_source.referrer,
+                _ => continue,
+            };
+            if field.is_none() && !value.trim().is_empty() {
+                *field = Some(value);
+            }
+        }
+        signup_source
+    }
+
+    /// Blank values are dropped and long ones cut short: a bad attribution
+    /// is no reason to turn a subscriber away.
+    pub fn normalize(self) -> Self {
+        let field = |value| normalize(value, Self::MAX_LENGTH);
+        Self {
+            source: field(self.source),
+            utm_source: field(self.utm_source),
+            utm_medium: field(self.utm_medium),
+            utm_campaign: field(self.utm_campaign),
+            utm_term: field(self.utm_term),
+            utm_content: field(self.utm_content),
+            referrer: normalize(self.referrer, Self::MAX_REFERRER_LENGTH),
+        }
+    }
+
+    /// Fill in the values missing from `self` with those of `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            source: self.source.or(other.source),
+            utm_source: self.utm_source.or(other.utm_source),
+            utm_medium: self.utm_medium.or(other.utm_medium),
+            utm_campaign: self.utm_campaign.or(other.utm_campaign),
+            utm_term: self.utm_term.or(other.utm_term),
+            utm_content: self.utm_content.or(other.utm_content),
+            referrer: self.referrer.or(other.referrer),
+        }
+    }
+}
+
+/// A query string component, unless it is not valid UTF-8 once decoded.
+fn decode(component: &str) -> Option<String> {
+    urlencoding::decode(&component.replace('+', " "))
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+fn normalize(value: Option<String>, max_length: usize) -> Option<String> {
+    let value = value?;
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.chars().take(max_length).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SignupSource;
+
+    #[test]
+    fn blank_values_are_dropped() {
+        let source = SignupSource {
+            source: Some("  footer-embed ".into()),
+            utm_campaign: Some("   ".into()),
+            ..Default::default()
+        }
+        .normalize();
+        assert_eq!(source.source.as_deref(), Some("footer-embed"));
+        assert_eq!(source.utm_campaign, None);
+    }
+
+    #[test]
+    fn long_values_are_cut_short() {
+        let source = SignupSource {
+            utm_source: Some("ё".repeat(300)),
+            referrer: Some(format!("https://example.com/{}", "a".repeat(3000))),
+            ..Default::default()
+        }
+        .normalize();
+        assert_eq!(source.utm_source.unwrap().chars().count(), 256);
+        assert_eq!(source.referrer.unwrap().len(), 2048);
+    }
+
+    #[test]
+    fn missing_values_are_filled_in_from_the_other_source() {
+        let form = SignupSource {
+            source: Some("sidebar".into()),
+            ..Default::default()
+        };
+        let query = SignupSource {
+            source: Some("ignored".into()),
+            utm_source: Some("mastodon".into()),
+            ..Default::default()
+        };
+        let source = form.or(query);
+        assert_eq!(source.source.as_deref(), Some("sidebar"));
+        assert_eq!(source.utm_source.as_deref(), Some("mastodon"));
+    }
+
+    #[test]
+    fn the_query_string_is_decoded() {
+        let source =
+            SignupSource::from_query("utm_campaign=spring+launch&utm_term=caf%C3%A9&page=2");
+        assert_eq!(source.utm_campaign.as_deref(), Some("spring launch"));
+        assert_eq!(source.utm_term.as_deref(), Some("café"));
+    }
+
+    #[test]
+    fn the_first_usable_value_of_a_repeated_parameter_wins() {
+        let source = SignupSource::from_query(
+            "utm_source=a&utm_source=b&utm_medium=&utm_medium=email&utm_term=%FF&utm_term=rust",
+        );
+        assert_eq!(source.utm_source.as_deref(), Some("a"));
+        assert_eq!(source.utm_medium.as_deref(), Some("email"));
+        assert_eq!(source.utm_term.as_deref(), Some("rust"));
+    }
+}
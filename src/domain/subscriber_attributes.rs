@@ -0,0 +1,132 @@
+use serde_json::{Map, Value};
+
+/// Free-form details about a subscriber (company, plan, city, ...), available
+/// as `{{ subscriber.<key> }}` merge tags in newsletter issues.
+#[derive(Debug, Default)]
+pub struct SubscriberAttributes(Map<String, Value>);
+
+impl SubscriberAttributes {
+    const MAX_ATTRIBUTES: usize = 50;
+    const MAX_KEY_LENGTH: usize = 64;
+    const MAX_VALUE_LENGTH: usize = 1024;
+    /// Merge tags expose these next to the attributes.
+    const RESERVED_KEYS: [&'static str; 2] = ["email", "name"];
+
+    pub fn parse(value: Value) -> Result<Self, String> {
+        let attributes = match value {
+            Value::Object(attributes) => attributes,
+            _ => return Err("Attributes must be a JSON object.".into()),
+        };
+        if attributes.len() > Self::MAX_ATTRIBUTES {
+            return Err(format!(
+                "A subscriber cannot have more than {} attributes.",
+                Self::MAX_ATTRIBUTES
+            ));
+        }
+        for (key, value) in &attributes {
+            if !is_valid_key(key) {
+                return Err(format!(
+                    "{} is not a valid attribute name: use up to {} letters, digits and underscores, not starting with a digit.",
+                    key,
+                    Self::MAX_KEY_LENGTH
+                ));
+            }
+            if Self::RESERVED_KEYS.contains(&key.as_str()) {
+                return Err(format!("{} is a reserved attribute name.", key));
+            }
+            match value {
+                Value::Null | Value::Bool(_) | Value::Number(_) => {}
+                Value::String(s) if s.chars().count() <= Self::MAX_VALUE_LENGTH => {}
+                Value::String(_) => {
+                    return Err(format!(
+                        "The value of {} is longer than {} characters.",
+                        key,
+                        Self::MAX_VALUE_LENGTH
+                    ))
+                }
+                Value::Array(_) | Value::Object(_) => {
+                    return Err(format!(
+                        "The value of {} must be a string, a number, a boolean or null.",
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(Self(attributes))
+    }
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    let starts_correctly = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    starts_correctly
+        && key.len() <= SubscriberAttributes::MAX_KEY_LENGTH
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl AsRef<Map<String, Value>> for SubscriberAttributes {
+    fn as_ref(&self) -> &Map<String, Value> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberAttributes;
+    use claims::{assert_err, assert_ok};
+    use serde_json::json;
+
+    #[test]
+    fn scalar_attributes_are_accepted() {
+        assert_ok!(SubscriberAttributes::parse(json!({
+            "company": "Acme",
+            "seats": 42,
+            "beta_tester": true,
+            "city": null,
+        })));
+    }
+
+    #[test]
+    fn attributes_must_be_an_object() {
+        assert_err!(SubscriberAttributes::parse(json!(["company"])));
+        assert_err!(SubscriberAttributes::parse(json!("company")));
+    }
+
+    #[test]
+    fn nested_values_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(
+            json!({"address": {"city": "Paris"}})
+        ));
+        assert_err!(SubscriberAttributes::parse(json!({"tags": ["a", "b"]})));
+    }
+
+    #[test]
+    fn keys_must_be_usable_in_merge_tags() {
+        for key in ["", "1st", "first-name", "first name", "ville_é"] {
+            assert_err!(SubscriberAttributes::parse(json!({ key: "x" })));
+        }
+        assert_ok!(SubscriberAttributes::parse(json!({"_first_name2": "x"})));
+    }
+
+    #[test]
+    fn reserved_keys_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(json!({"name": "x"})));
+        assert_err!(SubscriberAttributes::parse(json!({"email": "x"})));
+    }
+
+    #[test]
+    fn too_many_attributes_are_rejected() {
+        let attributes: serde_json::Map<_, _> =
+            (0..51).map(|i| (format!("key_{}", i), json!(i))).collect();
+        assert_err!(SubscriberAttributes::parse(attributes.into()));
+    }
+
+    #[test]
+    fn overly_long_values_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(
+            json!({"bio": "a".repeat(1025)})
+        ));
+    }
+}
@@ -1,5 +1,10 @@
 use validator::validate_email;
 
+/// An email address, as typed by its owner.
+///
+/// The address is kept verbatim for display and sending: only the mail server
+/// of the domain knows for sure what the local part means. Duplicates are
+/// detected on the canonical form instead.
 #[derive(Debug)]
 pub struct SubscriberEmail(String);
 
@@ -11,6 +16,83 @@ impl SubscriberEmail {
             Err(format!("{} is not a valid subscriber email.", s))
         }
     }
+
+    /// The part after the `@`, as DNS knows it.
+    pub fn domain(&self) -> String {
+        let (_, domain) = self
+            .0
+            .rsplit_once('@')
+            .expect("A valid email contains an @");
+        ascii_domain(domain)
+    }
+
+    /// The form two addresses reaching the same mailbox have in common.
+    pub fn canonical(&self, rules: &CanonicalEmailRules) -> String {
+        rules.canonicalize(&self.0)
+    }
+}
+
+/// Mailbox providers known to deliver several spellings of an address to
+/// the same inbox.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderRule {
+    /// `j.doe+news@googlemail.com` reaches `jdoe@gmail.com`.
+    Gmail,
+}
+
+impl ProviderRule {
+    fn domains(&self) -> &'static [&'static str] {
+        match self {
+            Self::Gmail => &["gmail.com", "googlemail.com"],
+        }
+    }
+
+    fn canonicalize(&self, local_part: &str) -> String {
+        match self {
+            Self::Gmail => {
+                let local_part = local_part.split('+').next().unwrap_or_default();
+                format!("{}@gmail.com", local_part.replace('.', ""))
+            }
+        }
+    }
+}
+
+/// How addresses are folded into their canonical form.
+///
+/// The stored canonical forms are recomputed at startup when the rules
+/// change, see [`crate::subscriber_canonicalization`].
+#[derive(Clone, Debug, Default)]
+pub struct CanonicalEmailRules {
+    provider_rules: Vec<ProviderRule>,
+}
+
+impl CanonicalEmailRules {
+    pub fn new(provider_rules: Vec<ProviderRule>) -> Self {
+        Self { provider_rules }
+    }
+
+    /// Lower-case the address and encode an internationalized domain in
+    /// punycode, then apply the rules of its provider, if enabled.
+    fn canonicalize(&self, email: &str) -> String {
+        let (local_part, domain) = email.rsplit_once('@').expect("A valid email contains an @");
+        let domain = ascii_domain(domain);
+        let local_part = local_part.to_lowercase();
+        match self
+            .provider_rules
+            .iter()
+            .find(|rule| rule.domains().contains(&domain.as_str()))
+        {
+            Some(rule) => rule.canonicalize(&local_part),
+            None => format!("{}@{}", local_part, domain),
+        }
+    }
+}
+
+/// Lower-cased, with internationalized labels in punycode.
+fn ascii_domain(domain: &str) -> String {
+    // UTS #46 mapping also lower-cases the domain and normalizes its Unicode.
+    idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase())
 }
 
 impl std::fmt::Display for SubscriberEmail {
@@ -27,8 +109,8 @@ impl AsRef<str> for SubscriberEmail {
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberEmail;
-    use claims::assert_err;
+    use super::{CanonicalEmailRules, ProviderRule, SubscriberEmail};
+    use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
 
@@ -65,4 +147,60 @@ mod tests {
         let name = "@domain.com".to_string();
         assert_err!(SubscriberEmail::parse(name));
     }
+
+    fn canonical(email: &str) -> String {
+        let rules = CanonicalEmailRules::new(vec![ProviderRule::Gmail]);
+        SubscriberEmail::parse(email.into())
+            .unwrap()
+            .canonical(&rules)
+    }
+
+    #[test]
+    fn the_original_address_is_preserved() {
+        let email = SubscriberEmail::parse("Ursula.LeGuin@Example.com".into()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula.LeGuin@Example.com");
+        assert_eq!(
+            email.canonical(&CanonicalEmailRules::default()),
+            "ursula.leguin@example.com"
+        );
+    }
+
+    #[test]
+    fn internationalized_domains_are_encoded_in_punycode() {
+        assert_ok!(SubscriberEmail::parse("ursula@bücher.de".into()));
+        assert_eq!(canonical("ursula@Bücher.DE"), "ursula@xn--bcher-kva.de");
+        assert_eq!(
+            canonical("ursula@xn--bcher-kva.de"),
+            "ursula@xn--bcher-kva.de"
+        );
+    }
+
+    #[test]
+    fn gmail_dots_and_suffixes_are_folded() {
+        for email in [
+            "ursulaleguin@gmail.com",
+            "Ursula.Le.Guin@gmail.com",
+            "ursula.leguin+news@GMAIL.com",
+            "ursulaleguin@googlemail.com",
+        ] {
+            assert_eq!(canonical(email), "ursulaleguin@gmail.com");
+        }
+    }
+
+    #[test]
+    fn other_providers_keep_their_dots_and_suffixes() {
+        assert_eq!(
+            canonical("ursula.le.guin+news@example.com"),
+            "ursula.le.guin+news@example.com"
+        );
+    }
+
+    #[test]
+    fn gmail_addresses_are_only_folded_when_the_rule_is_enabled() {
+        let email = SubscriberEmail::parse("Ursula.LeGuin+news@googlemail.com".into()).unwrap();
+        assert_eq!(
+            email.canonical(&CanonicalEmailRules::default()),
+            "ursula.leguin+news@googlemail.com"
+        );
+    }
 }
@@ -26,19 +26,31 @@ impl EmailClient {
         }
     }
 
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), reqwest::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
     #[tracing::instrument(
     name = "Send an email"
-    skip(self, recipient, subject, html_content, text_content)
+    skip(self, recipient, subject, html_content, text_content, headers)
     fields(
         url = %self.base_url,
     )
     )]
-    pub async fn send_email(
+    pub async fn send_email_with_headers(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
+        headers: &[EmailHeader<'_>],
     ) -> Result<(), reqwest::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
@@ -47,6 +59,7 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            headers,
         };
         self.http_client
             .post(&url)
@@ -70,6 +83,16 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+}
+
+/// A custom header attached to an outgoing email (e.g. `List-Unsubscribe`).
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
 }
 
 #[cfg(test)]
@@ -77,13 +100,13 @@ mod tests {
     use std::time::Duration;
 
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, EmailHeader};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::Secret;
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
     struct SendEmailBodyMatcher;
@@ -146,6 +169,38 @@ mod tests {
         // Assert
     }
 
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_the_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            email(),
+            Secret::new(Faker.fake()),
+            Duration::from_millis(200),
+        );
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{ "Name": "List-Unsubscribe", "Value": "<https://example.com>" }]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe",
+            value: "<https://example.com>",
+        }];
+
+        // Act
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_the_server_returns_200() {
         // Arrange
@@ -0,0 +1,280 @@
+use sqlx::{PgExecutor, PgPool};
+use tera::{Context, Tera};
+use uuid::Uuid;
+
+use crate::{inline_css::inline_css, personalization::PersonalizedIssue};
+
+/// Wraps the bodies of the issues using it with a shared header, footer and
+/// styling.
+#[derive(serde::Serialize)]
+pub struct EmailLayout {
+    pub layout_id: Uuid,
+    pub name: String,
+    pub html_template: String,
+    pub text_template: String,
+    pub postal_address: String,
+}
+
+/// Offered when creating a layout, as a starting point.
+pub const STARTER_HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <style>
+      body { font-family: Georgia, serif; color: #222222; }
+      .footer { color: #777777; font-size: 12px; }
+    </style>
+  </head>
+  <body>
+    <h1>{{ title }}</h1>
+    {{ content | safe }}
+    <p class="footer">
+      {{ postal_address }}<br />
+      <a href="{{ unsubscribe_link }}">Unsubscribe</a>
+    </p>
+  </body>
+</html>
+"#;
+
+pub const STARTER_TEXT_TEMPLATE: &str = "{{ content }}
+
+--
+{{ postal_address }}
+Unsubscribe: {{ unsubscribe_link }}
+";
+
+#[derive(serde::Serialize)]
+struct LayoutContext<'a> {
+    title: &'a str,
+    content: &'a str,
+    unsubscribe_link: &'a str,
+    postal_address: &'a str,
+}
+
+impl EmailLayout {
+    /// Wrap the bodies of an issue, already personalized for the recipient.
+    /// CSS is inlined in the HTML body, as many mail clients ignore `<style>`.
+    pub fn wrap(
+        &self,
+        title: &str,
+        issue: &PersonalizedIssue,
+        unsubscribe_link: &str,
+    ) -> Result<PersonalizedIssue, tera::Error> {
+        let context = |content| LayoutContext {
+            title,
+            content,
+            unsubscribe_link,
+            postal_address: &self.postal_address,
+        };
+        let html_content = render(&self.html_template, context(&issue.html_content), true)?;
+        let text_content = render(&self.text_template, context(&issue.text_content), false)?;
+        Ok(PersonalizedIssue {
+            html_content: inline_css(&html_content),
+            text_content,
+        })
+    }
+}
+
+/// The bodies of an issue as they are sent. An issue is never sent without
+/// its layout, which carries the footer: if the layout cannot be rendered,
+/// the issue cannot be sent either.
+pub fn wrap_in_layout(
+    layout: Option<&EmailLayout>,
+    title: &str,
+    issue: PersonalizedIssue,
+    unsubscribe_link: &str,
+) -> Result<PersonalizedIssue, tera::Error> {
+    match layout {
+        Some(layout) => layout.wrap(title, &issue, unsubscribe_link),
+        None => Ok(issue),
+    }
+}
+
+fn render(
+    template: &str,
+    context: LayoutContext<'_>,
+    autoescape: bool,
+) -> Result<String, tera::Error> {
+    Tera::one_off(template, &Context::from_serialize(context)?, autoescape)
+}
+
+/// Both templates must render, and show the issue, the unsubscribe link and
+/// the postal address: the footer is what the law asks of bulk emails.
+pub fn validate_layout(html_template: &str, text_template: &str) -> Result<(), String> {
+    const CONTENT: &str = "<!-- content -->";
+    const UNSUBSCRIBE_LINK: &str = "UNSUBSCRIBE-LINK";
+    const POSTAL_ADDRESS: &str = "POSTAL-ADDRESS";
+
+    for (kind, template, autoescape, content_tag) in [
+        ("HTML", html_template, true, "{{ content | safe }}"),
+        ("text", text_template, false, "{{ content }}"),
+    ] {
+        let context = LayoutContext {
+            title: "Title",
+            content: CONTENT,
+            unsubscribe_link: UNSUBSCRIBE_LINK,
+            postal_address: POSTAL_ADDRESS,
+        };
+        let rendered = render(template, context, autoescape)
+            .map_err(|e| format!("The {} layout cannot be rendered: {}", kind, describe(&e)))?;
+        if !rendered.contains(CONTENT) {
+            return Err(format!(
+                "The {} layout must show the issue with {}.",
+                kind, content_tag
+            ));
+        }
+        if !rendered.contains(UNSUBSCRIBE_LINK) {
+            return Err(format!(
+                "The {} layout must include the unsubscribe link, {{{{ unsubscribe_link }}}}.",
+                kind
+            ));
+        }
+        if !rendered.contains(POSTAL_ADDRESS) {
+            return Err(format!(
+                "The {} layout must include the postal address, {{{{ postal_address }}}}.",
+                kind
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Tera explains what went wrong in the sources of its errors.
+fn describe(e: &tera::Error) -> String {
+    let mut description = e.to_string();
+    let mut current = std::error::Error::source(e);
+    while let Some(cause) = current {
+        description.push_str(&format!(": {}", cause));
+        current = cause.source();
+    }
+    description
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LayoutSelectionError {
+    #[error("There is no such email layout.")]
+    UnknownLayout,
+    #[error(transparent)]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+#[tracing::instrument(name = "Get email layouts", skip(db_pool))]
+pub async fn get_email_layouts(db_pool: &PgPool) -> Result<Vec<EmailLayout>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailLayout,
+        r#"
+        SELECT layout_id, name, html_template, text_template, postal_address
+        FROM email_layouts
+        ORDER BY name
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get an email layout", skip(executor))]
+pub async fn get_email_layout(
+    executor: impl PgExecutor<'_>,
+    layout_id: Uuid,
+) -> Result<Option<EmailLayout>, sqlx::Error> {
+    sqlx::query_as!(
+        EmailLayout,
+        r#"
+        SELECT layout_id, name, html_template, text_template, postal_address
+        FROM email_layouts
+        WHERE layout_id = $1
+        "#,
+        layout_id
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Turn the layout picked in a form into its id: picking none sends the
+/// issue as it was written.
+#[tracing::instrument(name = "Resolve email layout", skip(db_pool))]
+pub async fn resolve_email_layout(
+    db_pool: &PgPool,
+    layout_id: &str,
+) -> Result<Option<Uuid>, LayoutSelectionError> {
+    if layout_id.trim().is_empty() {
+        return Ok(None);
+    }
+    let layout_id: Uuid = layout_id
+        .trim()
+        .parse()
+        .map_err(|_| LayoutSelectionError::UnknownLayout)?;
+    sqlx::query!(
+        "SELECT layout_id FROM email_layouts WHERE layout_id = $1",
+        layout_id
+    )
+    .fetch_optional(db_pool)
+    .await?
+    .map(|r| Some(r.layout_id))
+    .ok_or(LayoutSelectionError::UnknownLayout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_layout, EmailLayout, STARTER_HTML_TEMPLATE, STARTER_TEXT_TEMPLATE};
+    use crate::personalization::PersonalizedIssue;
+    use claims::{assert_err, assert_ok};
+    use uuid::Uuid;
+
+    fn layout() -> EmailLayout {
+        EmailLayout {
+            layout_id: Uuid::new_v4(),
+            name: "Branded".into(),
+            html_template: STARTER_HTML_TEMPLATE.into(),
+            text_template: STARTER_TEXT_TEMPLATE.into(),
+            postal_address: "1 Infinite Loop, Cupertino".into(),
+        }
+    }
+
+    #[test]
+    fn the_starter_templates_are_valid() {
+        assert_ok!(validate_layout(
+            STARTER_HTML_TEMPLATE,
+            STARTER_TEXT_TEMPLATE
+        ));
+    }
+
+    #[test]
+    fn layouts_without_the_issue_or_the_footer_are_rejected() {
+        let html = STARTER_HTML_TEMPLATE;
+        let text = STARTER_TEXT_TEMPLATE;
+        for (html, text) in [
+            (
+                html.replace("{{ content | safe }}", "{{ content }}"),
+                text.into(),
+            ),
+            (html.replace("{{ unsubscribe_link }}", ""), text.into()),
+            (html.into(), text.replace("{{ postal_address }}", "")),
+            (html.replace("{{ title }}", "{{ title"), text.into()),
+        ] {
+            assert_err!(validate_layout(&html, &text));
+        }
+    }
+
+    #[test]
+    fn issues_are_wrapped_with_their_footer_and_inlined_styles() {
+        let issue = PersonalizedIssue {
+            html_content: "<p>Hello Ursula</p>".into(),
+            text_content: "Hello Ursula".into(),
+        };
+        let wrapped =
+            assert_ok!(layout().wrap("Monday <issue>", &issue, "https://example.com/unsubscribe"));
+        assert!(wrapped
+            .html_content
+            .contains("<h1>Monday &lt;issue&gt;</h1>\n    <p>Hello Ursula</p>"));
+        assert!(wrapped
+            .html_content
+            .contains(r#"<a href="https://example.com/unsubscribe">Unsubscribe</a>"#));
+        assert!(wrapped
+            .html_content
+            .contains(r#"<body style="font-family: Georgia, serif;color: #222222;">"#));
+        assert_eq!(
+            wrapped.text_content,
+            "Hello Ursula\n\n--\n1 Infinite Loop, Cupertino\nUnsubscribe: https://example.com/unsubscribe\n"
+        );
+    }
+}
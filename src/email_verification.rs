@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use trust_dns_resolver::{error::ResolveErrorKind, proto::op::ResponseCode, TokioAsyncResolver};
+
+use crate::{
+    configuration::{EmailVerificationSettings, MailDomainResolverKind},
+    domain::SubscriberEmail,
+};
+
+/// Finds the servers receiving email for a domain.
+#[async_trait]
+pub trait MailDomainResolver: Send + Sync {
+    /// The mail servers of `domain`, from its MX records or, without any, from
+    /// its own address (RFC 5321). Empty when the domain does not receive email.
+    async fn mail_servers(&self, domain: &str) -> Result<Vec<String>, anyhow::Error>;
+}
+
+pub struct DnsResolver(TokioAsyncResolver);
+
+impl DnsResolver {
+    pub fn from_system_conf() -> Result<Self, anyhow::Error> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .context("Failed to read the system DNS configuration")?;
+        Ok(Self(resolver))
+    }
+}
+
+#[async_trait]
+impl MailDomainResolver for DnsResolver {
+    #[tracing::instrument(name = "Look up mail servers", skip(self))]
+    async fn mail_servers(&self, domain: &str) -> Result<Vec<String>, anyhow::Error> {
+        // Fully qualified, so that the search domains of the host are not tried.
+        let fqdn = format!("{}.", domain.trim_end_matches('.'));
+        match self.0.mx_lookup(fqdn.as_str()).await {
+            // A single MX pointing at the root is a "null MX" (RFC 7505):
+            // the domain explicitly does not receive email.
+            Ok(mx) => Ok(mx
+                .iter()
+                .filter(|mx| !mx.exchange().is_root())
+                .map(|mx| mx.exchange().to_utf8())
+                .collect()),
+            Err(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { response_code, .. }
+                    if *response_code == ResponseCode::NXDomain =>
+                {
+                    Ok(vec![])
+                }
+                ResolveErrorKind::NoRecordsFound { .. } => {
+                    match self.0.lookup_ip(fqdn.as_str()).await {
+                        Ok(_) => Ok(vec![domain.to_owned()]),
+                        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                            Ok(vec![])
+                        }
+                        Err(e) => Err(e).context("Failed to look up the address of a domain"),
+                    }
+                }
+                _ => Err(e).context("Failed to look up the MX records of a domain"),
+            },
+        }
+    }
+}
+
+/// Answers from a fixed map, without network access.
+pub struct StaticResolver(HashMap<String, Vec<String>>);
+
+impl StaticResolver {
+    pub fn new(mail_servers: HashMap<String, Vec<String>>) -> Self {
+        Self(
+            mail_servers
+                .into_iter()
+                .map(|(domain, servers)| (domain.to_lowercase(), servers))
+                .collect(),
+        )
+    }
+}
+
+#[async_trait]
+impl MailDomainResolver for StaticResolver {
+    async fn mail_servers(&self, domain: &str) -> Result<Vec<String>, anyhow::Error> {
+        Ok(self.0.get(domain).cloned().unwrap_or_default())
+    }
+}
+
+/// Why an address that is valid on paper cannot be used.
+#[derive(thiserror::Error, Debug)]
+pub enum UndeliverableEmail {
+    #[error("{0} is a disposable email provider. Please use a permanent address.")]
+    DisposableDomain(String),
+    #[error("{0} does not receive emails. Please check the address for typos.")]
+    NoMailServer(String),
+}
+
+/// Catches the addresses that would bounce or vanish before storing them.
+pub struct EmailVerifier {
+    check_mail_servers: bool,
+    resolver: Box<dyn MailDomainResolver>,
+    disposable_domains: HashSet<String>,
+}
+
+impl EmailVerifier {
+    pub fn build(settings: &EmailVerificationSettings) -> Result<Self, anyhow::Error> {
+        let resolver: Box<dyn MailDomainResolver> = match settings.resolver {
+            MailDomainResolverKind::Dns => Box::new(DnsResolver::from_system_conf()?),
+            MailDomainResolverKind::Static => {
+                Box::new(StaticResolver::new(settings.static_mail_servers.clone()))
+            }
+        };
+        Ok(Self::new(
+            resolver,
+            settings.check_mail_servers,
+            &settings.disposable_domains,
+        ))
+    }
+
+    pub fn new(
+        resolver: Box<dyn MailDomainResolver>,
+        check_mail_servers: bool,
+        disposable_domains: &[String],
+    ) -> Self {
+        Self {
+            check_mail_servers,
+            resolver,
+            disposable_domains: disposable_domains
+                .iter()
+                .map(|d| d.trim_end_matches('.').to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// A failed DNS lookup lets the address through: the double opt-in will
+    /// catch it if it really is undeliverable.
+    #[tracing::instrument(name = "Verify email deliverability", skip(self))]
+    pub async fn verify(&self, email: &SubscriberEmail) -> Result<(), UndeliverableEmail> {
+        let domain = email.domain().trim_end_matches('.').to_owned();
+        if self.is_disposable(&domain) {
+            return Err(UndeliverableEmail::DisposableDomain(domain));
+        }
+        if !self.check_mail_servers {
+            return Ok(());
+        }
+        match self.resolver.mail_servers(&domain).await {
+            Ok(servers) if servers.is_empty() => Err(UndeliverableEmail::NoMailServer(domain)),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to look up the mail servers of {}. Accepting the address.",
+                    domain
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// The domain itself, or any of its parents, is on the blocklist.
+    fn is_disposable(&self, domain: &str) -> bool {
+        let mut candidate = domain;
+        loop {
+            if self.disposable_domains.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) => candidate = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmailVerifier, MailDomainResolver, StaticResolver, UndeliverableEmail};
+    use crate::domain::SubscriberEmail;
+    use async_trait::async_trait;
+    use claims::{assert_matches, assert_ok};
+    use std::collections::HashMap;
+
+    fn verifier(check_mail_servers: bool) -> EmailVerifier {
+        let resolver = StaticResolver::new(HashMap::from([(
+            "gmail.com".to_string(),
+            vec!["gmail-smtp-in.l.google.com".to_string()],
+        )]));
+        EmailVerifier::new(
+            Box::new(resolver),
+            check_mail_servers,
+            &["mailinator.com".to_string()],
+        )
+    }
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn an_address_on_a_domain_with_mail_servers_is_accepted() {
+        assert_ok!(verifier(true).verify(&email("ursula@gmail.com")).await);
+    }
+
+    #[tokio::test]
+    async fn an_address_on_a_domain_without_mail_servers_is_rejected() {
+        assert_matches!(
+            verifier(true)
+                .verify(&email("ursula@no-mail.example"))
+                .await,
+            Err(UndeliverableEmail::NoMailServer(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn mail_servers_are_not_checked_when_disabled() {
+        assert_ok!(
+            verifier(false)
+                .verify(&email("ursula@no-mail.example"))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn disposable_domains_and_their_subdomains_are_rejected() {
+        for address in [
+            "ursula@mailinator.com",
+            "ursula@MAILINATOR.com",
+            "ursula@eu.mailinator.com",
+        ] {
+            assert_matches!(
+                verifier(false).verify(&email(address)).await,
+                Err(UndeliverableEmail::DisposableDomain(_))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_resolver_lets_the_address_through() {
+        struct FailingResolver;
+
+        #[async_trait]
+        impl MailDomainResolver for FailingResolver {
+            async fn mail_servers(&self, _domain: &str) -> Result<Vec<String>, anyhow::Error> {
+                anyhow::bail!("SERVFAIL")
+            }
+        }
+
+        let verifier = EmailVerifier::new(Box::new(FailingResolver), true, &[]);
+        assert_ok!(verifier.verify(&email("ursula@gmail.com")).await);
+    }
+}
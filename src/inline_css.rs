@@ -0,0 +1,91 @@
+use css_inline::CSSInliner;
+
+/// Copy the rules of `<style>` elements to the `style` attribute of the
+/// elements they match, as many mail clients ignore `<style>` elements.
+///
+/// Declarations in `style` attributes win over the rules, and more specific
+/// rules over less specific ones. The `<style>` elements are kept, for the
+/// media queries and the rules on hovered or focused elements that cannot be
+/// inlined. Nothing is fetched: external stylesheets are left as they are.
+pub fn inline_css(html: &str) -> String {
+    if !html.contains("<style") {
+        return html.to_owned();
+    }
+    let inliner = CSSInliner::options()
+        .keep_style_tags(true)
+        .keep_link_tags(true)
+        .load_remote_stylesheets(false)
+        .build();
+    inliner.inline(html).unwrap_or_else(|e| {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to inline the CSS of an email, sending it with its <style> elements",
+        );
+        html.to_owned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inline_css;
+
+    fn body(html: &str) -> &str {
+        let start = html.find("<body>").unwrap() + "<body>".len();
+        let end = html.find("</body>").unwrap();
+        &html[start..end]
+    }
+
+    #[test]
+    fn html_without_style_elements_is_left_as_it_is() {
+        let html = "<p>Hello</p>";
+        assert_eq!(inline_css(html), html);
+    }
+
+    #[test]
+    fn rules_are_copied_to_the_elements_they_match() {
+        let html = inline_css(
+            "<style>p { color: #222; margin: 0 } .footer { color: #777 }</style>\
+            <p>Hello</p><p class=\"footer\">Bye</p>",
+        );
+        assert_eq!(
+            body(&html),
+            "<p style=\"color: #222;margin: 0;\">Hello</p>\
+            <p class=\"footer\" style=\"margin: 0;color: #777;\">Bye</p>"
+        );
+    }
+
+    #[test]
+    fn more_specific_rules_and_style_attributes_win() {
+        let html = inline_css(
+            "<style>#intro { color: red } p.lead { color: green } p { color: blue; font-size: 14px }</style>\
+            <p id=\"intro\" class=\"lead\">A</p><p class=\"lead\" style=\"color: black\">B</p>",
+        );
+        assert_eq!(
+            body(&html),
+            "<p class=\"lead\" id=\"intro\" style=\"font-size: 14px;color: red;\">A</p>\
+            <p class=\"lead\" style=\"font-size: 14px;color: black\">B</p>"
+        );
+    }
+
+    #[test]
+    fn media_queries_and_dynamic_rules_stay_in_the_style_element() {
+        let html = inline_css(
+            "<style>/* Layout */ a { color: red } a:hover { color: blue }\
+            @media (max-width: 600px) { p { margin: 0 } }</style><a href=\"#\">Link</a>",
+        );
+        assert!(html.contains("@media (max-width: 600px) { p { margin: 0 } }</style>"));
+        assert!(html.contains("<a href=\"#\" style=\"color: red;\">Link</a>"));
+    }
+
+    #[test]
+    fn strings_and_urls_do_not_end_declarations() {
+        let html = inline_css(
+            "<style>p { font-family: \"A;B{}\", serif; background: url(\"x.png?a=1;b={}\") }</style>\
+            <p>Hello</p>",
+        );
+        assert!(html.contains(
+            "<p style=\"font-family: 'A;B{}', serif;background: url('x.png?a=1;b={}');\">Hello</p>"
+        ));
+    }
+}
@@ -1,15 +1,22 @@
 use std::time::Duration;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail, email_client::EmailClient,
+    configuration::Settings,
+    domain::{CanonicalEmailRules, SubscriberEmail},
+    email_client::{EmailClient, EmailHeader},
+    email_layouts::{get_email_layout, wrap_in_layout},
+    personalization::{personalize_issue, MergeFields, PersonalizedIssue},
     startup::get_db_pool,
+    suppressions::is_suppressed,
+    unsubscribe::unsubscribe_link,
 };
 use chrono::Utc;
 use rand::{
     rngs::{OsRng, StdRng},
     Rng, SeedableRng,
 };
-use sqlx::{PgPool, Postgres, Transaction};
+use secrecy::Secret;
+use sqlx::{types::Json, PgPool, Postgres, Transaction};
 use std::cmp::min;
 use tracing::{field::display, Span};
 use uuid::Uuid;
@@ -17,21 +24,46 @@ use uuid::Uuid;
 pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
     let connection_pool = get_db_pool(&configuration.database);
     let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    worker_loop(
+        connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.application.hmac_secret,
+        configuration.canonical_emails.rules(),
+    )
+    .await
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
+async fn worker_loop(
+    pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    rules: CanonicalEmailRules,
+) -> Result<(), anyhow::Error> {
     let mut rng = StdRng::from_seed(OsRng.gen());
     loop {
-        if try_execute_task(&pool, &email_client, &mut rng)
-            .await
-            .is_err()
-        {
+        let outbox_outcome = try_execute_outbox_task(&pool, &email_client, &rules, &mut rng).await;
+        let schedule_outcome = try_publish_scheduled_issue(&pool).await;
+        let issue_outcome = try_execute_task(
+            &pool,
+            &email_client,
+            &base_url,
+            &hmac_secret,
+            &rules,
+            &mut rng,
+        )
+        .await;
+        if outbox_outcome.is_err() || schedule_outcome.is_err() || issue_outcome.is_err() {
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
     }
 }
 
+/// A layout that still does not render after this many attempts, some hours
+/// in, is not going to be fixed in time for the issue to be relevant.
+const MAX_LAYOUT_RETRIES: i16 = 20;
+
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
@@ -41,6 +73,9 @@ pub enum ExecutionOutcome {
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    rules: &CanonicalEmailRules,
     rng: &mut StdRng,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
     let task = dequeue_task(pool).await?;
@@ -51,60 +86,108 @@ pub async fn try_execute_task(
     let (mut transaction, queue_item) = task.unwrap();
 
     Span::current()
-        .record("newsletter_issue_id", &display(queue_item.issue_id))
-        .record("subscriber_email", &display(&queue_item.email))
-        .record("n_retries", &display(queue_item.n_retries))
-        .record("execute_after", &display(&queue_item.execute_after));
+        .record("newsletter_issue_id", display(queue_item.issue_id))
+        .record("subscriber_email", display(&queue_item.email))
+        .record("n_retries", display(queue_item.n_retries))
+        .record("execute_after", display(&queue_item.execute_after));
+
+    let subscriber = match get_confirmed_subscriber(pool, &queue_item.email).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            tracing::info!("Skipping a subscriber who is no longer confirmed");
+            delete_task(transaction, queue_item.issue_id, &queue_item.email).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
 
     match SubscriberEmail::parse(queue_item.email.clone()) {
         Ok(email) => {
             let issue = get_issue(pool, queue_item.issue_id).await?;
+            let layout = match issue.layout_id {
+                Some(layout_id) => get_email_layout(pool, layout_id).await?,
+                None => None,
+            };
+            let merge_fields = MergeFields {
+                email: email.as_ref(),
+                name: &subscriber.name,
+                attributes: &subscriber.attributes,
+            };
+            let unsubscribe_link = unsubscribe_link(base_url, hmac_secret, subscriber.id);
+            let PersonalizedIssue {
+                html_content,
+                text_content,
+            } = match wrap_in_layout(
+                layout.as_ref(),
+                &issue.title,
+                personalize_issue(&issue.html_content, &issue.text_content, &merge_fields),
+                &unsubscribe_link,
+            ) {
+                Ok(wrapped) => wrapped,
+                Err(e) if queue_item.n_retries >= MAX_LAYOUT_RETRIES => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        n_retries = %queue_item.n_retries,
+                        "Failed to render the email layout of the issue. Giving up.",
+                    );
+                    delete_task(transaction, queue_item.issue_id, &queue_item.email).await?;
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+                Err(e) => {
+                    // Retried later, rather than sent without its footer
+                    let execute_after = retry_task(transaction, &queue_item, rng).await?;
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        n_retries = %queue_item.n_retries,
+                        execute_after = %execute_after,
+                        "Failed to render the email layout of the issue. Retrying.",
+                    );
+                    return Ok(ExecutionOutcome::TaskCompleted);
+                }
+            };
+            let list_unsubscribe = format!("<{}>", unsubscribe_link);
+            // RFC 8058: mail clients can unsubscribe with a single POST to the link
+            let headers = [
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: &list_unsubscribe,
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                },
+            ];
+            // Checked last, so that an address suppressed while the issue is
+            // being sent out is not emailed.
+            if is_suppressed(pool, &email, rules).await? {
+                tracing::info!("Skipping a suppressed address");
+                delete_task(transaction, queue_item.issue_id, email.as_ref()).await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
             match email_client
-                .send_email(
+                .send_email_with_headers(
                     &email,
                     &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
+                    &html_content,
+                    &text_content,
+                    &headers,
                 )
                 .await
             {
-                Ok(_) => delete_task(transaction, queue_item.issue_id, email.as_ref()).await?,
+                Ok(_) => {
+                    record_delivery(&mut transaction, queue_item.issue_id, subscriber.id).await?;
+                    delete_task(transaction, queue_item.issue_id, email.as_ref()).await?
+                }
                 Err(e) => {
-                    const CAP: u16 = 900;
-                    const BASE: u16 = 2;
-
-                    let max_wait_seconds =
-                        min(CAP, BASE.pow((queue_item.n_retries + 1).try_into()?)); // max wait is 5 minutes
-                    let wait_seconds = rng.gen_range(1..max_wait_seconds);
-                    let execute_after = Utc::now() + chrono::Duration::seconds(wait_seconds.into());
-
+                    let execute_after = retry_task(transaction, &queue_item, rng).await?;
                     tracing::error!(
                         error.cause_chain = ?e,
                         error.message = %e,
                         n_retries = %queue_item.n_retries,
                         execute_after = %execute_after,
-                        wait_seconds = %wait_seconds,
                         "Failed to deliver issue to a confirmed subscriber. Retrying.",
                     );
-
-                    sqlx::query!(
-                        r#"
-                        UPDATE issue_delivery_queue
-                        SET
-                            n_retries = $3,
-                            execute_after = $4
-                        WHERE
-                            newsletter_issue_id = $1
-                            AND subscriber_email = $2
-                        "#,
-                        queue_item.issue_id,
-                        email.to_string(),
-                        queue_item.n_retries + 1,
-                        execute_after,
-                    )
-                    .execute(&mut transaction)
-                    .await?;
-                    transaction.commit().await?;
                 }
             }
         }
@@ -120,6 +203,126 @@ pub async fn try_execute_task(
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+/// Fan out the next scheduled issue that is due, if any.
+///
+/// Other workers skip the issue while it is locked, and find it `sending`
+/// afterwards: it goes out once, whatever the number of workers.
+#[tracing::instrument(skip_all,fields(newsletter_issue_id=tracing::field::Empty),err)]
+pub async fn try_publish_scheduled_issue(pool: &PgPool) -> Result<ExecutionOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE status = 'scheduled' AND send_at <= NOW()
+        ORDER BY send_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let issue_id = match issue {
+        Some(issue) => issue.newsletter_issue_id,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current().record("newsletter_issue_id", display(issue_id));
+
+    enqueue_delivery_tasks(&mut transaction, issue_id).await?;
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Fan the issue out to the confirmed members of its lists, once per address
+/// even if they belong to several of them, leaving out suppressed addresses.
+///
+/// The issue is `sending` until the delivery workers empty its queue, or
+/// `sent` straight away if nobody is to receive it.
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let n_recipients = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT DISTINCT $1::uuid, s.email
+        FROM subscriptions s
+        JOIN list_memberships m ON m.subscriber_id = s.id
+        JOIN newsletter_issue_lists l ON l.list_id = m.list_id
+        WHERE l.newsletter_issue_id = $1
+            AND s.status = 'confirmed'
+            AND m.status = 'confirmed'
+            AND NOT EXISTS (
+                SELECT 1 FROM suppressions x WHERE x.canonical_email = s.canonical_email
+            )
+        "#,
+        newsletter_issue_id,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = CASE WHEN $2 THEN 'sending' ELSE 'sent' END,
+            published_at = NOW(),
+            updated_at = NOW()
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        n_recipients > 0,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// Put the task back in the queue, to be retried after a delay.
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    mut transaction: PgTransaction,
+    queue_item: &IssueDeliveryQueueItem,
+    rng: &mut StdRng,
+) -> Result<chrono::DateTime<Utc>, anyhow::Error> {
+    let execute_after = Utc::now() + retry_delay(queue_item.n_retries, rng)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = $3,
+            execute_after = $4
+        WHERE
+            newsletter_issue_id = $1
+            AND subscriber_email = $2
+        "#,
+        queue_item.issue_id,
+        queue_item.email,
+        queue_item.n_retries + 1,
+        execute_after,
+    )
+    .execute(&mut transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(execute_after)
+}
+
+/// Exponential backoff with jitter: wait up to 2^(n_retries + 1) seconds,
+/// capped at 15 minutes.
+fn retry_delay(n_retries: i16, rng: &mut StdRng) -> Result<chrono::Duration, anyhow::Error> {
+    const CAP: u16 = 900;
+    const BASE: u16 = 2;
+
+    let max_wait_seconds = min(CAP, BASE.saturating_pow((n_retries + 1).try_into()?));
+    let wait_seconds = rng.gen_range(1..max_wait_seconds);
+    Ok(chrono::Duration::seconds(wait_seconds.into()))
+}
+
 #[derive(Debug)]
 struct IssueDeliveryQueueItem {
     issue_id: Uuid,
@@ -161,6 +364,7 @@ async fn dequeue_task(
     }
 }
 
+/// The issue is `sent` once its last task is done.
 #[tracing::instrument(skip_all)]
 async fn delete_task(
     mut transaction: PgTransaction,
@@ -179,21 +383,96 @@ async fn delete_task(
     )
     .execute(&mut transaction)
     .await?;
+    // Workers finishing the last tasks of an issue take turns: whoever goes
+    // last sees every other deletion committed.
+    sqlx::query!(
+        "SELECT 1 AS locked FROM newsletter_issues WHERE newsletter_issue_id = $1 FOR UPDATE",
+        issue_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'sent', updated_at = NOW()
+        WHERE
+            newsletter_issue_id = $1
+            AND status = 'sending'
+            AND NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue WHERE newsletter_issue_id = $1
+            )
+        "#,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await?;
     transaction.commit().await?;
     Ok(())
 }
 
+/// Keep track of who received what, for the subscriber details page.
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_id)
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        issue_id,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    name: String,
+    attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_confirmed_subscriber(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<ConfirmedSubscriber>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT id, name, attributes as "attributes: Json<serde_json::Map<String, serde_json::Value>>"
+        FROM subscriptions
+        WHERE
+            email = $1
+            AND status = 'confirmed'
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(r.map(|r| ConfirmedSubscriber {
+        id: r.id,
+        name: r.name,
+        attributes: r.attributes.0,
+    }))
+}
+
 struct NewsletterIssue {
     title: String,
     text_content: String,
     html_content: String,
+    layout_id: Option<Uuid>,
 }
 #[tracing::instrument(skip_all)]
 async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, anyhow::Error> {
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
-        SELECT title, text_content, html_content
+        SELECT title, text_content, html_content, layout_id
         FROM newsletter_issues
         WHERE
             newsletter_issue_id = $1
@@ -204,3 +483,125 @@ async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<NewsletterIssue, any
     .await?;
     Ok(issue)
 }
+
+/// Deliver one of the transactional emails (e.g. subscription confirmations)
+/// waiting in the outbox, with the same retry policy as newsletter issues.
+#[tracing::instrument(skip_all,fields(email_id=tracing::field::Empty,recipient=tracing::field::Empty),err)]
+pub async fn try_execute_outbox_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rules: &CanonicalEmailRules,
+    rng: &mut StdRng,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_outbox_email(pool).await?;
+
+    if task.is_none() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    let (mut transaction, outbox_email) = task.unwrap();
+
+    Span::current()
+        .record("email_id", display(outbox_email.email_id))
+        .record("recipient", display(&outbox_email.recipient));
+
+    match SubscriberEmail::parse(outbox_email.recipient.clone()) {
+        Ok(recipient) => {
+            if is_suppressed(pool, &recipient, rules).await? {
+                tracing::info!("Skipping an email to a suppressed address");
+                delete_outbox_email(transaction, outbox_email.email_id).await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+            match email_client
+                .send_email(
+                    &recipient,
+                    &outbox_email.subject,
+                    &outbox_email.html_content,
+                    &outbox_email.text_content,
+                )
+                .await
+            {
+                Ok(_) => delete_outbox_email(transaction, outbox_email.email_id).await?,
+                Err(e) => {
+                    let wait = retry_delay(outbox_email.n_retries, rng)?;
+                    let execute_after = Utc::now() + wait;
+
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        n_retries = %outbox_email.n_retries,
+                        execute_after = %execute_after,
+                        wait_seconds = %wait.num_seconds(),
+                        "Failed to deliver an email from the outbox. Retrying.",
+                    );
+
+                    sqlx::query!(
+                        r#"
+                        UPDATE email_outbox
+                        SET
+                            n_retries = $2,
+                            execute_after = $3
+                        WHERE email_id = $1
+                        "#,
+                        outbox_email.email_id,
+                        outbox_email.n_retries + 1,
+                        execute_after,
+                    )
+                    .execute(&mut transaction)
+                    .await?;
+                    transaction.commit().await?;
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping an email from the outbox. Its recipient is invalid",
+            );
+            delete_outbox_email(transaction, outbox_email.email_id).await?;
+        }
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    text_content: String,
+    html_content: String,
+    n_retries: i16,
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_outbox_email(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, OutboxEmail)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT email_id, recipient, subject, text_content, html_content, n_retries
+        FROM email_outbox
+        WHERE execute_after < NOW()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(r.map(|r| (transaction, r)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_outbox_email(
+    mut transaction: PgTransaction,
+    email_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!("DELETE FROM email_outbox WHERE email_id = $1", email_id)
+        .execute(&mut transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(())
+}
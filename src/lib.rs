@@ -1,12 +1,27 @@
 pub mod authentication;
+pub mod bot_protection;
 pub mod configuration;
+pub mod delivery_events;
 pub mod domain;
 pub mod email_client;
+pub mod email_layouts;
+pub mod email_verification;
 pub mod idempotency;
+pub mod inline_css;
 pub mod issue_delivery_worker;
+pub mod localization;
+pub mod mailing_lists;
+pub mod markdown;
+pub mod personal_data;
+pub mod personalization;
+pub mod rate_limit;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod subscriber_canonicalization;
+pub mod subscriber_import;
+pub mod suppressions;
 pub mod telemetry;
 pub mod tera;
+pub mod unsubscribe;
 pub mod utils;
@@ -0,0 +1,191 @@
+//! The languages subscribers are addressed in.
+//!
+//! Translations are Tera templates sitting next to the English ones, with the
+//! locale before the extension: `subscriptions/confirm.fr.j2` translates
+//! `subscriptions/confirm.j2`. A missing translation falls back to the next
+//! less specific locale, down to the English template.
+use crate::domain::Locale;
+use actix_web::http::header::{AcceptLanguage, Preference, Quality};
+use tera::Tera;
+use uuid::Uuid;
+
+/// The languages with translations, the default one first.
+pub const SUPPORTED_LOCALES: [&str; 2] = [Locale::DEFAULT, "fr"];
+
+/// The templates with translations.
+pub const LOCALIZED_TEMPLATES: [&str; 14] = [
+    "emails/confirmation.subject.j2",
+    "emails/confirmation.txt.j2",
+    "emails/confirmation.html.j2",
+    "emails/welcome.subject.j2",
+    "emails/welcome.txt.j2",
+    "emails/welcome.html.j2",
+    "emails/unsubscribed.subject.j2",
+    "emails/unsubscribed.txt.j2",
+    "emails/unsubscribed.html.j2",
+    "subscriptions/confirm.j2",
+    "subscriptions/confirm_error.j2",
+    "subscriptions/confirmed.j2",
+    "subscriptions/unsubscribe.j2",
+    "subscriptions/unsubscribed.j2",
+];
+
+/// The first of the reader's languages, by preference, that has translations.
+pub fn negotiate_locale(accept_language: &AcceptLanguage) -> Option<Locale> {
+    // A zero quality rules a language out.
+    let acceptable = AcceptLanguage(
+        accept_language
+            .0
+            .iter()
+            .filter(|item| item.quality > Quality::ZERO)
+            .cloned()
+            .collect(),
+    );
+    acceptable
+        .ranked()
+        .into_iter()
+        .filter_map(|preference| match preference {
+            Preference::Specific(tag) => Locale::parse(tag.as_str()).ok(),
+            Preference::Any => None,
+        })
+        .find(|locale| SUPPORTED_LOCALES.contains(&locale.language()))
+}
+
+/// The locale to address a reader in: theirs if they are a known subscriber,
+/// else the best match for their browser.
+pub fn page_locale(
+    subscriber_locale: Option<Locale>,
+    accept_language: Option<&AcceptLanguage>,
+) -> Locale {
+    subscriber_locale
+        .or_else(|| accept_language.and_then(negotiate_locale))
+        .unwrap_or_default()
+}
+
+/// Render the most specific translation of `name` available for `locale`.
+///
+/// The language actually used is exposed to the template as `lang`.
+pub fn render_localized(
+    tera: &Tera,
+    name: &str,
+    locale: &Locale,
+    context: &tera::Context,
+) -> Result<String, tera::Error> {
+    let (template, lang) = localized_template(tera, name, locale);
+    let mut context = context.clone();
+    context.insert("lang", lang);
+    tera.render(&template, &context)
+}
+
+fn localized_template<'a>(tera: &Tera, name: &str, locale: &'a Locale) -> (String, &'a str) {
+    let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    locale
+        .fallback_chain()
+        .filter(|lang| *lang != Locale::DEFAULT)
+        .map(|lang| (format!("{}.{}.{}", stem, lang, extension), lang))
+        .find(|(template, _)| tera.get_template_names().any(|t| t == template))
+        .unwrap_or_else(|| (name.to_owned(), Locale::DEFAULT))
+}
+
+/// `None` for unknown subscribers.
+#[tracing::instrument(skip(executor))]
+pub async fn get_subscriber_locale(
+    executor: impl sqlx::PgExecutor<'_>,
+    subscriber_id: Uuid,
+) -> Result<Option<Locale>, sqlx::Error> {
+    let record = sqlx::query!(
+        "SELECT locale FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    // Stored locales were valid when stored.
+    Ok(record.map(|r| Locale::parse(&r.locale).unwrap_or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{localized_template, negotiate_locale, LOCALIZED_TEMPLATES, SUPPORTED_LOCALES};
+    use crate::domain::Locale;
+    use actix_web::http::header::{AcceptLanguage, Header};
+    use actix_web::test::TestRequest;
+    use claims::{assert_none, assert_some_eq};
+    use tera::Tera;
+
+    fn accept_language(value: &str) -> AcceptLanguage {
+        let request = TestRequest::default()
+            .insert_header(("Accept-Language", value))
+            .to_http_request();
+        AcceptLanguage::parse(&request).unwrap()
+    }
+
+    fn tera() -> Tera {
+        let mut tera = Tera::default();
+        tera.add_raw_templates(vec![
+            ("page.j2", "Hello"),
+            ("page.fr.j2", "Bonjour"),
+            ("page.fr-CA.j2", "Allô"),
+        ])
+        .unwrap();
+        tera
+    }
+
+    fn locale(tag: &str) -> Locale {
+        Locale::parse(tag).unwrap()
+    }
+
+    #[test]
+    fn the_most_preferred_supported_language_is_picked() {
+        assert_some_eq!(
+            negotiate_locale(&accept_language("de-DE, fr-CA;q=0.8, en;q=0.5")),
+            locale("fr-CA")
+        );
+        assert_some_eq!(
+            negotiate_locale(&accept_language("fr;q=0, en-GB")),
+            locale("en-GB")
+        );
+    }
+
+    #[test]
+    fn unsupported_languages_are_not_picked() {
+        assert_none!(negotiate_locale(&accept_language("de, *;q=0.5")));
+    }
+
+    #[test]
+    fn the_most_specific_translation_is_used() {
+        let tera = tera();
+        assert_eq!(
+            localized_template(&tera, "page.j2", &locale("fr-CA")),
+            ("page.fr-CA.j2".into(), "fr-CA")
+        );
+        assert_eq!(
+            localized_template(&tera, "page.j2", &locale("fr-BE")),
+            ("page.fr.j2".into(), "fr")
+        );
+    }
+
+    #[test]
+    fn missing_translations_fall_back_to_the_default_locale() {
+        let tera = tera();
+        assert_eq!(
+            localized_template(&tera, "page.j2", &locale("de")),
+            ("page.j2".into(), "en")
+        );
+    }
+
+    #[test]
+    fn every_supported_locale_is_fully_translated() {
+        let tera = crate::tera::init_tera();
+        for lang in SUPPORTED_LOCALES {
+            for name in LOCALIZED_TEMPLATES {
+                assert_eq!(
+                    localized_template(&tera, name, &locale(lang)).1,
+                    lang,
+                    "{} has no {} translation",
+                    name,
+                    lang
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A topic readers can subscribe to, and issues can be published to.
+#[derive(serde::Serialize)]
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub slug: String,
+    pub name: String,
+    pub is_default: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ListSelectionError {
+    #[error("Unknown mailing list(s): {}.", .0.join(", "))]
+    UnknownLists(Vec<String>),
+    #[error("There is no default mailing list.")]
+    NoDefaultList,
+    #[error(transparent)]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+#[tracing::instrument(name = "Get mailing lists", skip(db_pool))]
+pub async fn get_mailing_lists(db_pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        "SELECT list_id, slug, name, is_default FROM lists ORDER BY name"
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+/// Turn the slugs picked in a form into list ids.
+///
+/// Picking nothing selects the default list, which keeps existing
+/// integrations working as if there was a single newsletter.
+#[tracing::instrument(name = "Resolve mailing lists", skip(db_pool))]
+pub async fn resolve_mailing_lists(
+    db_pool: &PgPool,
+    slugs: &[String],
+) -> Result<Vec<Uuid>, ListSelectionError> {
+    if slugs.is_empty() {
+        let default_list = sqlx::query!("SELECT list_id FROM lists WHERE is_default")
+            .fetch_optional(db_pool)
+            .await?
+            .ok_or(ListSelectionError::NoDefaultList)?;
+        return Ok(vec![default_list.list_id]);
+    }
+
+    let rows = sqlx::query!(
+        "SELECT list_id, slug FROM lists WHERE slug = ANY($1)",
+        slugs
+    )
+    .fetch_all(db_pool)
+    .await?;
+    let unknown: Vec<String> = slugs
+        .iter()
+        .filter(|slug| !rows.iter().any(|r| &r.slug == *slug))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(ListSelectionError::UnknownLists(unknown));
+    }
+    Ok(rows.into_iter().map(|r| r.list_id).collect())
+}
@@ -5,6 +5,8 @@ use zero2prod::{
     configuration::get_configuration,
     issue_delivery_worker::run_worker_until_stopped,
     startup::Application,
+    subscriber_canonicalization::{run_canonicalize_command, CanonicalizeCommand},
+    subscriber_import::{run_import_command, ImportCommand},
     telemetry::{get_subscriber, init_subscriber},
 };
 
@@ -15,6 +17,17 @@ async fn main() -> anyhow::Result<()> {
 
     let configuration = get_configuration().expect("Failed to read configuration.");
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("import-subscribers") {
+        let command = ImportCommand::parse(args.into_iter().skip(1)).map_err(anyhow::Error::msg)?;
+        return run_import_command(configuration, command).await;
+    }
+    if args.first().map(String::as_str) == Some("canonicalize-subscribers") {
+        let command =
+            CanonicalizeCommand::parse(args.into_iter().skip(1)).map_err(anyhow::Error::msg)?;
+        return run_canonicalize_command(configuration, command).await;
+    }
+
     let application = Application::build(configuration.clone()).await?;
     let application_task = tokio::spawn(application.run_until_stopped());
 
@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{html, Alignment, Event, HeadingLevel, Options, Parser, Tag};
+use textwrap::{WordSeparator, WordSplitter};
+
+/// The width plain-text emails are wrapped at.
+const TEXT_WIDTH: usize = 72;
+
+/// The bodies derived from the Markdown source of an issue.
+#[derive(Debug)]
+pub struct RenderedMarkdown {
+    pub html_content: String,
+    pub text_content: String,
+}
+
+/// CommonMark, with tables and footnotes. Merge tags are left as they are,
+/// to be rendered for each subscriber on delivery.
+///
+/// Only plain `{{ subscriber.<key> }}` tags are allowed: their values are
+/// escaped on delivery, while filters such as `safe` or statements could
+/// undo the sanitization of the HTML body.
+pub fn render_markdown(markdown: &str) -> Result<RenderedMarkdown, String> {
+    let (markdown, merge_tags) = set_merge_tags_aside(markdown)?;
+    let rendered = RenderedMarkdown {
+        html_content: restore_merge_tags(render_html(&markdown), &merge_tags),
+        text_content: restore_merge_tags(render_text(&markdown), &merge_tags),
+    };
+    // Backslash escapes and character references only turn into braces once
+    // rendered: the bodies are what gets personalized, so they are checked too.
+    set_merge_tags_aside(&rendered.html_content)?;
+    set_merge_tags_aside(&rendered.text_content)?;
+    Ok(rendered)
+}
+
+fn parser(markdown: &str) -> Parser<'_, '_> {
+    Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES)
+}
+
+/// Raw HTML is allowed in Markdown: whatever could run in a mail client, or
+/// leak the address of the reader, is stripped.
+fn render_html(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser(markdown));
+    let mut sanitizer = ammonia::Builder::default();
+    // Footnote references link to their definitions
+    sanitizer.add_tag_attributes("div", &["id"]);
+    sanitizer.clean(&unsafe_html).to_string()
+}
+
+/// Merge tags are swapped for placeholders that neither the Markdown parser
+/// nor the sanitizer touch: tags in link destinations would otherwise break
+/// the links.
+fn set_merge_tags_aside(markdown: &str) -> Result<(String, Vec<&str>), String> {
+    let mut merge_tags = Vec::new();
+    let mut output = String::with_capacity(markdown.len());
+    let mut rest = markdown;
+    while let Some(start) = rest.find('{') {
+        let tag = &rest[start..];
+        if !(tag.starts_with("{{") || tag.starts_with("{%") || tag.starts_with("{#")) {
+            push_text(&mut output, &rest[..=start]);
+            rest = &rest[start + 1..];
+            continue;
+        }
+        let merge_tag = tag
+            .find("}}")
+            .map(|end| &tag[..end + 2])
+            .filter(|merge_tag| is_merge_tag(merge_tag))
+            .ok_or_else(|| {
+                let excerpt: String = tag.chars().take(40).collect();
+                format!(
+                    "Only {{{{ subscriber.<key> }}}} merge tags can be used in Markdown, not {}",
+                    excerpt
+                )
+            })?;
+        push_text(&mut output, &rest[..start]);
+        output.push_str(&placeholder(merge_tags.len()));
+        merge_tags.push(merge_tag);
+        rest = &rest[start + merge_tag.len()..];
+    }
+    push_text(&mut output, rest);
+    Ok((output, merge_tags))
+}
+
+/// `{{ subscriber.<key> }}`, without filters.
+fn is_merge_tag(tag: &str) -> bool {
+    tag.strip_prefix("{{")
+        .and_then(|tag| tag.strip_suffix("}}"))
+        .and_then(|expression| expression.trim().strip_prefix("subscriber."))
+        .is_some_and(|key| {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+/// The placeholder delimiters are private-use characters, which an author
+/// has no business typing: they are dropped, percent-encoded in a link too,
+/// so as not to be mistaken for a placeholder.
+fn push_text(output: &mut String, text: &str) {
+    let text: String = text
+        .chars()
+        .filter(|c| *c != PLACEHOLDER_START && *c != PLACEHOLDER_END)
+        .collect();
+    output.push_str(
+        &text
+            .replace(&encode_placeholder(&PLACEHOLDER_START.to_string()), "")
+            .replace(&encode_placeholder(&PLACEHOLDER_END.to_string()), ""),
+    );
+}
+
+/// Placeholders in link destinations come out percent-encoded.
+fn restore_merge_tags(mut rendered: String, merge_tags: &[&str]) -> String {
+    for (i, merge_tag) in merge_tags.iter().enumerate() {
+        let placeholder = placeholder(i);
+        rendered = rendered
+            .replace(&placeholder, merge_tag)
+            .replace(&encode_placeholder(&placeholder), merge_tag);
+    }
+    rendered
+}
+
+fn encode_placeholder(placeholder: &str) -> String {
+    placeholder
+        .bytes()
+        .map(|b| match b {
+            b'0'..=b'9' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+
+fn placeholder(i: usize) -> String {
+    format!("{}{}{}", PLACEHOLDER_START, i, PLACEHOLDER_END)
+}
+
+fn render_text(markdown: &str) -> String {
+    let mut renderer = TextRenderer::default();
+    for event in parser(markdown) {
+        renderer.handle(event);
+    }
+    renderer.flush_inline(true);
+    renderer.output.trim_end().to_owned()
+}
+
+enum Container {
+    BlockQuote,
+    List {
+        next_number: Option<u64>,
+    },
+    /// A list item or a footnote definition: the marker is written on the
+    /// first line, the following lines are indented as much.
+    Item {
+        marker: String,
+        marker_written: bool,
+    },
+}
+
+#[derive(Default)]
+struct TextRenderer {
+    output: String,
+    /// The text of the paragraph, heading, list item or table cell being rendered.
+    inline: String,
+    containers: Vec<Container>,
+    /// Set when the next block must be separated by a blank line, even in a tight list.
+    separate_next_block: bool,
+    /// Where the text of each open link starts in `inline`, and where it points to.
+    links: Vec<(usize, String)>,
+    code_block: Option<String>,
+    table: Option<Table>,
+    footnote_numbers: HashMap<String, usize>,
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    current_row: Vec<String>,
+}
+
+impl TextRenderer {
+    fn handle(&mut self, event: Event<'_>) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match self.code_block.as_mut() {
+                Some(code) => code.push_str(&text),
+                None => self.inline.push_str(&text),
+            },
+            Event::Code(code) => self.inline.push_str(&code),
+            Event::FootnoteReference(label) => {
+                let number = self.footnote_number(&label);
+                self.inline.push_str(&format!("[{}]", number));
+            }
+            Event::SoftBreak => self.inline.push(' '),
+            Event::HardBreak => self.inline.push('\n'),
+            Event::Rule => {
+                self.flush_inline(true);
+                self.write_lines(vec!["-".repeat(TEXT_WIDTH)], false);
+            }
+            // There is no plain-text version of raw HTML
+            Event::Html(_) | Event::TaskListMarker(_) => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph | Tag::Heading(..) => self.flush_inline(true),
+            Tag::BlockQuote => {
+                self.flush_inline(true);
+                self.containers.push(Container::BlockQuote);
+            }
+            Tag::CodeBlock(_) => {
+                self.flush_inline(true);
+                self.code_block = Some(String::new());
+            }
+            Tag::List(first_number) => {
+                self.flush_inline(true);
+                // Top-level lists stand apart from the paragraphs around them
+                if !self.in_item() {
+                    self.separate_next_block = true;
+                }
+                self.containers.push(Container::List {
+                    next_number: first_number,
+                });
+            }
+            Tag::Item => {
+                let marker = match self.containers.last_mut() {
+                    Some(Container::List {
+                        next_number: Some(n),
+                    }) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".into(),
+                };
+                self.containers.push(Container::Item {
+                    marker,
+                    marker_written: false,
+                });
+            }
+            Tag::FootnoteDefinition(label) => {
+                self.flush_inline(true);
+                let number = self.footnote_number(&label);
+                self.separate_next_block = true;
+                self.containers.push(Container::Item {
+                    marker: format!("[{}]: ", number),
+                    marker_written: false,
+                });
+            }
+            Tag::Table(alignments) => {
+                self.flush_inline(true);
+                self.table = Some(Table {
+                    alignments,
+                    rows: Vec::new(),
+                    current_row: Vec::new(),
+                });
+            }
+            Tag::TableHead | Tag::TableRow | Tag::TableCell => {}
+            Tag::Emphasis => self.inline.push('_'),
+            Tag::Strong => self.inline.push('*'),
+            Tag::Strikethrough => {}
+            Tag::Link(_, destination, _) | Tag::Image(_, destination, _) => self
+                .links
+                .push((self.inline.len(), destination.to_string())),
+        }
+    }
+
+    fn end(&mut self, tag: Tag<'_>) {
+        match tag {
+            Tag::Paragraph => self.flush_inline(false),
+            Tag::Heading(level, ..) => {
+                let heading = std::mem::take(&mut self.inline).trim().to_owned();
+                let underline = match level {
+                    HeadingLevel::H1 => "=",
+                    HeadingLevel::H2 => "-",
+                    _ => "",
+                };
+                let underline = underline.repeat(heading.chars().count().min(TEXT_WIDTH));
+                let lines = std::iter::once(heading)
+                    .chain((!underline.is_empty()).then_some(underline))
+                    .collect();
+                self.write_lines(lines, false);
+            }
+            Tag::BlockQuote => {
+                self.flush_inline(true);
+                self.containers.pop();
+                self.separate_next_block = true;
+            }
+            Tag::List(_) => {
+                self.flush_inline(true);
+                self.containers.pop();
+                if !self.in_item() {
+                    self.separate_next_block = true;
+                }
+            }
+            Tag::Item | Tag::FootnoteDefinition(_) => {
+                self.flush_inline(true);
+                self.containers.pop();
+            }
+            Tag::CodeBlock(_) => {
+                let code = self.code_block.take().unwrap_or_default();
+                let lines = code
+                    .trim_end_matches('\n')
+                    .lines()
+                    .map(|line| format!("    {}", line))
+                    .collect();
+                self.write_lines(lines, false);
+            }
+            Tag::TableCell => {
+                let cell = std::mem::take(&mut self.inline).trim().to_owned();
+                if let Some(table) = self.table.as_mut() {
+                    table.current_row.push(cell);
+                }
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(table) = self.table.as_mut() {
+                    let row = std::mem::take(&mut table.current_row);
+                    table.rows.push(row);
+                }
+            }
+            Tag::Table(_) => {
+                if let Some(table) = self.table.take() {
+                    self.write_lines(table.render(), false);
+                }
+            }
+            Tag::Emphasis => self.inline.push('_'),
+            Tag::Strong => self.inline.push('*'),
+            Tag::Strikethrough => {}
+            Tag::Link(..) => {
+                if let Some((start, destination)) = self.links.pop() {
+                    let text = &self.inline[start..];
+                    // Autolinks and links to footnotes need no address
+                    let is_redundant = text == destination
+                        || destination.strip_prefix("mailto:") == Some(text)
+                        || destination.starts_with('#');
+                    if !is_redundant {
+                        self.inline.push_str(&format!(" ({})", destination));
+                    }
+                }
+            }
+            // The alternative text stands in for the image
+            Tag::Image(..) => {
+                self.links.pop();
+            }
+        }
+    }
+
+    fn in_item(&self) -> bool {
+        self.containers
+            .iter()
+            .any(|c| matches!(c, Container::Item { .. }))
+    }
+
+    fn footnote_number(&mut self, label: &str) -> usize {
+        let next_number = self.footnote_numbers.len() + 1;
+        *self
+            .footnote_numbers
+            .entry(label.to_owned())
+            .or_insert(next_number)
+    }
+
+    /// Wrap the pending text. Tight list items are not separated by blank lines.
+    fn flush_inline(&mut self, tight: bool) {
+        let text = std::mem::take(&mut self.inline);
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let (first_prefix, prefix) = self.prefixes();
+        let mut lines = Vec::new();
+        // Hard line breaks start a new line, but not a new list item
+        for (i, line) in text.split('\n').enumerate() {
+            let options = textwrap::Options::new(TEXT_WIDTH)
+                .initial_indent(if i == 0 { &first_prefix } else { &prefix })
+                .subsequent_indent(&prefix)
+                // Long addresses must stay whole, on a line of their own
+                .break_words(false)
+                .word_separator(WordSeparator::AsciiSpace)
+                .word_splitter(WordSplitter::NoHyphenation);
+            lines.extend(
+                textwrap::wrap(line.trim(), options)
+                    .into_iter()
+                    .map(|line| line.trim_end().to_owned()),
+            );
+        }
+        self.write(lines, tight);
+    }
+
+    /// Write lines that must not be wrapped, such as code or tables.
+    fn write_lines(&mut self, lines: Vec<String>, tight: bool) {
+        let (first_prefix, prefix) = self.prefixes();
+        let lines = lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| {
+                let prefix = if i == 0 { &first_prefix } else { &prefix };
+                format!("{}{}", prefix, line).trim_end().to_owned()
+            })
+            .collect();
+        self.write(lines, tight);
+    }
+
+    fn write(&mut self, lines: Vec<String>, tight: bool) {
+        if !self.output.is_empty() && (!tight || self.separate_next_block) {
+            self.output.push('\n');
+        }
+        self.separate_next_block = !tight;
+        for line in lines {
+            self.output.push_str(&line);
+            self.output.push('\n');
+        }
+    }
+
+    /// The prefixes of the first line of the next block, and of the lines after it.
+    fn prefixes(&mut self) -> (String, String) {
+        let mut first_prefix = String::new();
+        let mut prefix = String::new();
+        for container in &mut self.containers {
+            match container {
+                Container::BlockQuote => {
+                    first_prefix.push_str("> ");
+                    prefix.push_str("> ");
+                }
+                Container::List { .. } => {}
+                Container::Item {
+                    marker,
+                    marker_written,
+                } => {
+                    let indent = " ".repeat(marker.chars().count());
+                    if *marker_written {
+                        first_prefix.push_str(&indent);
+                    } else {
+                        first_prefix.push_str(marker);
+                        *marker_written = true;
+                    }
+                    prefix.push_str(&indent);
+                }
+            }
+        }
+        (first_prefix, prefix)
+    }
+}
+
+impl Table {
+    /// Columns are padded to line up, with a rule under the header.
+    fn render(&self) -> Vec<String> {
+        let n_columns = self.rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..n_columns)
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(i))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let mut lines: Vec<String> = self
+            .rows
+            .iter()
+            .map(|row| {
+                (0..n_columns)
+                    .map(|i| {
+                        let cell = row.get(i).map(String::as_str).unwrap_or("");
+                        let alignment = self.alignments.get(i).unwrap_or(&Alignment::None);
+                        pad(cell, widths[i], alignment)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            })
+            .collect();
+        if !lines.is_empty() {
+            let rule = widths
+                .iter()
+                .map(|width| "-".repeat(*width))
+                .collect::<Vec<_>>()
+                .join("-|-");
+            lines.insert(1, rule);
+        }
+        lines
+    }
+}
+
+fn pad(cell: &str, width: usize, alignment: &Alignment) -> String {
+    let padding = width - cell.chars().count();
+    let (left, right) = match alignment {
+        Alignment::Right => (padding, 0),
+        Alignment::Center => (padding / 2, padding - padding / 2),
+        Alignment::None | Alignment::Left => (0, padding),
+    };
+    format!("{}{}{}", " ".repeat(left), cell, " ".repeat(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_markdown, RenderedMarkdown};
+    use claims::assert_err;
+
+    fn render(markdown: &str) -> RenderedMarkdown {
+        render_markdown(markdown).unwrap()
+    }
+
+    #[test]
+    fn paragraphs_are_wrapped_in_the_text_version() {
+        let rendered = render(&format!(
+            "{}\n\nThe end.",
+            "All work and no play. ".repeat(6)
+        ));
+        let lines: Vec<&str> = rendered.text_content.lines().collect();
+        assert!(lines.iter().all(|line| line.chars().count() <= 72));
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[2], "");
+        assert_eq!(lines[3], "The end.");
+        assert!(rendered
+            .html_content
+            .starts_with("<p>All work and no play."));
+    }
+
+    #[test]
+    fn long_addresses_are_not_broken() {
+        let url = format!("https://example.com/{}", "a-b".repeat(40));
+        let rendered = render(&format!("Read [the post]({}) now.", url));
+        assert!(rendered
+            .text_content
+            .lines()
+            .any(|line| line == format!("({})", url)));
+    }
+
+    #[test]
+    fn lists_quotes_and_headings_are_laid_out() {
+        let rendered = render(
+            "# Monday issue\n\nThis week:\n\n- Rust\n- Postgres\n  1. Indexes\n  2. Locks\n\n> Quoted\n> text",
+        );
+        assert_eq!(
+            rendered.text_content,
+            "Monday issue\n============\n\nThis week:\n\n- Rust\n- Postgres\n  1. Indexes\n  2. Locks\n\n> Quoted text"
+        );
+    }
+
+    #[test]
+    fn tables_are_rendered() {
+        let rendered = render("| Crate | Stars |\n|-------|------:|\n| sqlx | 9 |\n| tera | 30 |");
+        assert_eq!(
+            rendered.text_content,
+            "Crate | Stars\n------|------\nsqlx  |     9\ntera  |    30"
+        );
+        assert!(rendered.html_content.contains("<table>"));
+        assert!(rendered.html_content.contains("<td>sqlx</td>"));
+    }
+
+    #[test]
+    fn footnotes_are_numbered() {
+        let rendered = render("Fearless concurrency[^fc].\n\n[^fc]: Mostly.");
+        assert_eq!(
+            rendered.text_content,
+            "Fearless concurrency[1].\n\n[1]: Mostly."
+        );
+        assert!(rendered.html_content.contains(r##"<a href="#fc""##));
+        assert!(rendered.html_content.contains(r#"<div id="fc">"#));
+    }
+
+    #[test]
+    fn unsafe_html_is_stripped() {
+        let rendered = render(
+            "Hi <script>alert(1)</script><img src=x onerror=\"alert(2)\">\n\n[x](javascript:alert(3))",
+        );
+        assert!(!rendered.html_content.contains("script"));
+        assert!(!rendered.html_content.contains("onerror"));
+        assert!(!rendered.html_content.contains("javascript"));
+    }
+
+    #[test]
+    fn merge_tags_are_left_untouched() {
+        let rendered = render(
+            "Hi {{subscriber.first_name}}, [your profile](https://example.com/?e={{ subscriber.email }})",
+        );
+        assert_eq!(
+            rendered.html_content,
+            "<p>Hi {{subscriber.first_name}}, <a href=\"https://example.com/?e={{ subscriber.email }}\" rel=\"noopener noreferrer\">your profile</a></p>\n"
+        );
+        assert_eq!(
+            rendered.text_content,
+            "Hi {{subscriber.first_name}}, your profile (https://example.com/?e={{ subscriber.email }})"
+        );
+    }
+
+    #[test]
+    fn only_plain_merge_tags_are_allowed() {
+        assert_err!(render_markdown(
+            r#"{{ "<script>alert(1)</script>" | safe }}"#
+        ));
+        assert_err!(render_markdown("{{ subscriber.company | safe }}"));
+        assert_err!(render_markdown("{% if subscriber.name %}Hi{% endif %}"));
+        assert_err!(render_markdown("{# note #}"));
+        assert_err!(render_markdown("Hi {{ subscriber.name"));
+    }
+
+    #[test]
+    fn escaped_braces_cannot_smuggle_tera_expressions() {
+        assert_err!(render_markdown(r"\{\{ subscriber.company | safe \}\}"));
+        assert_err!(render_markdown(
+            r#"&#123;&#123; get_env(name="APP_DATABASE__PASSWORD") &#125;&#125;"#
+        ));
+        assert_err!(render_markdown(
+            "&lbrace;% if true %&rbrace;Hi&lbrace;% endif %&rbrace;"
+        ));
+        assert_err!(render_markdown(
+            r"[x](https://example.com/?a=\{\{ subscriber.name | safe \}\})"
+        ));
+    }
+
+    #[test]
+    fn text_cannot_pass_for_a_merge_tag() {
+        let rendered = render(
+            "\u{E000}0\u{E001} MERGETAG0END [link](https://example.com/?a=%EE%80%800%EE%80%81) {{ subscriber.name }}",
+        );
+        assert_eq!(
+            rendered.html_content,
+            "<p>0 MERGETAG0END <a href=\"https://example.com/?a=0\" rel=\"noopener noreferrer\">link</a> {{ subscriber.name }}</p>\n"
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! Data-subject requests: everything held about an email address, and its erasure.
+use crate::domain::{CanonicalEmailRules, SubscriberEmail};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The tombstone of an erased address: enough to recognise it, not to recover it.
+///
+/// The canonical form is hashed, so that any spelling of the address matches.
+/// It depends on the provider rules: hashes cannot be recomputed, so the
+/// tombstones recorded before the rules changed only match the spellings the
+/// new rules canonicalize the same way.
+pub fn email_hash(email: &str, rules: &CanonicalEmailRules) -> String {
+    let identity = canonical_email(email, rules).unwrap_or_else(|| email.trim().to_lowercase());
+    hex::encode(Sha256::digest(identity.as_bytes()))
+}
+
+/// Everything held about an email address, as handed over to its owner.
+#[derive(serde::Serialize, Debug)]
+pub struct PersonalData {
+    pub email: String,
+    pub subscriber: Option<SubscriberRecord>,
+    pub status_history: Vec<StatusChange>,
+    pub lists: Vec<Membership>,
+    pub subscription_tokens: Vec<SubscriptionToken>,
+    pub delivered_issues: Vec<DeliveredIssue>,
+    pub queued_issues: Vec<QueuedIssue>,
+    pub queued_emails: Vec<QueuedEmail>,
+    /// What the email provider reported about the emails sent to the address.
+    pub delivery_events: Vec<DeliveryEventRecord>,
+    pub suppressions: Vec<Suppression>,
+    /// The imports whose error report mentions the address.
+    pub subscriber_imports: Vec<Uuid>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub attributes: serde_json::Value,
+    pub consent_source: Option<String>,
+    pub consented_at: Option<DateTime<Utc>>,
+    pub locale: String,
+    pub signup_source: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+    pub referrer: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct StatusChange {
+    pub status: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Membership {
+    pub list: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The token itself is left out: it is a credential, not information about the subscriber.
+#[derive(serde::Serialize, Debug)]
+pub struct SubscriptionToken {
+    pub created_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct DeliveredIssue {
+    pub title: String,
+    pub delivered_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct QueuedIssue {
+    pub title: String,
+    pub execute_after: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct QueuedEmail {
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct DeliveryEventRecord {
+    pub event_type: String,
+    pub bounce_type: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Suppression {
+    pub reason: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The canonical form of the address, if it is a valid one.
+fn canonical_email(email: &str, rules: &CanonicalEmailRules) -> Option<String> {
+    SubscriberEmail::parse(email.trim().to_owned())
+        .ok()
+        .map(|email| email.canonical(rules))
+}
+
+/// The spelling given and those the address is stored under, then their
+/// lower-cased forms, by which the tables keyed by address are matched.
+fn spellings_of(
+    email: &str,
+    stored_spellings: impl IntoIterator<Item = String>,
+) -> (Vec<String>, Vec<String>) {
+    let mut spellings = vec![email.trim().to_owned()];
+    for spelling in stored_spellings {
+        if !spellings.contains(&spelling) {
+            spellings.push(spelling);
+        }
+    }
+    let mut addresses: Vec<String> = spellings.iter().map(|s| s.to_lowercase()).collect();
+    addresses.sort();
+    addresses.dedup();
+    (spellings, addresses)
+}
+
+/// Addresses are matched regardless of case, as mailboxes are in practice.
+/// The subscriber is also found under any spelling of their address, and so
+/// is everything an erasure would remove.
+#[tracing::instrument(name = "Collect personal data", skip(db_pool, rules))]
+pub async fn collect_personal_data(
+    db_pool: &PgPool,
+    email: &str,
+    rules: &CanonicalEmailRules,
+) -> Result<PersonalData, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+        SELECT
+            id, email, name, status, subscribed_at, attributes, consent_source, consented_at,
+            locale, signup_source, utm_source, utm_medium, utm_campaign, utm_term, utm_content,
+            referrer
+        FROM subscriptions
+        WHERE lower(email) = lower($1) OR canonical_email = $2
+        "#,
+        email,
+        canonical_email(email, rules),
+    )
+    .fetch_optional(db_pool)
+    .await
+    .context("Failed to fetch the subscriber")?;
+    // No subscriber matches the nil id.
+    let subscriber_id = subscriber.as_ref().map(|s| s.id).unwrap_or_default();
+    let (_, addresses) = spellings_of(email, subscriber.iter().map(|s| s.email.clone()));
+
+    let status_history = sqlx::query_as!(
+        StatusChange,
+        r#"
+        SELECT status, changed_at
+        FROM subscription_status_changes
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the status history")?;
+    let lists = sqlx::query_as!(
+        Membership,
+        r#"
+        SELECT l.slug AS list, m.status, m.created_at
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        WHERE m.subscriber_id = $1
+        ORDER BY l.slug
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the list memberships")?;
+    let subscription_tokens = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT created_at, consumed_at
+        FROM subscription_tokens
+        WHERE subscriber_id = $1
+        ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the subscription tokens")?;
+    let delivered_issues = sqlx::query_as!(
+        DeliveredIssue,
+        r#"
+        SELECT i.title, d.delivered_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_id = $1
+        ORDER BY d.delivered_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the delivered issues")?;
+    let queued_issues = sqlx::query_as!(
+        QueuedIssue,
+        r#"
+        SELECT i.title, q.execute_after
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE lower(q.subscriber_email) = ANY($1)
+        ORDER BY q.execute_after
+        "#,
+        &addresses[..]
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the queued issues")?;
+    let queued_emails = sqlx::query_as!(
+        QueuedEmail,
+        r#"
+        SELECT subject, created_at
+        FROM email_outbox
+        WHERE lower(recipient) = ANY($1)
+        ORDER BY created_at
+        "#,
+        &addresses[..]
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the queued emails")?;
+    let delivery_events = sqlx::query_as!(
+        DeliveryEventRecord,
+        r#"
+        SELECT event_type, bounce_type, occurred_at
+        FROM delivery_events
+        WHERE lower(recipient) = ANY($1)
+        ORDER BY occurred_at
+        "#,
+        &addresses[..]
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the delivery events")?;
+    let suppressions = sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT reason, source, created_at
+        FROM suppressions
+        WHERE lower(email) = ANY($1) OR canonical_email = $2
+        "#,
+        &addresses[..],
+        canonical_email(email, rules),
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the suppressions")?;
+    let subscriber_imports = sqlx::query!(
+        r#"
+        SELECT import_id
+        FROM subscriber_imports
+        WHERE EXISTS (
+            SELECT 1 FROM UNNEST($1::text[]) AS a(address)
+            WHERE strpos(lower(error_report), a.address) > 0
+        )
+        ORDER BY created_at
+        "#,
+        &addresses[..]
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch the subscriber imports")?
+    .into_iter()
+    .map(|r| r.import_id)
+    .collect();
+
+    Ok(PersonalData {
+        email: email.to_owned(),
+        subscriber,
+        status_history,
+        lists,
+        subscription_tokens,
+        delivered_issues,
+        queued_issues,
+        queued_emails,
+        delivery_events,
+        suppressions,
+        subscriber_imports,
+    })
+}
+
+/// What an erasure removed.
+#[derive(serde::Serialize, Debug, Default)]
+pub struct Erasure {
+    pub subscriber_deleted: bool,
+    pub n_queued_issues: u64,
+    pub n_queued_emails: u64,
+    pub n_delivery_events: u64,
+    pub n_suppressions: u64,
+    pub n_idempotency_records: u64,
+    pub n_import_reports: u64,
+}
+
+/// Remove every trace of an email address, and record its tombstone.
+///
+/// The status history and the delivery log of the subscriber go with it.
+/// The tombstone is recorded even when nothing was held, so that the address
+/// is not imported later on. Suppressions go too: signing up again is a fresh
+/// consent.
+#[tracing::instrument(name = "Erase personal data", skip(db_pool, rules))]
+pub async fn erase_personal_data(
+    db_pool: &PgPool,
+    email: &str,
+    rules: &CanonicalEmailRules,
+) -> Result<Erasure, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let mut erasure = Erasure::default();
+
+    // The address may be stored under another spelling than the one given.
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT id, email FROM subscriptions
+        WHERE lower(email) = lower($1) OR canonical_email = $2
+        FOR UPDATE
+        "#,
+        email,
+        canonical_email(email, rules),
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch the subscribers")?;
+    let (spellings, addresses) = spellings_of(email, subscribers.iter().map(|s| s.email.clone()));
+
+    erasure.n_queued_issues = sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE lower(subscriber_email) = ANY($1)",
+        &addresses[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the queued issues")?
+    .rows_affected();
+    erasure.n_queued_emails = sqlx::query!(
+        "DELETE FROM email_outbox WHERE lower(recipient) = ANY($1)",
+        &addresses[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the queued emails")?
+    .rows_affected();
+    for subscriber in &subscribers {
+        delete_subscriber(&mut transaction, subscriber.id)
+            .await
+            .context("Failed to delete the subscriber")?;
+        erasure.subscriber_deleted = true;
+    }
+    erasure.n_delivery_events = sqlx::query!(
+        "DELETE FROM delivery_events WHERE lower(recipient) = ANY($1)",
+        &addresses[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the delivery events")?
+    .rows_affected();
+    erasure.n_suppressions = sqlx::query!(
+        "DELETE FROM suppressions WHERE lower(email) = ANY($1) OR canonical_email = $2",
+        &addresses[..],
+        canonical_email(email, rules),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the suppressions")?
+    .rows_affected();
+    for spelling in &spellings {
+        // Saved responses are opaque bytes, which may not even be text.
+        erasure.n_idempotency_records += sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE position(convert_to($1, 'UTF8') IN response_body) > 0
+                OR position(convert_to(lower($1), 'UTF8') IN response_body) > 0
+            "#,
+            spelling
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the saved responses mentioning the address")?
+        .rows_affected();
+    }
+    for address in &addresses {
+        erasure.n_import_reports += scrub_import_reports(&mut transaction, address).await?;
+        sqlx::query!(
+            "INSERT INTO erased_subscribers (email_hash) VALUES ($1) ON CONFLICT DO NOTHING",
+            email_hash(address, rules)
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to record the tombstone of the address")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to erase personal data")?;
+    Ok(erasure)
+}
+
+/// Delete the subscriber and everything attached to them.
+#[tracing::instrument(skip(transaction))]
+pub async fn delete_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    // Emails are keyed by address in the queues.
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE subscriber_email IN (SELECT email FROM subscriptions WHERE id = $1)
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM email_outbox
+        WHERE recipient IN (SELECT email FROM subscriptions WHERE id = $1)
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM list_memberships WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    // The status history and the deliveries go with it.
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", subscriber_id)
+        .execute(&mut *transaction)
+        .await?;
+    Ok(())
+}
+
+/// Drop the rows of the address from the error reports of past imports.
+async fn scrub_import_reports(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<u64, anyhow::Error> {
+    let reports = sqlx::query!(
+        r#"
+        SELECT import_id, error_report
+        FROM subscriber_imports
+        WHERE strpos(lower(error_report), lower($1)) > 0
+        FOR UPDATE
+        "#,
+        email
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the import reports mentioning the address")?;
+    let mut n_scrubbed = 0;
+    for report in reports {
+        let (error_report, n_removed) = remove_rows_of(&report.error_report, email)?;
+        if n_removed == 0 {
+            continue;
+        }
+        sqlx::query!(
+            r#"
+            UPDATE subscriber_imports
+            SET error_report = $2, n_rejected = n_rejected - $3
+            WHERE import_id = $1
+            "#,
+            report.import_id,
+            error_report,
+            i32::try_from(n_removed)?,
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to scrub an import report")?;
+        n_scrubbed += 1;
+    }
+    Ok(n_scrubbed)
+}
+
+/// The `row,email,error` report without the rows of `email`, and how many were removed.
+fn remove_rows_of(error_report: &str, email: &str) -> Result<(String, usize), anyhow::Error> {
+    let mut reader = csv::Reader::from_reader(error_report.as_bytes());
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(reader.headers()?)?;
+    let mut n_removed = 0;
+    for record in reader.records() {
+        let record = record?;
+        if record
+            .get(1)
+            .is_some_and(|e| e.trim().eq_ignore_ascii_case(email.trim()))
+        {
+            n_removed += 1;
+        } else {
+            writer.write_record(&record)?;
+        }
+    }
+    Ok((String::from_utf8(writer.into_inner()?)?, n_removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{email_hash, remove_rows_of};
+    use crate::domain::{CanonicalEmailRules, ProviderRule};
+
+    fn hash(email: &str) -> String {
+        email_hash(email, &CanonicalEmailRules::new(vec![ProviderRule::Gmail]))
+    }
+
+    #[test]
+    fn the_hash_is_that_of_the_canonical_address() {
+        assert_eq!(
+            hash("Ursula.LeGuin@gmail.com"),
+            hash("ursulaleguin+news@googlemail.com")
+        );
+        assert_eq!(hash("ursula@Bücher.de"), hash("ursula@xn--bcher-kva.de"));
+        // Invalid addresses are still hashed
+        assert_eq!(hash("Not an address"), hash("not an address"));
+    }
+
+    #[test]
+    fn the_hash_does_not_depend_on_case_or_surrounding_spaces() {
+        assert_eq!(hash("ursula@gmail.com"), hash(" Ursula@GMAIL.com "));
+        assert_ne!(hash("ursula@gmail.com"), hash("octavia@gmail.com"));
+    }
+
+    #[test]
+    fn only_the_rows_of_the_address_are_removed_from_a_report() {
+        let report = "row,email,error\n\
+            2,Ursula@gmail.com,Duplicate of row 1.\n\
+            3,octavia@example.com,\"Not, valid.\"\n\
+            4,ursula@gmail.com.evil.com,Duplicate of row 1.\n";
+        let (scrubbed, n_removed) = remove_rows_of(report, "ursula@gmail.com").unwrap();
+        assert_eq!(n_removed, 1);
+        assert_eq!(
+            scrubbed,
+            "row,email,error\n\
+            3,octavia@example.com,\"Not, valid.\"\n\
+            4,ursula@gmail.com.evil.com,Duplicate of row 1.\n"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use serde_json::{Map, Value};
+use tera::{Context, Tera};
+
+/// The recipient details exposed to merge tags as `{{ subscriber.<key> }}`.
+pub struct MergeFields<'a> {
+    pub email: &'a str,
+    pub name: &'a str,
+    pub attributes: &'a Map<String, Value>,
+}
+
+/// Check that the merge tags in an issue body can be rendered, so that a typo
+/// is caught when publishing rather than on delivery.
+pub fn validate_merge_tags(content: &str) -> Result<(), tera::Error> {
+    Tera::default().add_raw_template("issue", content)
+}
+
+/// Render the merge tags of an issue body for a single recipient.
+///
+/// A tag referring to an attribute the recipient does not have renders as an
+/// empty string, unless it provides its own fallback
+/// (`{{ subscriber.city | default(value="your city") }}`). If the content still
+/// cannot be rendered, it is sent as-is rather than not at all.
+pub fn personalize(content: &str, fields: &MergeFields<'_>, autoescape: bool) -> String {
+    let mut subscriber = fields.attributes.clone();
+    subscriber.insert("email".into(), fields.email.into());
+    subscriber.insert("name".into(), fields.name.into());
+
+    let render = |subscriber: &Map<String, Value>| {
+        let mut context = Context::new();
+        context.insert("subscriber", subscriber);
+        Tera::one_off(content, &context, autoescape)
+    };
+
+    // Fill in the attributes Tera reports as missing one at a time, rather than
+    // upfront: `default` filters only apply to values that are not in the context.
+    let mut rendered = render(&subscriber);
+    for _ in 0..MAX_MISSING_ATTRIBUTES {
+        match rendered.as_ref().err().and_then(missing_attribute) {
+            Some(key) if !subscriber.contains_key(&key) => {
+                subscriber.insert(key, "".into());
+                rendered = render(&subscriber);
+            }
+            _ => break,
+        }
+    }
+    rendered.unwrap_or_else(|e| {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to render merge tags, sending the content as-is",
+        );
+        content.to_owned()
+    })
+}
+
+/// The bodies of an issue as one recipient receives them.
+pub struct PersonalizedIssue {
+    pub html_content: String,
+    pub text_content: String,
+}
+
+/// What the delivery workers send, and what editors preview.
+pub fn personalize_issue(
+    html_content: &str,
+    text_content: &str,
+    fields: &MergeFields<'_>,
+) -> PersonalizedIssue {
+    PersonalizedIssue {
+        html_content: personalize(html_content, fields, true),
+        text_content: personalize(text_content, fields, false),
+    }
+}
+
+const MAX_MISSING_ATTRIBUTES: usize = 20;
+
+/// The `<key>` of the `subscriber.<key>` variable a rendering failed on, if any.
+fn missing_attribute(e: &tera::Error) -> Option<String> {
+    let mut current: Option<&dyn std::error::Error> = Some(e);
+    while let Some(e) = current {
+        let message = e.to_string();
+        if let Some(key) = message
+            .strip_prefix("Variable `subscriber.")
+            .and_then(|rest| rest.split_once('`'))
+            .filter(|(_, rest)| rest.starts_with(" not found in context"))
+            .map(|(key, _)| key)
+        {
+            return Some(key.to_owned());
+        }
+        current = e.source();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{personalize, validate_merge_tags, MergeFields};
+    use claims::{assert_err, assert_ok};
+    use serde_json::{json, Map, Value};
+
+    fn attributes(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn fields(attributes: &Map<String, Value>) -> MergeFields<'_> {
+        MergeFields {
+            email: "ursula@example.com",
+            name: "Ursula",
+            attributes,
+        }
+    }
+
+    #[test]
+    fn name_email_and_attributes_are_rendered() {
+        let attributes = attributes(json!({"company": "Acme"}));
+        let rendered = personalize(
+            "Hi {{ subscriber.name }} ({{ subscriber.email }}) from {{ subscriber.company }}",
+            &fields(&attributes),
+            false,
+        );
+        assert_eq!(rendered, "Hi Ursula (ursula@example.com) from Acme");
+    }
+
+    #[test]
+    fn a_missing_attribute_renders_as_an_empty_string() {
+        let attributes = Map::new();
+        let rendered = personalize(
+            "Greetings from {{ subscriber.city }}!",
+            &fields(&attributes),
+            false,
+        );
+        assert_eq!(rendered, "Greetings from !");
+    }
+
+    #[test]
+    fn the_default_filter_handles_missing_attributes() {
+        let attributes = Map::new();
+        let rendered = personalize(
+            "Hi {{ subscriber.nickname | default(value=\"friend\") }}, {{ subscriber.city }}",
+            &fields(&attributes),
+            false,
+        );
+        assert_eq!(rendered, "Hi friend, ");
+    }
+
+    #[test]
+    fn attribute_values_are_escaped_in_html() {
+        let attributes = attributes(json!({"company": "<script>alert(1)</script>"}));
+        let rendered = personalize(
+            "<p>{{ subscriber.company }}</p>",
+            &fields(&attributes),
+            true,
+        );
+        assert!(!rendered.contains("<script>"));
+    }
+
+    #[test]
+    fn content_that_cannot_be_rendered_is_sent_as_is() {
+        let attributes = Map::new();
+        let content = "{{ subscriber.name | no_such_filter }}";
+        assert_eq!(personalize(content, &fields(&attributes), false), content);
+    }
+
+    #[test]
+    fn syntax_errors_are_detected() {
+        assert_ok!(validate_merge_tags("Hi {{ subscriber.name }}"));
+        assert_err!(validate_merge_tags("Hi {{ subscriber.name "));
+    }
+}
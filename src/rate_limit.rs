@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{ContentType, RETRY_AFTER},
+    web, HttpMessage, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    configuration::{RateLimit, RateLimitBackend, RateLimitSettings},
+    domain::{CanonicalEmailRules, SubscriberEmail},
+    utils::e500,
+};
+
+/// Fixed-window counters on the requests sent by a client IP, and on the
+/// requests targeting an email address.
+pub struct RateLimiter {
+    store: RateLimitStore,
+    per_ip: RateLimit,
+    per_email: RateLimit,
+    trust_proxy_headers: bool,
+}
+
+enum RateLimitStore {
+    /// Shared by every instance of the application.
+    Redis(ConnectionManager),
+    InMemory(Mutex<InMemoryWindows>),
+}
+
+/// Many keys are never hit again, a form token being used only once: expired
+/// windows are swept away whenever the map has doubled since the last sweep.
+#[derive(Default)]
+struct InMemoryWindows {
+    windows: HashMap<String, Window>,
+    purge_at_len: usize,
+}
+
+struct Window {
+    n_requests: u64,
+    ends_at: Instant,
+}
+
+impl InMemoryWindows {
+    const MIN_PURGE_AT_LEN: usize = 1024;
+
+    fn hit(&mut self, key: &str, limit: RateLimit, now: Instant) -> (u64, Duration) {
+        if self.windows.len() >= self.purge_at_len {
+            self.windows.retain(|_, w| w.ends_at > now);
+            self.purge_at_len = (2 * self.windows.len()).max(Self::MIN_PURGE_AT_LEN);
+        }
+        let window = self
+            .windows
+            .entry(key.to_owned())
+            .and_modify(|w| {
+                if w.ends_at <= now {
+                    w.n_requests = 0;
+                    w.ends_at = now + limit.window();
+                }
+            })
+            .or_insert_with(|| Window {
+                n_requests: 0,
+                ends_at: now + limit.window(),
+            });
+        window.n_requests += 1;
+        (window.n_requests, window.ends_at - now)
+    }
+}
+
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+impl RateLimiter {
+    pub async fn build(
+        settings: &RateLimitSettings,
+        redis_uri: &Secret<String>,
+    ) -> Result<Self, anyhow::Error> {
+        let store = match settings.backend {
+            RateLimitBackend::Redis => {
+                let client = redis::Client::open(redis_uri.expose_secret().as_str())?;
+                RateLimitStore::Redis(ConnectionManager::new(client).await?)
+            }
+            RateLimitBackend::InMemory => RateLimitStore::InMemory(Mutex::default()),
+        };
+        Ok(Self {
+            store,
+            per_ip: settings.per_ip,
+            per_email: settings.per_email,
+            trust_proxy_headers: settings.trust_proxy_headers,
+        })
+    }
+
+    /// Count a request against `key`.
+    #[tracing::instrument(name = "Hit rate limit", skip(self))]
+    pub async fn hit(
+        &self,
+        key: &str,
+        limit: RateLimit,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        let (n_requests, remaining) = match &self.store {
+            RateLimitStore::Redis(connection) => {
+                // The window starts with the first request and is never extended.
+                let (n_requests, remaining_ms): (u64, i64) = redis::pipe()
+                    .atomic()
+                    .cmd("SET")
+                    .arg(key)
+                    .arg(0)
+                    .arg("PX")
+                    .arg(limit.window().as_millis() as u64)
+                    .arg("NX")
+                    .ignore()
+                    .cmd("INCR")
+                    .arg(key)
+                    .cmd("PTTL")
+                    .arg(key)
+                    .query_async(&mut connection.clone())
+                    .await
+                    .context("Failed to update a rate limit counter in Redis")?;
+                (
+                    n_requests,
+                    Duration::from_millis(remaining_ms.max(0) as u64),
+                )
+            }
+            RateLimitStore::InMemory(windows) => {
+                windows.lock().unwrap().hit(key, limit, Instant::now())
+            }
+        };
+
+        if n_requests > limit.max_requests {
+            Ok(RateLimitDecision::Limited {
+                retry_after: remaining,
+            })
+        } else {
+            Ok(RateLimitDecision::Allowed)
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TargetEmail {
+    email: Option<String>,
+}
+
+/// Throttle endpoints that send emails, so that they cannot be used to flood
+/// an inbox or to burn through our sending reputation.
+///
+/// The limiter fails open: if the counters cannot be updated the request goes
+/// through, as the subscription flow matters more than the protection.
+pub async fn rate_limit_subscriptions(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let rate_limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .cloned()
+        .ok_or_else(|| e500("The rate limiter is missing from the application data"))?;
+    let rules = req
+        .app_data::<web::Data<CanonicalEmailRules>>()
+        .cloned()
+        .ok_or_else(|| e500("The canonical email rules are missing from the application data"))?;
+
+    let client_ip = if rate_limiter.trust_proxy_headers {
+        req.connection_info()
+            .realip_remote_addr()
+            .map(ToOwned::to_owned)
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    };
+
+    // The handler still needs the body: read it, then put it back.
+    let body = req.extract::<web::Bytes>().await?;
+    let target_email = match req.content_type() {
+        "application/json" => serde_json::from_slice::<TargetEmail>(&body).ok(),
+        _ => serde_urlencoded::from_bytes::<TargetEmail>(&body).ok(),
+    }
+    .and_then(|t| t.email)
+    // Spelling an address differently does not buy more attempts.
+    .map(
+        |email| match SubscriberEmail::parse(email.trim().to_owned()) {
+            Ok(email) => email.canonical(&rules),
+            Err(_) => email.trim().to_lowercase(),
+        },
+    );
+    req.set_payload(bytes_to_payload(body));
+
+    let mut checks = Vec::with_capacity(2);
+    if let Some(client_ip) = client_ip {
+        checks.push((format!("rate_limit:ip:{}", client_ip), rate_limiter.per_ip));
+    }
+    if let Some(target_email) = target_email {
+        checks.push((
+            format!("rate_limit:email:{}", target_email),
+            rate_limiter.per_email,
+        ));
+    }
+    for (key, limit) in checks {
+        match rate_limiter.hit(&key, limit).await {
+            Ok(RateLimitDecision::Allowed) => {}
+            Ok(RateLimitDecision::Limited { retry_after }) => {
+                return Err(too_many_requests(retry_after));
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to check a rate limit. Letting the request through.",
+                );
+            }
+        }
+    }
+
+    next.call(req).await
+}
+
+fn too_many_requests(retry_after: Duration) -> actix_web::Error {
+    // Round up: retrying a bit too late beats retrying a bit too early.
+    let retry_after_seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after_seconds.to_string()))
+        .content_type(ContentType::plaintext())
+        .body("Too many requests. Please try again later.");
+    InternalError::from_response(anyhow::anyhow!("Rate limit exceeded"), response).into()
+}
+
+fn bytes_to_payload(body: web::Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    payload.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InMemoryWindows;
+    use crate::configuration::RateLimit;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn expired_windows_do_not_pile_up_in_memory() {
+        let mut windows = InMemoryWindows::default();
+        let once = RateLimit {
+            max_requests: 1,
+            window_seconds: 60,
+        };
+        let start = Instant::now();
+
+        for i in 0..10_000 {
+            // A new key every second, each kept for a minute.
+            let now = start + Duration::from_secs(i);
+            windows.hit(&format!("form_token:{}", i), once, now);
+        }
+
+        assert!(windows.windows.len() <= 2 * InMemoryWindows::MIN_PURGE_AT_LEN);
+    }
+
+    #[test]
+    fn live_windows_survive_a_purge() {
+        let mut windows = InMemoryWindows::default();
+        let once = RateLimit {
+            max_requests: 1,
+            window_seconds: 60,
+        };
+        let now = Instant::now();
+        windows.hit("form_token:kept", once, now);
+
+        for i in 0..InMemoryWindows::MIN_PURGE_AT_LEN {
+            windows.hit(&format!("form_token:{}", i), once, now);
+        }
+
+        assert_eq!(windows.hit("form_token:kept", once, now).0, 2);
+    }
+}
@@ -9,6 +9,15 @@ use crate::{authentication::UserId, utils::e500};
 #[derive(serde::Serialize)]
 struct DashboardContext {
     username: String,
+    signups_by_source: Vec<SignupCount>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SignupCount {
+    /// `None` for signups with no attribution at all.
+    pub source: Option<String>,
+    pub n_signups: i64,
+    pub n_confirmed: i64,
 }
 
 #[tracing::instrument(name = "Get admin dashboard", skip(db_pool, user_id, tera))]
@@ -19,11 +28,15 @@ pub async fn admin_dashboard(
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let username = get_username(*user_id, &db_pool).await.map_err(e500)?;
+    let signups_by_source = get_signups_by_source(&db_pool).await.map_err(e500)?;
 
     let body = {
-        let context = tera::Context::from_serialize(DashboardContext { username })
-            .context("Failed to serialize context")
-            .map_err(e500)?;
+        let context = tera::Context::from_serialize(DashboardContext {
+            username,
+            signups_by_source,
+        })
+        .context("Failed to serialize context")
+        .map_err(e500)?;
 
         tera.render("admin/dashboard.j2", &context)
             .context("Failed to render admin dashboard")
@@ -43,3 +56,28 @@ pub async fn get_username(user_id: Uuid, db_pool: &PgPool) -> Result<String, any
         .context("Failed to perform a query to retrieve a username.")?;
     Ok(row.username)
 }
+
+/// A signup is credited to the `source` the form reported, else to the
+/// campaign's `utm_source`, else to the host of the referring page.
+#[tracing::instrument(name = "Count signups by source", skip(db_pool))]
+pub async fn get_signups_by_source(db_pool: &PgPool) -> Result<Vec<SignupCount>, anyhow::Error> {
+    sqlx::query_as!(
+        SignupCount,
+        r#"
+        SELECT
+            COALESCE(
+                signup_source,
+                utm_source,
+                substring(referrer FROM '^[A-Za-z][A-Za-z0-9+.-]*://([^/:?#]+)')
+            ) AS source,
+            count(*) AS "n_signups!",
+            count(*) FILTER (WHERE status = 'confirmed') AS "n_confirmed!"
+        FROM subscriptions
+        GROUP BY 1
+        ORDER BY 2 DESC, 1 NULLS LAST
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to count the signups by source.")
+}
@@ -0,0 +1,442 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use serde_json::{Map, Value};
+use sqlx::{types::Json, PgPool};
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{CanonicalEmailRules, SubscriberEmail},
+    email_layouts::{get_email_layout, get_email_layouts, wrap_in_layout, EmailLayout},
+    mailing_lists::{get_mailing_lists, MailingList},
+    personalization::{personalize_issue, MergeFields, PersonalizedIssue},
+    routes::get_username,
+    startup::{ApplicationBaseUrl, HmacSecret},
+    unsubscribe::unsubscribe_link,
+    utils::{e404, e500},
+};
+
+#[derive(serde::Serialize)]
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    status: String,
+    updated_at: DateTime<Utc>,
+    send_at: Option<DateTime<Utc>>,
+    published_at: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub(super) struct Issue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub markdown_content: Option<String>,
+    pub text_content: String,
+    pub html_content: String,
+    pub layout_id: Option<Uuid>,
+    pub status: String,
+    send_at: Option<DateTime<Utc>>,
+    published_at: Option<String>,
+}
+
+#[tracing::instrument(name = "Get issues page", skip(tera, db_pool, user_id, flash_messages))]
+pub async fn issues_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        issues: Vec<IssueSummary>,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let issues = get_issues(&db_pool)
+        .await
+        .context("Failed to fetch the newsletter issues")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        issues,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/issues.j2", &context)
+        .context("Failed to render the issues page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// An empty draft, saved on its first submission.
+#[tracing::instrument(name = "Get new issue form", skip_all)]
+pub async fn new_issue_form(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_mailing_lists(&db_pool)
+        .await
+        .context("Failed to fetch the mailing lists")
+        .map_err(e500)?;
+    let selected_list_ids = lists
+        .iter()
+        .filter(|list| list.is_default)
+        .map(|list| list.list_id)
+        .collect();
+    render_issue_page(
+        &tera,
+        &db_pool,
+        user_id.into_inner(),
+        flash_messages,
+        None,
+        lists,
+        selected_list_ids,
+    )
+    .await
+}
+
+/// Drafts and ready issues can be edited, the others are read-only.
+#[tracing::instrument(name = "Get issue page", skip(tera, db_pool, user_id, flash_messages))]
+pub async fn issue_page(
+    issue_id: web::Path<Uuid>,
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue = get_issue(&db_pool, issue_id)
+        .await
+        .context("Failed to fetch the newsletter issue")
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no such newsletter issue."))?;
+    let lists = get_mailing_lists(&db_pool)
+        .await
+        .context("Failed to fetch the mailing lists")
+        .map_err(e500)?;
+    let selected_list_ids = get_issue_list_ids(&db_pool, issue_id)
+        .await
+        .context("Failed to fetch the lists of the newsletter issue")
+        .map_err(e500)?;
+    render_issue_page(
+        &tera,
+        &db_pool,
+        user_id.into_inner(),
+        flash_messages,
+        Some(issue),
+        lists,
+        selected_list_ids,
+    )
+    .await
+}
+
+async fn render_issue_page(
+    tera: &Tera,
+    db_pool: &PgPool,
+    user_id: UserId,
+    flash_messages: IncomingFlashMessages,
+    issue: Option<Issue>,
+    lists: Vec<MailingList>,
+    selected_list_ids: Vec<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        issue: Option<Issue>,
+        lists: Vec<MailingList>,
+        selected_list_ids: Vec<Uuid>,
+        layouts: Vec<EmailLayout>,
+        idempotency_key: String,
+    }
+
+    let username = get_username(*user_id, db_pool).await.map_err(e500)?;
+    let layouts = get_email_layouts(db_pool)
+        .await
+        .context("Failed to fetch the email layouts")
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        issue,
+        lists,
+        selected_list_ids,
+        layouts,
+        idempotency_key: Uuid::new_v4().to_string(),
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/issue.j2", &context)
+        .context("Failed to render the issue page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PreviewQuery {
+    /// The address of the subscriber to preview the issue for.
+    #[serde(default)]
+    email: String,
+}
+
+/// The issue as the delivery workers would send it, personalized for a
+/// subscriber or for a sample reader.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    name = "Preview an issue",
+    skip(query, tera, db_pool, rules, base_url, hmac_secret, user_id)
+)]
+pub async fn issue_preview(
+    issue_id: web::Path<Uuid>,
+    query: web::Query<PreviewQuery>,
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        username: String,
+        issue: Issue,
+        email: String,
+        notice: Option<&'static str>,
+        reader_email: String,
+        html_content: String,
+        text_content: String,
+    }
+
+    let issue = get_issue(&db_pool, issue_id.into_inner())
+        .await
+        .context("Failed to fetch the newsletter issue")
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no such newsletter issue."))?;
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let email = query.into_inner().email.trim().to_owned();
+    let (reader, notice) = if email.is_empty() {
+        (Reader::sample(), None)
+    } else {
+        let reader = match SubscriberEmail::parse(email.clone()) {
+            Ok(email) => find_reader(&db_pool, &email, &rules)
+                .await
+                .context("Failed to fetch the subscriber")
+                .map_err(e500)?,
+            Err(_) => None,
+        };
+        match reader {
+            Some(reader) => (reader, None),
+            None => (
+                Reader::sample(),
+                Some(
+                    "There is no subscriber with this address: the preview is for a sample reader.",
+                ),
+            ),
+        }
+    };
+    let layout = get_issue_layout(&db_pool, &issue)
+        .await
+        .context("Failed to fetch the email layout of the issue")
+        .map_err(e500)?;
+    let PersonalizedIssue {
+        html_content,
+        text_content,
+    } = reader
+        .render(&issue, layout.as_ref(), &base_url.0, &hmac_secret.0)
+        .context("Failed to render the email layout of the issue")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        username,
+        issue,
+        email,
+        notice,
+        reader_email: reader.email,
+        html_content,
+        text_content,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/issue_preview.j2", &context)
+        .context("Failed to render the issue preview")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Whom a preview or a test send is personalized for.
+pub(super) struct Reader {
+    /// `None` for the sample reader.
+    pub subscriber_id: Option<Uuid>,
+    pub email: String,
+    pub name: String,
+    pub attributes: Map<String, Value>,
+}
+
+impl Reader {
+    /// Stands in for subscribers in previews and test sends.
+    pub fn sample() -> Self {
+        Self {
+            subscriber_id: None,
+            email: "reader@example.com".into(),
+            name: "Newsletter Reader".into(),
+            attributes: Map::new(),
+        }
+    }
+
+    pub fn merge_fields(&self) -> MergeFields<'_> {
+        MergeFields {
+            email: &self.email,
+            name: &self.name,
+            attributes: &self.attributes,
+        }
+    }
+
+    /// The issue as the delivery workers would send it to this reader. The
+    /// unsubscribe link of the sample reader is signed, but for nobody.
+    pub fn render(
+        &self,
+        issue: &Issue,
+        layout: Option<&EmailLayout>,
+        base_url: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Result<PersonalizedIssue, tera::Error> {
+        let unsubscribe_link = unsubscribe_link(
+            base_url,
+            hmac_secret,
+            self.subscriber_id.unwrap_or_else(Uuid::nil),
+        );
+        wrap_in_layout(
+            layout,
+            &issue.title,
+            personalize_issue(
+                &issue.html_content,
+                &issue.text_content,
+                &self.merge_fields(),
+            ),
+            &unsubscribe_link,
+        )
+    }
+}
+
+pub(super) async fn get_issue_layout(
+    db_pool: &PgPool,
+    issue: &Issue,
+) -> Result<Option<EmailLayout>, sqlx::Error> {
+    match issue.layout_id {
+        Some(layout_id) => get_email_layout(db_pool, layout_id).await,
+        None => Ok(None),
+    }
+}
+
+/// The subscriber with this address, confirmed or not.
+#[tracing::instrument(skip(db_pool, rules))]
+pub(super) async fn find_reader(
+    db_pool: &PgPool,
+    email: &SubscriberEmail,
+    rules: &CanonicalEmailRules,
+) -> Result<Option<Reader>, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT id, email, name, attributes as "attributes: Json<Map<String, Value>>"
+        FROM subscriptions
+        WHERE canonical_email = $1
+        "#,
+        email.canonical(rules),
+    )
+    .fetch_optional(db_pool)
+    .await?;
+    Ok(r.map(|r| Reader {
+        subscriber_id: Some(r.id),
+        email: r.email,
+        name: r.name,
+        attributes: r.attributes.0,
+    }))
+}
+
+/// Work in progress first, then the most recently published.
+#[tracing::instrument(skip(db_pool))]
+async fn get_issues(db_pool: &PgPool) -> Result<Vec<IssueSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT newsletter_issue_id, title, status, updated_at, send_at, published_at
+        FROM newsletter_issues
+        ORDER BY status IN ('sending', 'sent'), published_at DESC, updated_at DESC
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(skip(db_pool))]
+pub(super) async fn get_issue(
+    db_pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<Issue>, sqlx::Error> {
+    sqlx::query_as!(
+        Issue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            markdown_content,
+            text_content,
+            html_content,
+            layout_id,
+            status,
+            send_at,
+            published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_issue_list_ids(db_pool: &PgPool, issue_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT list_id FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_all(db_pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.list_id).collect())
+}
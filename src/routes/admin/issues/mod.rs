@@ -0,0 +1,8 @@
+mod get;
+mod post;
+
+pub use get::{issue_page, issue_preview, issues_page, new_issue_form};
+pub use post::{
+    cancel_scheduled_issue, create_issue, delete_issue, mark_issue_ready, schedule_issue,
+    send_issue, send_test_issue, update_issue,
+};
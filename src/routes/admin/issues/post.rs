@@ -0,0 +1,587 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{CanonicalEmailRules, IssueContent, SendAt, SubscriberEmail},
+    email_client::EmailClient,
+    email_layouts::{resolve_email_layout, LayoutSelectionError},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_delivery_worker::enqueue_delivery_tasks,
+    mailing_lists::{resolve_mailing_lists, ListSelectionError},
+    personalization::{validate_merge_tags, PersonalizedIssue},
+    routes::{insert_newsletter_issue, save_issue_lists, schedule_send, success_message},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    suppressions::is_suppressed,
+    utils::{e400, e404, e500, see_other},
+};
+
+use super::get::{find_reader, get_issue, get_issue_layout, Reader};
+
+/// Tests go to a handful of colleagues, not to a list.
+const MAX_TEST_RECIPIENTS: usize = 5;
+
+#[derive(serde::Deserialize)]
+pub struct IssueFormData {
+    title: String,
+    /// Both bodies are derived from the Markdown source, when there is one.
+    #[serde(default)]
+    markdown_content: String,
+    #[serde(default)]
+    html_content: String,
+    #[serde(default)]
+    text_content: String,
+    /// Slugs of the lists whose members receive the issue.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Left empty to send the issue without a layout.
+    #[serde(default)]
+    layout_id: String,
+}
+
+/// Drafts are saved as they are: they are only checked once marked as ready.
+#[tracing::instrument(name = "Create a draft issue", skip_all)]
+pub async fn create_issue(
+    form: UrlEncodedForm<IssueFormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let IssueFormData {
+        title,
+        markdown_content,
+        html_content,
+        text_content,
+        lists,
+        layout_id,
+    } = form.into_inner();
+    let content =
+        IssueContent::parse(markdown_content, html_content, text_content).map_err(e400)?;
+    let list_ids = resolve_lists(&db_pool, &lists).await?;
+    let layout_id = resolve_layout(&db_pool, &layout_id).await?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let issue_id = insert_newsletter_issue(&mut transaction, "draft", &title, &content, layout_id)
+        .await
+        .context("Failed to store the draft issue")
+        .map_err(e500)?;
+    save_issue_lists(&mut transaction, issue_id, &list_ids)
+        .await
+        .context("Failed to store the lists of the draft issue")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to store a draft issue")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+/// Editing a ready issue turns it back into a draft, to be reviewed again.
+#[tracing::instrument(name = "Update a draft issue", skip(form, db_pool))]
+pub async fn update_issue(
+    issue_id: web::Path<Uuid>,
+    form: UrlEncodedForm<IssueFormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let IssueFormData {
+        title,
+        markdown_content,
+        html_content,
+        text_content,
+        lists,
+        layout_id,
+    } = form.into_inner();
+    let content =
+        IssueContent::parse(markdown_content, html_content, text_content).map_err(e400)?;
+    let list_ids = resolve_lists(&db_pool, &lists).await?;
+    let layout_id = resolve_layout(&db_pool, &layout_id).await?;
+    let issue_page = format!("/admin/issues/{}", issue_id);
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    if let Some(reason) = not_editable_reason(&mut transaction, issue_id).await? {
+        FlashMessage::error(reason).send();
+        return Ok(see_other(&issue_page));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            markdown_content = $5,
+            layout_id = $6,
+            status = 'draft',
+            updated_at = NOW()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id,
+        title,
+        content.text_content,
+        content.html_content,
+        content.markdown_content,
+        layout_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the draft issue")
+    .map_err(e500)?;
+    save_issue_lists(&mut transaction, issue_id, &list_ids)
+        .await
+        .context("Failed to store the lists of the draft issue")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to update a draft issue")
+        .map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&issue_page))
+}
+
+/// A draft is ready once it has a title and its merge tags are valid.
+#[tracing::instrument(name = "Mark an issue as ready", skip(db_pool))]
+pub async fn mark_issue_ready(
+    issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue_page = format!("/admin/issues/{}", issue_id);
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    if lock_issue_status(&mut transaction, issue_id).await? != "draft" {
+        FlashMessage::error("Only drafts can be marked as ready.").send();
+        return Ok(see_other(&issue_page));
+    }
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to fetch the draft issue")
+    .map_err(e500)?;
+    if issue.title.trim().is_empty() {
+        FlashMessage::error("Give the issue a title before marking it as ready.").send();
+        return Ok(see_other(&issue_page));
+    }
+    if let Err(e) = validate_merge_tags(&issue.html_content)
+        .and_then(|_| validate_merge_tags(&issue.text_content))
+    {
+        FlashMessage::error(format!("The issue contains invalid merge tags: {}", e)).send();
+        return Ok(see_other(&issue_page));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'ready', updated_at = NOW()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to mark the issue as ready")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to mark an issue as ready")
+        .map_err(e500)?;
+
+    FlashMessage::info("The issue is ready to be sent.").send();
+    Ok(see_other(&issue_page))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SendFormData {
+    idempotency_key: String,
+}
+
+/// Enqueue the deliveries of a ready issue, once whatever the number of submissions.
+#[tracing::instrument(
+    name = "Send a newsletter issue",
+    skip(form, db_pool, user_id),
+    fields(user_id=%&*user_id)
+)]
+pub async fn send_issue(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<SendFormData>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let user_id = user_id.into_inner();
+    let idempotency_key: IdempotencyKey = form.0.idempotency_key.try_into().map_err(e400)?;
+
+    let mut transaction = match try_processing(&db_pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message(None).send();
+            return Ok(saved_response);
+        }
+    };
+    // Dropping the transaction forgets the idempotency key: fixing the issue
+    // and submitting the form again is allowed.
+    if lock_issue_status(&mut transaction, issue_id).await? != "ready" {
+        FlashMessage::error("Only issues marked as ready can be sent.").send();
+        return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+    }
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
+
+    let response = see_other("/admin/issues");
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+    success_message(None).send();
+    Ok(response)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleFormData {
+    send_at: String,
+}
+
+/// Schedule a ready issue, or move an issue that has not gone out yet to another time.
+#[tracing::instrument(name = "Schedule a newsletter issue", skip(form, db_pool))]
+pub async fn schedule_issue(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<ScheduleFormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue_page = format!("/admin/issues/{}", issue_id);
+    let send_at = match SendAt::parse(&form.send_at, Utc::now()) {
+        Ok(send_at) => send_at,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&issue_page));
+        }
+    };
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    match lock_issue_status(&mut transaction, issue_id)
+        .await?
+        .as_str()
+    {
+        "ready" | "scheduled" => {}
+        "draft" => {
+            FlashMessage::error("Only issues marked as ready can be scheduled.").send();
+            return Ok(see_other(&issue_page));
+        }
+        _ => {
+            FlashMessage::error("This issue has already gone out.").send();
+            return Ok(see_other(&issue_page));
+        }
+    }
+    schedule_send(&mut transaction, issue_id, send_at)
+        .await
+        .context("Failed to schedule the newsletter issue")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to schedule an issue")
+        .map_err(e500)?;
+
+    success_message(Some(send_at)).send();
+    Ok(see_other(&issue_page))
+}
+
+/// The issue goes back to being ready, to be sent or scheduled again.
+#[tracing::instrument(name = "Cancel a scheduled issue", skip(db_pool))]
+pub async fn cancel_scheduled_issue(
+    issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue_page = format!("/admin/issues/{}", issue_id);
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    // Waits for a worker fanning the issue out, if one got to it first.
+    match lock_issue_status(&mut transaction, issue_id)
+        .await?
+        .as_str()
+    {
+        "scheduled" => {}
+        "draft" | "ready" => {
+            FlashMessage::error("This issue is not scheduled.").send();
+            return Ok(see_other(&issue_page));
+        }
+        _ => {
+            FlashMessage::error("This issue has already gone out.").send();
+            return Ok(see_other(&issue_page));
+        }
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'ready', send_at = NULL, updated_at = NOW()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to cancel the scheduled issue")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to cancel a scheduled issue")
+        .map_err(e500)?;
+
+    FlashMessage::info("The issue is no longer scheduled.").send();
+    Ok(see_other(&issue_page))
+}
+
+#[derive(serde::Deserialize)]
+pub struct TestSendFormData {
+    /// Addresses separated by commas, spaces or new lines.
+    recipients: String,
+}
+
+/// Email the issue, whatever its status, to a few addresses right away.
+/// Each copy is personalized for the subscriber with that address, or for a
+/// sample reader: nothing is enqueued nor recorded as delivered.
+#[tracing::instrument(
+    name = "Send a test of an issue",
+    skip(form, db_pool, rules, email_client, base_url, hmac_secret)
+)]
+pub async fn send_test_issue(
+    issue_id: web::Path<Uuid>,
+    form: web::Form<TestSendFormData>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+    let issue_page = format!("/admin/issues/{}", issue_id);
+    let recipients = match parse_test_recipients(&form.recipients) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&issue_page));
+        }
+    };
+    let issue = get_issue(&db_pool, issue_id)
+        .await
+        .context("Failed to fetch the newsletter issue")
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no such newsletter issue."))?;
+    let layout = get_issue_layout(&db_pool, &issue)
+        .await
+        .context("Failed to fetch the email layout of the issue")
+        .map_err(e500)?;
+    let subject = format!("[Test] {}", issue.title);
+
+    let mut sent = Vec::new();
+    for recipient in recipients {
+        if is_suppressed(db_pool.get_ref(), &recipient, &rules)
+            .await
+            .context("Failed to check the suppression list")
+            .map_err(e500)?
+        {
+            FlashMessage::error(format!(
+                "{} is on the suppression list: no test was sent to it.",
+                recipient.as_ref()
+            ))
+            .send();
+            continue;
+        }
+        let reader = find_reader(&db_pool, &recipient, &rules)
+            .await
+            .context("Failed to fetch the subscriber")
+            .map_err(e500)?
+            .unwrap_or_else(Reader::sample);
+        let PersonalizedIssue {
+            html_content,
+            text_content,
+        } = reader
+            .render(&issue, layout.as_ref(), &base_url.0, &hmac_secret.0)
+            .context("Failed to render the email layout of the issue")
+            .map_err(e500)?;
+        match email_client
+            .send_email(&recipient, &subject, &html_content, &text_content)
+            .await
+        {
+            Ok(()) => sent.push(recipient.as_ref().to_owned()),
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a test of the issue"
+                );
+                FlashMessage::error(format!(
+                    "The test could not be sent to {}.",
+                    recipient.as_ref()
+                ))
+                .send();
+            }
+        }
+    }
+    if !sent.is_empty() {
+        FlashMessage::info(format!("A test has been sent to {}.", sent.join(", "))).send();
+    }
+    Ok(see_other(&issue_page))
+}
+
+/// Only issues that have not gone out can be deleted.
+#[tracing::instrument(name = "Delete a draft issue", skip(db_pool))]
+pub async fn delete_issue(
+    issue_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue_id = issue_id.into_inner();
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    if let Some(reason) = not_editable_reason(&mut transaction, issue_id).await? {
+        FlashMessage::error(reason).send();
+        return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+    }
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the lists of the issue")
+    .map_err(e500)?;
+    let title = sqlx::query!(
+        "DELETE FROM newsletter_issues WHERE newsletter_issue_id = $1 RETURNING title",
+        issue_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to delete the issue")
+    .map_err(e500)?
+    .title;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to delete an issue")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("{} has been deleted.", title)).send();
+    Ok(see_other("/admin/issues"))
+}
+
+async fn resolve_lists(db_pool: &PgPool, slugs: &[String]) -> Result<Vec<Uuid>, actix_web::Error> {
+    resolve_mailing_lists(db_pool, slugs)
+        .await
+        .map_err(|e| match e {
+            ListSelectionError::UnknownLists(_) => e400(e),
+            e => e500(e),
+        })
+}
+
+async fn resolve_layout(
+    db_pool: &PgPool,
+    layout_id: &str,
+) -> Result<Option<Uuid>, actix_web::Error> {
+    resolve_email_layout(db_pool, layout_id)
+        .await
+        .map_err(|e| match e {
+            LayoutSelectionError::UnknownLayout => e400(e),
+            e => e500(e),
+        })
+}
+
+/// The status of the issue, locked until the end of the transaction.
+async fn lock_issue_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<String, actix_web::Error> {
+    let record = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1 FOR UPDATE",
+        issue_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to fetch the status of the issue")
+    .map_err(e500)?
+    .ok_or_else(|| e404("There is no such newsletter issue."))?;
+    Ok(record.status)
+}
+
+/// Why the issue can no longer be changed, if it cannot: only drafts and
+/// ready issues can.
+async fn not_editable_reason(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<Option<&'static str>, actix_web::Error> {
+    Ok(
+        match lock_issue_status(transaction, issue_id).await?.as_str() {
+            "draft" | "ready" => None,
+            "scheduled" => Some("This issue is scheduled: cancel the schedule first."),
+            _ => Some("This issue has already gone out."),
+        },
+    )
+}
+
+fn parse_test_recipients(recipients: &str) -> Result<Vec<SubscriberEmail>, String> {
+    let mut parsed: Vec<SubscriberEmail> = Vec::new();
+    for recipient in recipients.split(|c: char| c == ',' || c.is_whitespace()) {
+        if recipient.is_empty() {
+            continue;
+        }
+        let recipient = SubscriberEmail::parse(recipient.to_owned())?;
+        if !parsed.iter().any(|p| p.as_ref() == recipient.as_ref()) {
+            parsed.push(recipient);
+        }
+    }
+    if parsed.is_empty() {
+        return Err("Enter the addresses to send a test to.".into());
+    }
+    if parsed.len() > MAX_TEST_RECIPIENTS {
+        return Err(format!(
+            "Send a test to {} addresses at most.",
+            MAX_TEST_RECIPIENTS
+        ));
+    }
+    Ok(parsed)
+}
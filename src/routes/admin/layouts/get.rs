@@ -0,0 +1,132 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    email_layouts::{get_email_layout, EmailLayout, STARTER_HTML_TEMPLATE, STARTER_TEXT_TEMPLATE},
+    routes::get_username,
+    utils::{e404, e500},
+};
+
+#[derive(serde::Serialize)]
+struct LayoutSummary {
+    layout_id: Uuid,
+    name: String,
+    n_issues: i64,
+}
+
+/// The layouts, and a form to create one from the starter templates.
+#[tracing::instrument(name = "Get email layouts page", skip_all)]
+pub async fn email_layouts_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        layouts: Vec<LayoutSummary>,
+        starter_html_template: &'static str,
+        starter_text_template: &'static str,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let layouts = get_layout_summaries(&db_pool)
+        .await
+        .context("Failed to fetch the email layouts")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        layouts,
+        starter_html_template: STARTER_HTML_TEMPLATE,
+        starter_text_template: STARTER_TEXT_TEMPLATE,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/layouts.j2", &context)
+        .context("Failed to render the email layouts page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(
+    name = "Get email layout page",
+    skip(tera, db_pool, user_id, flash_messages)
+)]
+pub async fn email_layout_page(
+    layout_id: web::Path<Uuid>,
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        layout: EmailLayout,
+    }
+
+    let layout = get_email_layout(db_pool.get_ref(), layout_id.into_inner())
+        .await
+        .context("Failed to fetch the email layout")
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no such email layout."))?;
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        layout,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/layout.j2", &context)
+        .context("Failed to render the email layout page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_layout_summaries(db_pool: &PgPool) -> Result<Vec<LayoutSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        LayoutSummary,
+        r#"
+        SELECT l.layout_id, l.name, COUNT(i.newsletter_issue_id) AS "n_issues!"
+        FROM email_layouts l
+        LEFT JOIN newsletter_issues i ON i.layout_id = l.layout_id
+        GROUP BY l.layout_id
+        ORDER BY l.name
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{email_layout_page, email_layouts_page};
+pub use post::{create_email_layout, delete_email_layout, update_email_layout};
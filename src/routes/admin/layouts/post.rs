@@ -0,0 +1,177 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    email_layouts::validate_layout,
+    utils::{e404, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+    html_template: String,
+    text_template: String,
+    postal_address: String,
+}
+
+impl FormData {
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("The layout name cannot be empty.".into());
+        }
+        if self.postal_address.trim().is_empty() {
+            return Err("Enter the postal address shown in the footer.".into());
+        }
+        validate_layout(&self.html_template, &self.text_template)
+    }
+}
+
+#[tracing::instrument(name = "Create an email layout", skip(form, db_pool), fields(name = %form.name))]
+pub async fn create_email_layout(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Err(e) = form.validate() {
+        FlashMessage::error(e).send();
+        return Ok(see_other("/admin/layouts"));
+    }
+    let name = form.name.trim();
+
+    let layout_id = Uuid::new_v4();
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO email_layouts (layout_id, name, html_template, text_template, postal_address)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        layout_id,
+        name,
+        form.html_template,
+        form.text_template,
+        form.postal_address.trim(),
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to store the new email layout")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_inserted_rows == 0 {
+        FlashMessage::error(format!("A layout named '{}' already exists.", name)).send();
+        return Ok(see_other("/admin/layouts"));
+    }
+    FlashMessage::info(format!("The layout '{}' has been created.", name)).send();
+    Ok(see_other(&format!("/admin/layouts/{}", layout_id)))
+}
+
+/// Issues that have not gone out yet are sent with the updated layout.
+#[tracing::instrument(name = "Update an email layout", skip(form, db_pool))]
+pub async fn update_email_layout(
+    layout_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let layout_page = format!("/admin/layouts/{}", layout_id);
+    if let Err(e) = form.validate() {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&layout_page));
+    }
+    let name = form.name.trim();
+
+    let name_is_taken = sqlx::query!(
+        "SELECT layout_id FROM email_layouts WHERE name = $1 AND layout_id <> $2",
+        name,
+        layout_id
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to look for a layout with the same name")
+    .map_err(e500)?
+    .is_some();
+    if name_is_taken {
+        FlashMessage::error(format!("A layout named '{}' already exists.", name)).send();
+        return Ok(see_other(&layout_page));
+    }
+    let n_updated_rows = sqlx::query!(
+        r#"
+        UPDATE email_layouts
+        SET
+            name = $2,
+            html_template = $3,
+            text_template = $4,
+            postal_address = $5,
+            updated_at = NOW()
+        WHERE layout_id = $1
+        "#,
+        layout_id,
+        name,
+        form.html_template,
+        form.text_template,
+        form.postal_address.trim(),
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to update the email layout")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(e404("There is no such email layout."));
+    }
+
+    FlashMessage::info("The layout has been saved.").send();
+    Ok(see_other(&layout_page))
+}
+
+/// Layouts used by issues, even sent ones, are kept.
+#[tracing::instrument(name = "Delete an email layout", skip(db_pool))]
+pub async fn delete_email_layout(
+    layout_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let layout = sqlx::query!(
+        "SELECT name FROM email_layouts WHERE layout_id = $1 FOR UPDATE",
+        layout_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to fetch the email layout")
+    .map_err(e500)?
+    .ok_or_else(|| e404("There is no such email layout."))?;
+    let is_used = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM newsletter_issues WHERE layout_id = $1) AS "is_used!""#,
+        layout_id
+    )
+    .fetch_one(&mut transaction)
+    .await
+    .context("Failed to check whether the email layout is used")
+    .map_err(e500)?
+    .is_used;
+    if is_used {
+        FlashMessage::error("This layout is used by issues: it cannot be deleted.").send();
+        return Ok(see_other(&format!("/admin/layouts/{}", layout_id)));
+    }
+    sqlx::query!("DELETE FROM email_layouts WHERE layout_id = $1", layout_id)
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the email layout")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to delete an email layout")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("The layout '{}' has been deleted.", layout.name)).send();
+    Ok(see_other("/admin/layouts"))
+}
@@ -0,0 +1,83 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{authentication::UserId, routes::get_username, utils::e500};
+
+#[derive(serde::Serialize)]
+struct ListSummary {
+    list_id: Uuid,
+    slug: String,
+    name: String,
+    is_default: bool,
+    n_confirmed: i64,
+    n_pending: i64,
+}
+
+#[tracing::instrument(name = "Get mailing lists page", skip_all)]
+pub async fn mailing_lists_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        lists: Vec<ListSummary>,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let lists = get_list_summaries(&db_pool)
+        .await
+        .context("Failed to fetch the mailing lists")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        lists,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/lists.j2", &context)
+        .context("Failed to render the mailing lists page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_list_summaries(db_pool: &PgPool) -> Result<Vec<ListSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        ListSummary,
+        r#"
+        SELECT
+            l.list_id,
+            l.slug,
+            l.name,
+            l.is_default,
+            COUNT(m.subscriber_id) FILTER (WHERE m.status = 'confirmed') AS "n_confirmed!",
+            COUNT(m.subscriber_id) FILTER (WHERE m.status = 'pending_confirmation') AS "n_pending!"
+        FROM lists l
+        LEFT JOIN list_memberships m ON m.list_id = l.list_id
+        GROUP BY l.list_id
+        ORDER BY l.name
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+}
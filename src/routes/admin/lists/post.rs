@@ -0,0 +1,63 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    slug: String,
+    name: String,
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(form, db_pool), fields(slug = %form.slug))]
+pub async fn create_mailing_list(
+    form: web::Form<FormData>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let slug = form.slug.trim();
+    let name = form.name.trim();
+
+    // Slugs end up in form values and URLs: keep them boring.
+    let is_valid_slug = !slug.is_empty()
+        && slug.len() <= 64
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !is_valid_slug {
+        FlashMessage::error(
+            "The slug must be 1 to 64 characters long, using lowercase letters, digits and dashes.",
+        )
+        .send();
+        return Ok(see_other("/admin/lists"));
+    }
+    if name.is_empty() {
+        FlashMessage::error("The list name cannot be empty.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, slug, name)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (slug) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        slug,
+        name
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to store the new mailing list")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_inserted_rows == 0 {
+        FlashMessage::error(format!("A list with the slug '{}' already exists.", slug)).send();
+    } else {
+        FlashMessage::info(format!("The list '{}' has been created.", name)).send();
+    }
+    Ok(see_other("/admin/lists"))
+}
@@ -1,9 +1,23 @@
 mod dashboard;
+mod issues;
+mod layouts;
+mod lists;
 mod logout;
 mod newsletters;
 mod password;
+mod personal_data;
+mod subscriber_imports;
+mod subscribers;
+mod suppressions;
 
 pub use dashboard::*;
+pub use issues::*;
+pub use layouts::*;
+pub use lists::*;
 pub use logout::*;
 pub use newsletters::*;
 pub use password::*;
+pub use personal_data::*;
+pub use subscriber_imports::*;
+pub use subscribers::*;
+pub use suppressions::*;
@@ -5,7 +5,13 @@ use sqlx::PgPool;
 use tera::Tera;
 use uuid::Uuid;
 
-use crate::{authentication::UserId, routes::get_username, utils::e500};
+use crate::{
+    authentication::UserId,
+    email_layouts::{get_email_layouts, EmailLayout},
+    mailing_lists::{get_mailing_lists, MailingList},
+    routes::get_username,
+    utils::e500,
+};
 
 pub async fn send_newsletter_form(
     tera: web::Data<Tera>,
@@ -18,6 +24,8 @@ pub async fn send_newsletter_form(
         messages: Vec<String>,
         username: String,
         idempotency_key: String,
+        lists: Vec<MailingList>,
+        layouts: Vec<EmailLayout>,
     }
 
     let username = {
@@ -31,11 +39,21 @@ pub async fn send_newsletter_form(
         .collect();
 
     let idempotency_key = Uuid::new_v4().to_string();
+    let lists = get_mailing_lists(&db_pool)
+        .await
+        .context("Failed to fetch the mailing lists")
+        .map_err(e500)?;
+    let layouts = get_email_layouts(&db_pool)
+        .await
+        .context("Failed to fetch the email layouts")
+        .map_err(e500)?;
 
     let body_data = BodyData {
         messages,
         username,
         idempotency_key,
+        lists,
+        layouts,
     };
 
     let render_context = tera::Context::from_serialize(body_data)
@@ -3,3 +3,4 @@ mod post;
 
 pub use get::send_newsletter_form;
 pub use post::publish_newsletter;
+pub(crate) use post::{insert_newsletter_issue, save_issue_lists, schedule_send, success_message};
@@ -1,6 +1,11 @@
 use crate::{
     authentication::UserId,
+    domain::{IssueContent, SendAt},
+    email_layouts::{resolve_email_layout, LayoutSelectionError},
     idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_delivery_worker::enqueue_delivery_tasks,
+    mailing_lists::{resolve_mailing_lists, ListSelectionError},
+    personalization::validate_merge_tags,
     utils::{e400, e500, see_other},
 };
 use actix_web::{
@@ -8,16 +13,32 @@ use actix_web::{
     HttpResponse,
 };
 use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
 use anyhow::Context;
+use chrono::Utc;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     title: String,
+    /// Both bodies are derived from the Markdown source, when there is one.
+    #[serde(default)]
+    markdown_content: String,
+    #[serde(default)]
     html_content: String,
+    #[serde(default)]
     text_content: String,
     idempotency_key: String,
+    /// Slugs of the lists whose members receive the issue.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Left empty to send the issue without a layout.
+    #[serde(default)]
+    layout_id: String,
+    /// Left empty to send the issue right away.
+    #[serde(default)]
+    send_at: String,
 }
 
 #[tracing::instrument(
@@ -26,7 +47,7 @@ pub struct FormData {
     fields(user_id=%&*user_id)
 )]
 pub async fn publish_newsletter(
-    form: web::Form<FormData>,
+    form: UrlEncodedForm<FormData>,
     db_pool: web::Data<PgPool>,
     user_id: ReqData<UserId>,
 ) -> Result<HttpResponse, actix_web::Error> {
@@ -35,68 +56,121 @@ pub async fn publish_newsletter(
     // We must destructure the form to avoid upsetting the borrow-checker
     let FormData {
         title,
+        markdown_content,
         text_content,
         html_content,
         idempotency_key,
-    } = form.0;
+        lists,
+        layout_id,
+        send_at,
+    } = form.into_inner();
 
     let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let content =
+        IssueContent::parse(markdown_content, html_content, text_content).map_err(e400)?;
+    if content.html_content.trim().is_empty() && content.text_content.trim().is_empty() {
+        return Err(e400("The newsletter issue has no content."));
+    }
+    validate_merge_tags(&content.html_content)
+        .and_then(|_| validate_merge_tags(&content.text_content))
+        .context("The newsletter content contains invalid merge tags")
+        .map_err(e400)?;
+    let send_at = match send_at.trim() {
+        "" => None,
+        send_at => Some(SendAt::parse(send_at, Utc::now()).map_err(e400)?),
+    };
+    let list_ids = resolve_mailing_lists(&db_pool, &lists)
+        .await
+        .map_err(|e| match e {
+            ListSelectionError::UnknownLists(_) => e400(e),
+            e => e500(e),
+        })?;
+    let layout_id = resolve_email_layout(&db_pool, &layout_id)
+        .await
+        .map_err(|e| match e {
+            LayoutSelectionError::UnknownLayout => e400(e),
+            e => e500(e),
+        })?;
     let mut transaction = match try_processing(&db_pool, &idempotency_key, *user_id)
         .await
         .map_err(e500)?
     {
         NextAction::StartProcessing(t) => t,
         NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
+            success_message(send_at).send();
             return Ok(saved_response);
         }
     };
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
+    let issue_id = insert_newsletter_issue(&mut transaction, "ready", &title, &content, layout_id)
         .await
         .context("Failed to store newsletter issue details")
         .map_err(e500)?;
-
-    enqueue_delivery_tasks(&mut transaction, issue_id)
+    save_issue_lists(&mut transaction, issue_id, &list_ids)
         .await
-        .context("Failed to enqueue delivery tasks")
+        .context("Failed to store the lists of the newsletter issue")
         .map_err(e500)?;
 
+    match send_at {
+        Some(send_at) => schedule_send(&mut transaction, issue_id, send_at)
+            .await
+            .context("Failed to schedule the newsletter issue")
+            .map_err(e500)?,
+        None => enqueue_delivery_tasks(&mut transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks")
+            .map_err(e500)?,
+    }
+
     let response = see_other("/admin/newsletters");
     let response = save_response(transaction, &idempotency_key, *user_id, response)
         .await
         .map_err(e500)?;
-    success_message().send();
+    success_message(send_at).send();
     Ok(response)
 }
 
-fn success_message() -> FlashMessage {
-    FlashMessage::info("The newsletter issue has been accepted - emails will go out shortly.")
+pub(crate) fn success_message(send_at: Option<SendAt>) -> FlashMessage {
+    match send_at {
+        Some(send_at) => FlashMessage::info(format!(
+            "The newsletter issue has been scheduled - emails will go out at {}.",
+            send_at
+        )),
+        None => FlashMessage::info(
+            "The newsletter issue has been accepted - emails will go out shortly.",
+        ),
+    }
 }
 
 #[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
+pub(crate) async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
+    status: &str,
     title: &str,
-    text_content: &str,
-    html_content: &str,
+    content: &IssueContent,
+    layout_id: Option<Uuid>,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
         r#"
         INSERT INTO newsletter_issues (
             newsletter_issue_id,
+            status,
             title,
             text_content,
             html_content,
-            published_at
+            markdown_content,
+            layout_id
         )
-        VALUES ($1, $2, $3, $4, NOW())
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         "#,
         newsletter_issue_id,
+        status,
         title,
-        text_content,
-        html_content
+        content.text_content,
+        content.html_content,
+        content.markdown_content,
+        layout_id,
     )
     .execute(transaction)
     .await?;
@@ -104,22 +178,47 @@ async fn insert_newsletter_issue(
     Ok(newsletter_issue_id)
 }
 
+/// The lists whose members receive the issue, replacing any earlier selection.
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub(crate) async fn save_issue_lists(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
 ) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM newsletter_issue_lists WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
     sqlx::query!(
         r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id,
-            subscriber_email
-        )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        INSERT INTO newsletter_issue_lists (newsletter_issue_id, list_id)
+        SELECT $1, UNNEST($2::uuid[])
+        "#,
+        newsletter_issue_id,
+        list_ids,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+/// The delivery workers fan the issue out once `send_at` is reached.
+#[tracing::instrument(skip(transaction))]
+pub(crate) async fn schedule_send(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    send_at: SendAt,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET status = 'scheduled', send_at = $2, updated_at = NOW()
+        WHERE newsletter_issue_id = $1
         "#,
         newsletter_issue_id,
+        send_at.as_ref(),
     )
     .execute(transaction)
     .await?;
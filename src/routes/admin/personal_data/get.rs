@@ -0,0 +1,86 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use tera::Tera;
+
+use super::DataSubject;
+use crate::{
+    authentication::UserId,
+    domain::CanonicalEmailRules,
+    personal_data::collect_personal_data,
+    routes::get_username,
+    utils::{e400, e500},
+};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct QueryParams {
+    /// Pre-fills the form, e.g. from the page of a subscriber.
+    #[serde(default)]
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Get personal data page",
+    skip(tera, db_pool, user_id, flash_messages)
+)]
+pub async fn personal_data_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    query: web::Query<QueryParams>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData<'a> {
+        messages: Vec<String>,
+        username: String,
+        email: &'a str,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        email: &query.email,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/personal_data.j2", &context)
+        .context("Failed to render the personal data page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Download everything held about an address, as a JSON file to hand to its owner.
+#[tracing::instrument(name = "Export personal data", skip(db_pool, rules))]
+pub async fn export_personal_data(
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    query: web::Query<DataSubject>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = query.email().ok_or_else(|| e400("The email is missing."))?;
+    let personal_data = collect_personal_data(&db_pool, email, &rules)
+        .await
+        .map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("personal-data.json".into())],
+        })
+        .json(personal_data))
+}
@@ -0,0 +1,18 @@
+mod get;
+mod post;
+
+pub use get::{export_personal_data, personal_data_page};
+pub use post::erase_personal_data;
+
+/// The address a data-subject request is about.
+#[derive(serde::Deserialize, Debug)]
+pub struct DataSubject {
+    email: String,
+}
+
+impl DataSubject {
+    /// Stored addresses are not necessarily valid ones: only blanks are refused.
+    fn email(&self) -> Option<&str> {
+        Some(self.email.trim()).filter(|e| !e.is_empty())
+    }
+}
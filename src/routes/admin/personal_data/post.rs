@@ -0,0 +1,38 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+
+use super::DataSubject;
+use crate::{
+    domain::CanonicalEmailRules,
+    personal_data,
+    utils::{e500, see_other},
+};
+
+/// Erase an address at the request of its owner.
+///
+/// The address is not echoed back: it no longer belongs in the session either.
+#[tracing::instrument(name = "Erase personal data on request", skip(form, db_pool, rules))]
+pub async fn erase_personal_data(
+    form: web::Form<DataSubject>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(email) = form.email() else {
+        FlashMessage::error("The email is missing.").send();
+        return Ok(see_other("/admin/personal-data"));
+    };
+    let erasure = personal_data::erase_personal_data(&db_pool, email, &rules)
+        .await
+        .map_err(e500)?;
+    if erasure.subscriber_deleted {
+        FlashMessage::info("The subscriber and their data have been erased.").send();
+    } else {
+        FlashMessage::info(
+            "The address was not a subscriber. Its remaining traces have been erased, \
+            and it cannot be imported anymore.",
+        )
+        .send();
+    }
+    Ok(see_other("/admin/personal-data"))
+}
@@ -0,0 +1,141 @@
+use actix_web::{
+    http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    mailing_lists::{get_mailing_lists, MailingList},
+    routes::get_username,
+    utils::{e404, e500},
+};
+
+#[tracing::instrument(name = "Get import subscribers form", skip_all)]
+pub async fn import_subscribers_form(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        lists: Vec<MailingList>,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let lists = get_mailing_lists(&db_pool)
+        .await
+        .context("Failed to fetch the mailing lists")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        lists,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/import_subscribers.j2", &context)
+        .context("Failed to render the import subscribers form")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberImport {
+    import_id: Uuid,
+    n_rows: i32,
+    n_created: i32,
+    n_updated: i32,
+    n_rejected: i32,
+}
+
+#[tracing::instrument(name = "Get subscriber import page", skip(tera, db_pool, user_id))]
+pub async fn subscriber_import_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    import_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        username: String,
+        import: SubscriberImport,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let import = sqlx::query_as!(
+        SubscriberImport,
+        r#"
+        SELECT import_id, n_rows, n_created, n_updated, n_rejected
+        FROM subscriber_imports
+        WHERE import_id = $1
+        "#,
+        import_id.into_inner()
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the subscriber import")
+    .map_err(e500)?
+    .ok_or_else(|| e404("There is no such import."))?;
+
+    let context = tera::Context::from_serialize(BodyData { username, import })
+        .context("Failed to build context")
+        .map_err(e500)?;
+    let body = tera
+        .render("admin/subscriber_import.j2", &context)
+        .context("Failed to render the subscriber import page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(name = "Download the error report of an import", skip(db_pool))]
+pub async fn subscriber_import_error_report(
+    db_pool: web::Data<PgPool>,
+    import_id: web::Path<Uuid>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    let error_report = sqlx::query!(
+        "SELECT error_report FROM subscriber_imports WHERE import_id = $1",
+        import_id
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to fetch the error report of the import")
+    .map_err(e500)?
+    .ok_or_else(|| e404("There is no such import."))?
+    .error_report;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "import-{}-errors.csv",
+                import_id
+            ))],
+        })
+        .body(error_report))
+}
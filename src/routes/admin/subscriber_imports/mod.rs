@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{import_subscribers_form, subscriber_import_error_report, subscriber_import_page};
+pub use post::import_subscribers;
@@ -0,0 +1,147 @@
+use actix_multipart::{Field, Multipart};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use futures_util::TryStreamExt;
+use sqlx::PgPool;
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{
+    domain::CanonicalEmailRules,
+    mailing_lists::{resolve_mailing_lists, ListSelectionError},
+    startup::ApplicationBaseUrl,
+    subscriber_import::{ImportError, ImportOptions, ImportReport, SubscriberImport},
+    utils::{e400, e500, see_other},
+};
+
+/// Import the CSV file of a `multipart/form-data` upload.
+///
+/// The file is processed while it is being received: the other fields of the
+/// form must come first, which is the case when it is the last input of the form.
+#[tracing::instrument(name = "Import subscribers", skip_all)]
+pub async fn import_subscribers(
+    mut payload: Multipart,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut lists = Vec::new();
+    let mut pre_confirmed = false;
+    let mut consent_source = String::new();
+    let mut report = None;
+
+    while let Some(mut field) = payload.try_next().await.map_err(e400)? {
+        match field.name() {
+            Some("lists") => lists.push(read_text(&mut field).await?),
+            Some("pre_confirmed") => pre_confirmed = true,
+            Some("consent_source") => consent_source = read_text(&mut field).await?,
+            Some("file") if report.is_none() => {
+                let consent_source = consent_source.trim();
+                if pre_confirmed && consent_source.is_empty() {
+                    FlashMessage::error(
+                        "Record how the subscribers gave their consent to import them as confirmed.",
+                    )
+                    .send();
+                    return Ok(see_other("/admin/subscribers/import"));
+                }
+                let list_ids = match resolve_mailing_lists(&db_pool, &lists).await {
+                    Ok(list_ids) => list_ids,
+                    Err(e @ ListSelectionError::UnknownLists(_)) => {
+                        FlashMessage::error(e.to_string()).send();
+                        return Ok(see_other("/admin/subscribers/import"));
+                    }
+                    Err(e) => return Err(e500(e)),
+                };
+                let options = ImportOptions {
+                    list_ids,
+                    consent_source: pre_confirmed.then(|| consent_source.to_owned()),
+                };
+                match import_file(&mut field, &db_pool, &rules, &tera, &base_url.0, options).await?
+                {
+                    Ok(r) => report = Some(r),
+                    Err(message) => {
+                        FlashMessage::error(message).send();
+                        return Ok(see_other("/admin/subscribers/import"));
+                    }
+                }
+            }
+            _ => while field.try_next().await.map_err(e400)?.is_some() {},
+        }
+    }
+
+    let report = match report {
+        Some(report) => report,
+        None => {
+            FlashMessage::error("Pick a CSV file to import.").send();
+            return Ok(see_other("/admin/subscribers/import"));
+        }
+    };
+    let import_id = store_report(&db_pool, &report)
+        .await
+        .context("Failed to store the report of the import")
+        .map_err(e500)?;
+    Ok(see_other(&format!(
+        "/admin/subscribers/imports/{}",
+        import_id
+    )))
+}
+
+/// Returns the message to show when the file itself is the problem.
+async fn import_file(
+    field: &mut Field,
+    db_pool: &PgPool,
+    rules: &CanonicalEmailRules,
+    tera: &Tera,
+    base_url: &str,
+    options: ImportOptions,
+) -> Result<Result<ImportReport, String>, actix_web::Error> {
+    let mut import = SubscriberImport::new(db_pool, rules, tera, base_url, options);
+    let mut result = Ok(());
+    while let Some(chunk) = field.try_next().await.map_err(e400)? {
+        result = import.feed(&chunk).await;
+        if result.is_err() {
+            break;
+        }
+    }
+    let result = match result {
+        Ok(()) => import.finish().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(report) => Ok(Ok(report)),
+        Err(e @ ImportError::MissingColumns) => Ok(Err(e.to_string())),
+        Err(e) => Err(e500(e)),
+    }
+}
+
+async fn read_text(field: &mut Field) -> Result<String, actix_web::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(e400)? {
+        bytes.extend_from_slice(&chunk);
+    }
+    String::from_utf8(bytes).map_err(e400)
+}
+
+#[tracing::instrument(skip_all)]
+async fn store_report(db_pool: &PgPool, report: &ImportReport) -> Result<Uuid, anyhow::Error> {
+    let import_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id, n_rows, n_created, n_updated, n_rejected, error_report
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        import_id,
+        i32::try_from(report.n_rows)?,
+        i32::try_from(report.n_created)?,
+        i32::try_from(report.n_updated)?,
+        i32::try_from(report.rejected_rows.len())?,
+        report.error_report()?,
+    )
+    .execute(db_pool)
+    .await?;
+    Ok(import_id)
+}
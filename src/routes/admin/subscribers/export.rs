@@ -0,0 +1,239 @@
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web, HttpResponse,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Subscribers fetched per query: the export never holds more than this in memory.
+const BATCH_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// JSON Lines, one subscriber per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedMembership {
+    list: String,
+    status: String,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    lists: Vec<ExportedMembership>,
+}
+
+/// Stream every subscriber, batch by batch.
+///
+/// Each batch is a query of its own, so a subscriber added while the export is
+/// running may or may not be part of it.
+#[tracing::instrument(name = "Export subscribers", skip(db_pool))]
+pub async fn export_subscribers(
+    db_pool: web::Data<PgPool>,
+    params: web::Query<ExportParams>,
+) -> HttpResponse {
+    let format = params.format;
+    let batches = stream::try_unfold(
+        (db_pool.into_inner(), None, true),
+        move |(db_pool, after, is_first)| async move {
+            let subscribers = get_subscribers_after(&db_pool, after).await?;
+            let Some(last) = subscribers.last() else {
+                return Ok::<_, anyhow::Error>(None);
+            };
+            let after = Some(last.id);
+            let chunk = match format {
+                ExportFormat::Csv => to_csv(&subscribers, is_first)?,
+                ExportFormat::Ndjson => to_ndjson(&subscribers)?,
+            };
+            Ok(Some((web::Bytes::from(chunk), (db_pool, after, false))))
+        },
+    );
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "subscribers-{}.{}",
+                Utc::now().format("%Y-%m-%d"),
+                format.extension()
+            ))],
+        })
+        .streaming(batches)
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_subscribers_after(
+    db_pool: &PgPool,
+    after: Option<Uuid>,
+) -> Result<Vec<ExportedSubscriber>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            s.id,
+            s.email,
+            s.name,
+            s.status,
+            s.subscribed_at,
+            COALESCE(
+                array_agg(l.slug ORDER BY l.slug) FILTER (WHERE l.slug IS NOT NULL),
+                '{}'
+            ) AS "list_slugs!",
+            COALESCE(
+                array_agg(m.status ORDER BY l.slug) FILTER (WHERE l.slug IS NOT NULL),
+                '{}'
+            ) AS "list_statuses!"
+        FROM subscriptions s
+        LEFT JOIN list_memberships m ON m.subscriber_id = s.id
+        LEFT JOIN lists l ON l.list_id = m.list_id
+        WHERE $1::uuid IS NULL OR s.id > $1
+        GROUP BY s.id
+        ORDER BY s.id
+        LIMIT $2
+        "#,
+        after,
+        BATCH_SIZE,
+    )
+    .fetch_all(db_pool)
+    .await
+    .context("Failed to fetch a batch of subscribers to export")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| ExportedSubscriber {
+            id: r.id,
+            email: r.email,
+            name: r.name,
+            status: r.status,
+            subscribed_at: r.subscribed_at,
+            lists: r
+                .list_slugs
+                .into_iter()
+                .zip(r.list_statuses)
+                .map(|(list, status)| ExportedMembership { list, status })
+                .collect(),
+        })
+        .collect())
+}
+
+/// The memberships are flattened as `slug:status` pairs, separated by `;`.
+fn to_csv(subscribers: &[ExportedSubscriber], with_header: bool) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(vec![]);
+    if with_header {
+        writer.write_record(["id", "email", "name", "status", "subscribed_at", "lists"])?;
+    }
+    for subscriber in subscribers {
+        let lists = subscriber
+            .lists
+            .iter()
+            .map(|m| format!("{}:{}", m.list, m.status))
+            .collect::<Vec<_>>()
+            .join(";");
+        writer.write_record([
+            subscriber.id.to_string().as_str(),
+            &subscriber.email,
+            &subscriber.name,
+            &subscriber.status,
+            &subscriber.subscribed_at.to_rfc3339(),
+            &lists,
+        ])?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+fn to_ndjson(subscribers: &[ExportedSubscriber]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut buffer = vec![];
+    for subscriber in subscribers {
+        serde_json::to_writer(&mut buffer, subscriber)?;
+        buffer.push(b'\n');
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_csv, to_ndjson, ExportedMembership, ExportedSubscriber};
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn subscriber() -> ExportedSubscriber {
+        ExportedSubscriber {
+            id: Uuid::nil(),
+            email: "ursula@gmail.com".into(),
+            name: "Le Guin, Ursula".into(),
+            status: "confirmed".into(),
+            subscribed_at: Utc.with_ymd_and_hms(2023, 2, 1, 9, 30, 0).unwrap(),
+            lists: vec![
+                ExportedMembership {
+                    list: "newsletter".into(),
+                    status: "confirmed".into(),
+                },
+                ExportedMembership {
+                    list: "weekly".into(),
+                    status: "unsubscribed".into(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn only_the_first_csv_batch_has_a_header() {
+        let first = String::from_utf8(to_csv(&[subscriber()], true).unwrap()).unwrap();
+        let next = String::from_utf8(to_csv(&[subscriber()], false).unwrap()).unwrap();
+        let row = "00000000-0000-0000-0000-000000000000,ursula@gmail.com,\"Le Guin, Ursula\",\
+            confirmed,2023-02-01T09:30:00+00:00,newsletter:confirmed;weekly:unsubscribed\n";
+        assert_eq!(
+            first,
+            format!("id,email,name,status,subscribed_at,lists\n{}", row)
+        );
+        assert_eq!(next, row);
+    }
+
+    #[test]
+    fn ndjson_has_one_subscriber_per_line() {
+        let ndjson = String::from_utf8(to_ndjson(&[subscriber(), subscriber()]).unwrap()).unwrap();
+        let lines: Vec<_> = ndjson.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let value: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(value["email"], "ursula@gmail.com");
+        assert_eq!(value["lists"][1]["list"], "weekly");
+        assert_eq!(value["lists"][1]["status"], "unsubscribed");
+    }
+}
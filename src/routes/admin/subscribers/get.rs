@@ -0,0 +1,346 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tera::Tera;
+use uuid::Uuid;
+
+use super::STATUSES;
+use crate::{
+    authentication::UserId,
+    routes::get_username,
+    utils::{e400, e404, e500},
+};
+
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize, Debug)]
+pub struct QueryParams {
+    /// Matched against the email address and the name.
+    #[serde(default)]
+    q: String,
+    #[serde(default)]
+    status: String,
+    page: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Get subscribers page",
+    skip(tera, db_pool, user_id, flash_messages)
+)]
+pub async fn subscribers_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    query: web::Query<QueryParams>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData<'a> {
+        messages: Vec<String>,
+        username: String,
+        subscribers: Vec<SubscriberSummary>,
+        statuses: [&'static str; 5],
+        q: &'a str,
+        status: &'a str,
+        page: i64,
+        n_pages: i64,
+        n_subscribers: i64,
+    }
+
+    let q = query.q.trim();
+    let status = query.status.as_str();
+    if !status.is_empty() && !STATUSES.contains(&status) {
+        return Err(e400(format!("Unknown subscriber status: {}", status)));
+    }
+    let page = query.page.unwrap_or(1).max(1);
+    let offset = (page - 1)
+        .checked_mul(PAGE_SIZE)
+        .ok_or_else(|| e400(format!("No such page: {}", page)))?;
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let (subscribers, n_subscribers) = search_subscribers(&db_pool, q, status, offset)
+        .await
+        .context("Failed to search the subscribers")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        subscribers,
+        statuses: STATUSES,
+        q,
+        status,
+        page,
+        n_pages: (n_subscribers + PAGE_SIZE - 1) / PAGE_SIZE,
+        n_subscribers,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/subscribers.j2", &context)
+        .context("Failed to render the subscribers page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// One page of the subscribers matching the filters, newest first, and how many match.
+#[tracing::instrument(skip(db_pool))]
+async fn search_subscribers(
+    db_pool: &PgPool,
+    q: &str,
+    status: &str,
+    offset: i64,
+) -> Result<(Vec<SubscriberSummary>, i64), sqlx::Error> {
+    let pattern = format!("%{}%", escape_like(q));
+    let status = (!status.is_empty()).then_some(status);
+
+    let n_subscribers = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM subscriptions
+        WHERE
+            (email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status = $2)
+        "#,
+        pattern,
+        status,
+    )
+    .fetch_one(db_pool)
+    .await?
+    .count;
+    let subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE
+            (email ILIKE $1 OR name ILIKE $1)
+            AND ($2::text IS NULL OR status = $2)
+        ORDER BY subscribed_at DESC, id
+        LIMIT $3 OFFSET $4
+        "#,
+        pattern,
+        status,
+        PAGE_SIZE,
+        offset,
+    )
+    .fetch_all(db_pool)
+    .await?;
+    Ok((subscribers, n_subscribers))
+}
+
+/// Match `%`, `_` and `\` literally in a `LIKE` pattern.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(serde::Serialize)]
+struct SubscriberDetails {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    attributes: serde_json::Value,
+    locale: String,
+}
+
+#[derive(serde::Serialize)]
+struct Membership {
+    name: String,
+    status: String,
+}
+
+#[derive(serde::Serialize)]
+struct StatusChange {
+    status: String,
+    changed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize)]
+struct Delivery {
+    title: String,
+    delivered_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Get subscriber page",
+    skip(tera, db_pool, user_id, flash_messages)
+)]
+pub async fn subscriber_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    subscriber_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        messages: Vec<String>,
+        username: String,
+        subscriber: SubscriberDetails,
+        memberships: Vec<Membership>,
+        history: Vec<StatusChange>,
+        deliveries: Vec<Delivery>,
+    }
+
+    let subscriber_id = subscriber_id.into_inner();
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let subscriber = get_subscriber_details(&db_pool, subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber")
+        .map_err(e500)?
+        .ok_or_else(|| e404("There is no such subscriber."))?;
+    let memberships = get_memberships(&db_pool, subscriber_id)
+        .await
+        .context("Failed to fetch the list memberships of the subscriber")
+        .map_err(e500)?;
+    let history = get_status_history(&db_pool, subscriber_id)
+        .await
+        .context("Failed to fetch the status history of the subscriber")
+        .map_err(e500)?;
+    let deliveries = get_deliveries(&db_pool, subscriber_id)
+        .await
+        .context("Failed to fetch the issues delivered to the subscriber")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        subscriber,
+        memberships,
+        history,
+        deliveries,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/subscriber.j2", &context)
+        .context("Failed to render the subscriber page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_subscriber_details(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, sqlx::Error> {
+    sqlx::query_as!(
+        SubscriberDetails,
+        r#"
+        SELECT id, email, name, status, subscribed_at, attributes, locale
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(db_pool)
+    .await
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_memberships(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<Membership>, sqlx::Error> {
+    sqlx::query_as!(
+        Membership,
+        r#"
+        SELECT l.name, m.status
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        WHERE m.subscriber_id = $1
+        ORDER BY l.name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_status_history(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<StatusChange>, sqlx::Error> {
+    sqlx::query_as!(
+        StatusChange,
+        r#"
+        SELECT status, changed_at
+        FROM subscription_status_changes
+        WHERE subscriber_id = $1
+        ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_deliveries(
+    db_pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<Delivery>, sqlx::Error> {
+    sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT i.title, d.delivered_at
+        FROM issue_deliveries d
+        JOIN newsletter_issues i ON i.newsletter_issue_id = d.newsletter_issue_id
+        WHERE d.subscriber_id = $1
+        ORDER BY d.delivered_at DESC
+        "#,
+        subscriber_id
+    )
+    .fetch_all(db_pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::escape_like;
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("100%_off\\"), "100\\%\\_off\\\\");
+        assert_eq!(escape_like("ursula"), "ursula");
+    }
+}
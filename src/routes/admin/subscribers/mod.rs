@@ -0,0 +1,16 @@
+mod export;
+mod get;
+mod post;
+
+pub use export::export_subscribers;
+pub use get::{subscriber_page, subscribers_page};
+pub use post::{admin_confirm_subscriber, admin_delete_subscriber, admin_unsubscribe_subscriber};
+
+/// The statuses a subscriber can be in, in the order they are offered as filters.
+const STATUSES: [&str; 5] = [
+    "pending_confirmation",
+    "confirmed",
+    "unsubscribed",
+    "bounced",
+    "complained",
+];
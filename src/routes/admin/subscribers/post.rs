@@ -0,0 +1,122 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    personal_data::delete_subscriber,
+    routes::{confirm_subscriber, unsubscribe_subscriber},
+    utils::{e404, e500, see_other},
+};
+
+/// Confirm on behalf of the subscriber, e.g. when they gave their consent offline.
+#[tracing::instrument(name = "Confirm a subscriber manually", skip(db_pool))]
+pub async fn admin_confirm_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let status = lock_subscriber(&mut transaction, subscriber_id).await?;
+    if status == "confirmed" {
+        FlashMessage::info("The subscriber is already confirmed.").send();
+    } else {
+        confirm_subscriber(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to confirm the subscriber")
+            .map_err(e500)?;
+        // The links in the confirmation emails already sent are now moot.
+        sqlx::query!(
+            "DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND consumed_at IS NULL",
+            subscriber_id
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete the pending subscription tokens")
+        .map_err(e500)?;
+        FlashMessage::info("The subscriber has been confirmed.").send();
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to confirm a subscriber")
+        .map_err(e500)?;
+    Ok(see_other(&format!("/admin/subscribers/{}", subscriber_id)))
+}
+
+#[tracing::instrument(name = "Unsubscribe a subscriber manually", skip(db_pool))]
+pub async fn admin_unsubscribe_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let status = lock_subscriber(&mut transaction, subscriber_id).await?;
+    if status == "unsubscribed" {
+        FlashMessage::info("The subscriber is already unsubscribed.").send();
+    } else {
+        unsubscribe_subscriber(&mut transaction, subscriber_id)
+            .await
+            .context("Failed to unsubscribe the subscriber")
+            .map_err(e500)?;
+        FlashMessage::info("The subscriber has been unsubscribed.").send();
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to unsubscribe a subscriber")
+        .map_err(e500)?;
+    Ok(see_other(&format!("/admin/subscribers/{}", subscriber_id)))
+}
+
+/// Remove the subscriber and everything attached to them.
+#[tracing::instrument(name = "Delete a subscriber", skip(db_pool))]
+pub async fn admin_delete_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    lock_subscriber(&mut transaction, subscriber_id).await?;
+    delete_subscriber(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to delete the subscriber")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to delete a subscriber")
+        .map_err(e500)?;
+    FlashMessage::info("The subscriber has been deleted.").send();
+    Ok(see_other("/admin/subscribers"))
+}
+
+/// The status of the subscriber, locked until the end of the transaction.
+async fn lock_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<String, actix_web::Error> {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to fetch the subscriber")
+    .map_err(e500)?
+    .map(|r| r.status)
+    .ok_or_else(|| e404("There is no such subscriber."))
+}
@@ -0,0 +1,91 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{authentication::UserId, routes::get_username, utils::e500};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct QueryParams {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(serde::Serialize)]
+struct Suppression {
+    suppression_id: Uuid,
+    email: String,
+    reason: String,
+    source: String,
+    created_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(
+    name = "Get suppressions page",
+    skip(tera, db_pool, user_id, flash_messages)
+)]
+pub async fn suppressions_page(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    query: web::Query<QueryParams>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    #[derive(serde::Serialize)]
+    struct BodyData<'a> {
+        messages: Vec<String>,
+        username: String,
+        suppressions: Vec<Suppression>,
+        q: &'a str,
+    }
+
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+    let messages = flash_messages
+        .iter()
+        .map(|m| m.content().to_string())
+        .collect();
+    let q = query.q.trim();
+    let suppressions = get_suppressions(&db_pool, q)
+        .await
+        .context("Failed to fetch the suppressions")
+        .map_err(e500)?;
+
+    let context = tera::Context::from_serialize(BodyData {
+        messages,
+        username,
+        suppressions,
+        q,
+    })
+    .context("Failed to build context")
+    .map_err(e500)?;
+    let body = tera
+        .render("admin/suppressions.j2", &context)
+        .context("Failed to render the suppressions page")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[tracing::instrument(skip(db_pool))]
+async fn get_suppressions(db_pool: &PgPool, q: &str) -> Result<Vec<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT suppression_id, email, reason, source, created_at
+        FROM suppressions
+        WHERE strpos(lower(email), lower($1)) > 0
+            OR strpos(lower(reason), lower($1)) > 0
+        ORDER BY created_at DESC
+        "#,
+        q
+    )
+    .fetch_all(db_pool)
+    .await
+}
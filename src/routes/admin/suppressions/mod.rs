@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::suppressions_page;
+pub use post::{create_suppression, delete_suppression, update_suppression};
@@ -0,0 +1,121 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{CanonicalEmailRules, SubscriberEmail},
+    routes::get_username,
+    utils::{e404, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct NewSuppression {
+    email: String,
+    reason: String,
+}
+
+/// Suppress an address: it will not be emailed anymore, whatever its status.
+#[tracing::instrument(name = "Suppress an address", skip(form, db_pool, rules, user_id))]
+pub async fn create_suppression(
+    form: web::Form<NewSuppression>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.email.trim().to_owned()) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+    let reason = form.reason.trim();
+    if reason.is_empty() {
+        FlashMessage::error("Give a reason to suppress the address.").send();
+        return Ok(see_other("/admin/suppressions"));
+    }
+    let username = get_username(*user_id.into_inner(), &db_pool)
+        .await
+        .map_err(e500)?;
+
+    let n_inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (suppression_id, email, canonical_email, reason, source)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        email.as_ref(),
+        email.canonical(&rules),
+        reason,
+        format!("admin: {}", username),
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to store the suppression")
+    .map_err(e500)?
+    .rows_affected();
+
+    if n_inserted_rows == 0 {
+        FlashMessage::error(format!("{} is already suppressed.", email)).send();
+    } else {
+        FlashMessage::info(format!("{} has been suppressed.", email)).send();
+    }
+    Ok(see_other("/admin/suppressions"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionUpdate {
+    reason: String,
+}
+
+#[tracing::instrument(name = "Update a suppression", skip(form, db_pool))]
+pub async fn update_suppression(
+    suppression_id: web::Path<Uuid>,
+    form: web::Form<SuppressionUpdate>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let reason = form.reason.trim();
+    if reason.is_empty() {
+        FlashMessage::error("The reason cannot be empty.").send();
+        return Ok(see_other("/admin/suppressions"));
+    }
+    let n_updated_rows = sqlx::query!(
+        "UPDATE suppressions SET reason = $2 WHERE suppression_id = $1",
+        suppression_id.into_inner(),
+        reason
+    )
+    .execute(db_pool.get_ref())
+    .await
+    .context("Failed to update the suppression")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated_rows == 0 {
+        return Err(e404("There is no such suppression."));
+    }
+    FlashMessage::info("The suppression has been updated.").send();
+    Ok(see_other("/admin/suppressions"))
+}
+
+/// Lift a suppression: the address is emailed again if it is subscribed.
+#[tracing::instrument(name = "Delete a suppression", skip(db_pool))]
+pub async fn delete_suppression(
+    suppression_id: web::Path<Uuid>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = sqlx::query!(
+        "DELETE FROM suppressions WHERE suppression_id = $1 RETURNING email",
+        suppression_id.into_inner()
+    )
+    .fetch_optional(db_pool.get_ref())
+    .await
+    .context("Failed to delete the suppression")
+    .map_err(e500)?
+    .ok_or_else(|| e404("There is no such suppression."))?
+    .email;
+    FlashMessage::info(format!("{} is no longer suppressed.", email)).send();
+    Ok(see_other("/admin/suppressions"))
+}
@@ -0,0 +1,110 @@
+use crate::{
+    domain::FieldError,
+    routes::{error_chain_fmt, SubscriptionsConfirmError},
+};
+use actix_web::{error::JsonPayloadError, HttpRequest, HttpResponse, ResponseError};
+use reqwest::StatusCode;
+use std::fmt::Debug;
+
+/// Errors returned by the JSON API.
+///
+/// They are rendered as `{"errors": [{"code": ..., "field": ..., "message": ...}]}`,
+/// with `field` set for validation errors only.
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("The request body could not be parsed: {0}")]
+    MalformedRequest(String),
+    #[error("One or more fields are invalid.")]
+    ValidationError(Vec<FieldError>),
+    #[error("The subscription token does not exist.")]
+    TokenNotFound,
+    #[error("The subscription token has expired.")]
+    TokenExpired,
+    #[error("The subscription token has already been used.")]
+    TokenAlreadyUsed,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<SubscriptionsConfirmError> for ApiError {
+    fn from(e: SubscriptionsConfirmError) -> Self {
+        match e {
+            SubscriptionsConfirmError::NotFoundError(_) => ApiError::TokenNotFound,
+            SubscriptionsConfirmError::TokenExpired => ApiError::TokenExpired,
+            SubscriptionsConfirmError::TokenAlreadyUsed => ApiError::TokenAlreadyUsed,
+            SubscriptionsConfirmError::UnexpectedError(e) => ApiError::UnexpectedError(e),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ErrorObject<'a> {
+    code: &'a str,
+    field: Option<&'a str>,
+    message: String,
+}
+
+impl ApiError {
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::MalformedRequest(_) => "malformed_request",
+            ApiError::ValidationError(_) => "invalid_field",
+            ApiError::TokenNotFound => "token_not_found",
+            ApiError::TokenExpired => "token_expired",
+            ApiError::TokenAlreadyUsed => "token_already_used",
+            ApiError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    fn errors(&self) -> Vec<ErrorObject<'_>> {
+        match self {
+            ApiError::ValidationError(field_errors) => field_errors
+                .iter()
+                .map(|e| ErrorObject {
+                    code: self.code(),
+                    field: Some(e.field),
+                    message: e.message.clone(),
+                })
+                .collect(),
+            // Do not leak the details of unexpected failures to API clients
+            ApiError::UnexpectedError(_) => vec![ErrorObject {
+                code: self.code(),
+                field: None,
+                message: "An unexpected error occurred.".into(),
+            }],
+            e => vec![ErrorObject {
+                code: self.code(),
+                field: None,
+                message: e.to_string(),
+            }],
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MalformedRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::ValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TokenNotFound => StatusCode::NOT_FOUND,
+            ApiError::TokenExpired => StatusCode::GONE,
+            ApiError::TokenAlreadyUsed => StatusCode::CONFLICT,
+            ApiError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({ "errors": self.errors() }))
+    }
+}
+
+/// Report unparseable JSON bodies with the same shape as the other API errors.
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::MalformedRequest(err.to_string()).into()
+}
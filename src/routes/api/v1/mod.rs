@@ -0,0 +1,9 @@
+mod error;
+mod personal_data;
+mod subscriptions;
+mod subscriptions_confirm;
+
+pub use error::{json_error_handler, ApiError};
+pub use personal_data::{api_erase_personal_data, api_get_personal_data};
+pub use subscriptions::api_subscribe;
+pub use subscriptions_confirm::api_confirm;
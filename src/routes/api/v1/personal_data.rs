@@ -0,0 +1,54 @@
+use super::ApiError;
+use crate::{
+    domain::{CanonicalEmailRules, FieldError},
+    personal_data::{collect_personal_data, erase_personal_data},
+};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+/// The address a data-subject request is about.
+#[derive(serde::Deserialize)]
+pub struct DataSubjectRequest {
+    #[serde(default)]
+    email: String,
+}
+
+impl DataSubjectRequest {
+    fn email(&self) -> Result<&str, ApiError> {
+        match self.email.trim() {
+            "" => Err(ApiError::ValidationError(vec![FieldError {
+                field: "email",
+                message: "The email is missing.".into(),
+            }])),
+            email => Ok(email),
+        }
+    }
+}
+
+/// Everything held about an address. Admins only.
+#[tracing::instrument(
+    name = "Export personal data through the API",
+    skip(query, db_pool, rules)
+)]
+pub async fn api_get_personal_data(
+    query: web::Query<DataSubjectRequest>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+) -> Result<HttpResponse, ApiError> {
+    let personal_data = collect_personal_data(&db_pool, query.email()?, &rules).await?;
+    Ok(HttpResponse::Ok().json(personal_data))
+}
+
+/// Erase an address, returning what was removed. Admins only.
+#[tracing::instrument(
+    name = "Erase personal data through the API",
+    skip(body, db_pool, rules)
+)]
+pub async fn api_erase_personal_data(
+    body: web::Json<DataSubjectRequest>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+) -> Result<HttpResponse, ApiError> {
+    let erasure = erase_personal_data(&db_pool, body.email()?, &rules).await?;
+    Ok(HttpResponse::Ok().json(erasure))
+}
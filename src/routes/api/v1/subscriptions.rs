@@ -0,0 +1,83 @@
+use super::ApiError;
+use crate::{
+    domain::{CanonicalEmailRules, FieldError, NewSubscriber},
+    email_verification::EmailVerifier,
+    mailing_lists::{resolve_mailing_lists, ListSelectionError},
+    routes::{process_subscription, requested_locale},
+    startup::ApplicationBaseUrl,
+};
+use actix_web::{http::header::AcceptLanguage, web, HttpResponse};
+use sqlx::PgPool;
+use tera::Tera;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriptionRequest {
+    email: String,
+    name: String,
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Free-form details, available as merge tags in newsletter issues.
+    attributes: Option<serde_json::Value>,
+    /// A BCP 47 tag; the `Accept-Language` header is used otherwise.
+    locale: Option<String>,
+}
+
+impl TryFrom<SubscriptionRequest> for NewSubscriber {
+    type Error = Vec<FieldError>;
+
+    fn try_from(value: SubscriptionRequest) -> Result<Self, Self::Error> {
+        NewSubscriber::parse_with_details(value.email, value.name, value.attributes, value.locale)
+    }
+}
+
+#[tracing::instrument(
+    name = "Adding a new subscriber through the API",
+    skip(body, db_pool, rules, base_url, email_verifier, tera, accept_language),
+    fields(
+        subscriber_email = %body.email,
+        subscriber_name = %body.name,
+    )
+)]
+pub async fn api_subscribe(
+    body: web::Json<SubscriptionRequest>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    email_verifier: web::Data<EmailVerifier>,
+    tera: web::Data<Tera>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
+) -> Result<HttpResponse, ApiError> {
+    let list_ids = resolve_mailing_lists(&db_pool, &body.lists)
+        .await
+        .map_err(|e| match e {
+            ListSelectionError::UnknownLists(_) => ApiError::ValidationError(vec![FieldError {
+                field: "lists",
+                message: e.to_string(),
+            }]),
+            e => ApiError::UnexpectedError(e.into()),
+        })?;
+    let mut body = body.into_inner();
+    body.locale = requested_locale(body.locale, accept_language.as_deref());
+    let new_subscriber: NewSubscriber = body.try_into().map_err(ApiError::ValidationError)?;
+    email_verifier
+        .verify(&new_subscriber.email)
+        .await
+        .map_err(|e| {
+            ApiError::ValidationError(vec![FieldError {
+                field: "email",
+                message: e.to_string(),
+            }])
+        })?;
+    process_subscription(
+        &db_pool,
+        &rules,
+        &tera,
+        &new_subscriber,
+        &list_ids,
+        &base_url.0,
+    )
+    .await?;
+
+    // Whatever the state of the address, the confirmation happens out of band.
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "status": "accepted" })))
+}
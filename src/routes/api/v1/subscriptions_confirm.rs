@@ -0,0 +1,37 @@
+use super::ApiError;
+use crate::{
+    routes::try_confirm,
+    startup::{ApplicationBaseUrl, HmacSecret, SubscriptionTokenTtl},
+};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use tera::Tera;
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmationRequest {
+    subscription_token: String,
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber through the API",
+    skip(body, db_pool, tera, base_url, hmac_secret, token_ttl)
+)]
+pub async fn api_confirm(
+    body: web::Json<ConfirmationRequest>,
+    db_pool: web::Data<PgPool>,
+    tera: web::Data<Tera>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+) -> Result<HttpResponse, ApiError> {
+    try_confirm(
+        &db_pool,
+        &tera,
+        &base_url.0,
+        &hmac_secret.0,
+        &body.subscription_token,
+        token_ttl.0,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "confirmed" })))
+}
@@ -1,10 +1,17 @@
 use std::fmt::Debug;
 
-use actix_web::{http::header::ContentType, web, HttpResponse, ResponseError};
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
+use sqlx::PgPool;
 use tera::Tera;
 
-use super::error_chain_fmt;
+use super::{error_chain_fmt, external_referrer};
+use crate::{
+    bot_protection::BotProtection,
+    domain::SignupSource,
+    mailing_lists::{get_mailing_lists, MailingList},
+    startup::ApplicationBaseUrl,
+};
 
 #[derive(thiserror::Error)]
 pub enum HomeError {
@@ -26,8 +33,38 @@ impl ResponseError for HomeError {
     }
 }
 
-pub async fn home(tera: web::Data<Tera>) -> Result<HttpResponse, HomeError> {
-    let context = tera::Context::new();
+/// Campaign links land here: their attribution is carried over to the
+/// subscription form.
+pub async fn home(
+    tera: web::Data<Tera>,
+    db_pool: web::Data<PgPool>,
+    bot_protection: web::Data<BotProtection>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, HomeError> {
+    #[derive(serde::Serialize)]
+    struct BodyData {
+        lists: Vec<MailingList>,
+        form_token: String,
+        pow_difficulty: u8,
+        signup_source: SignupSource,
+    }
+
+    let lists = get_mailing_lists(&db_pool)
+        .await
+        .context("Failed to fetch the mailing lists")?;
+    let context = tera::Context::from_serialize(BodyData {
+        lists,
+        form_token: bot_protection.issue_form_token(),
+        pow_difficulty: bot_protection.proof_of_work_difficulty(),
+        signup_source: SignupSource::from_query(request.query_string())
+            .normalize()
+            .or(SignupSource {
+                referrer: external_referrer(&request, &base_url.0),
+                ..Default::default()
+            }),
+    })
+    .context("Failed to serialize context")?;
     let body = tera
         .render("index.j2", &context)
         .context("Failed to render html body")?;
@@ -1,13 +1,21 @@
 mod admin;
+mod api;
 mod health_check;
 mod home;
 mod login;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_resend_confirmation;
+mod subscriptions_unsubscribe;
+mod webhooks;
 
 pub use admin::*;
+pub use api::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_resend_confirmation::*;
+pub use subscriptions_unsubscribe::*;
+pub use webhooks::*;
@@ -1,81 +1,284 @@
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
-    email_client::EmailClient,
+    bot_protection::{BotCheck, BotCheckError, BotProtection},
+    domain::{CanonicalEmailRules, Locale, NewSubscriber, SignupSource, SubscriberEmail},
+    email_verification::{EmailVerifier, UndeliverableEmail},
+    localization::{negotiate_locale, render_localized},
+    mailing_lists::{resolve_mailing_lists, ListSelectionError},
+    personal_data::email_hash,
+    rate_limit::RateLimiter,
     startup::ApplicationBaseUrl,
 };
-use actix_web::{web, HttpResponse, ResponseError};
+use actix_web::{
+    http::header::{AcceptLanguage, REFERER},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_lab::extract::UrlEncodedForm;
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::StatusCode;
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{types::Json, PgPool, Postgres, Transaction};
 use std::fmt::{Debug, Display};
+use tera::Tera;
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    /// Slugs of the lists to join: the form sends one `lists` pair per checked box.
+    #[serde(default)]
+    lists: Vec<String>,
+    /// Picked by the reader; their browser's languages are used otherwise.
+    locale: Option<String>,
+    /// Set by the page hosting the form, such as a `source` naming the embed.
+    #[serde(flatten)]
+    signup_source: SignupSource,
+    /// Honeypot: hidden to humans, left empty by them.
+    #[serde(default)]
+    website: String,
+    form_token: Option<String>,
+    pow_nonce: Option<String>,
+}
+
+impl FormData {
+    fn bot_check(&self) -> BotCheck<'_> {
+        BotCheck {
+            honeypot: &self.website,
+            email: &self.email,
+            form_token: self.form_token.as_deref(),
+            pow_nonce: self.pow_nonce.as_deref(),
+        }
+    }
 }
 
 impl TryFrom<FormData> for NewSubscriber {
     type Error = String;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(NewSubscriber { email, name })
+        let mut new_subscriber =
+            NewSubscriber::parse_with_details(value.email, value.name, None, value.locale)
+                .map_err(|errors| {
+                    errors
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })?;
+        new_subscriber.signup_source = value.signup_source;
+        Ok(new_subscriber)
+    }
+}
+
+/// The locale asked for explicitly, else the best match for the browser's languages.
+pub fn requested_locale(
+    explicit: Option<String>,
+    accept_language: Option<&AcceptLanguage>,
+) -> Option<String> {
+    explicit
+        .filter(|locale| !locale.trim().is_empty())
+        .or_else(|| {
+            accept_language
+                .and_then(negotiate_locale)
+                .map(|locale| locale.to_string())
+        })
+}
+
+/// The page the reader came from, unless it is one of ours:
+/// moving around the site says nothing about how they found it.
+pub fn external_referrer(request: &HttpRequest, base_url: &str) -> Option<String> {
+    let referrer = request.headers().get(REFERER)?.to_str().ok()?;
+    let host = |url: &str| reqwest::Url::parse(url).ok()?.host_str().map(str::to_owned);
+    match (host(referrer), host(base_url)) {
+        (Some(referrer_host), Some(own_host)) if referrer_host != own_host => {
+            Some(referrer.to_owned())
+        }
+        _ => None,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, db_pool, email_client, base_url),
+    skip(
+        form,
+        request,
+        db_pool,
+        rules,
+        base_url,
+        bot_protection,
+        rate_limiter,
+        email_verifier,
+        tera,
+        accept_language
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name,
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    form: UrlEncodedForm<FormData>,
+    request: HttpRequest,
     db_pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
+    rules: web::Data<CanonicalEmailRules>,
     base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtection>,
+    rate_limiter: web::Data<RateLimiter>,
+    email_verifier: web::Data<EmailVerifier>,
+    tera: web::Data<Tera>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    match bot_protection.check(&form.bot_check()) {
+        Ok(()) => {}
+        Err(BotCheckError::HoneypotFilled) => {
+            // Let the bot believe it succeeded, so it has no reason to adapt.
+            tracing::warn!("Dropped a subscription with a filled-in honeypot");
+            return Ok(HttpResponse::Ok().finish());
+        }
+        Err(e) => return Err(SubscribeError::SuspectedBot(e)),
+    }
+    let list_ids = resolve_mailing_lists(&db_pool, &form.lists).await?;
+    let mut form = form.into_inner();
+    form.locale = requested_locale(form.locale, accept_language.as_deref());
+    // Embeds put the attribution in the form action's query string,
+    // the home page carries it over in hidden fields.
+    form.signup_source = form
+        .signup_source
+        .normalize()
+        .or(SignupSource::from_query(request.query_string()).normalize())
+        .or(SignupSource {
+            referrer: external_referrer(&request, &base_url.0),
+            ..Default::default()
+        });
+    let form_token = form.form_token.clone();
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
+    email_verifier.verify(&new_subscriber.email).await?;
+    // Only valid submissions use up their form, so that typos can be fixed.
+    bot_protection
+        .claim_form_token(form_token.as_deref(), &rate_limiter)
+        .await
+        .map_err(SubscribeError::SuspectedBot)?;
+    process_subscription(
+        &db_pool,
+        &rules,
+        &tera,
+        &new_subscriber,
+        &list_ids,
+        &base_url.0,
+    )
+    .await?;
 
+    // The response is the same whatever state the address was in,
+    // to avoid disclosing who is subscribed to the newsletter.
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Store the subscriber and enqueue their confirmation email in a single transaction.
+pub async fn process_subscription(
+    db_pool: &PgPool,
+    rules: &CanonicalEmailRules,
+    tera: &Tera,
+    new_subscriber: &NewSubscriber,
+    list_ids: &[Uuid],
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
     let mut transaction = db_pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
-        .await
-        .context("Failed to insert new subscriber in the database")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscription_token =
+        register_subscriber(&mut transaction, rules, new_subscriber, list_ids).await?;
+    if let Some(subscription_token) = subscription_token {
+        // The email is delivered by the background worker: a slow or unavailable
+        // email provider cannot fail the request once the subscriber is stored.
+        enqueue_confirmation_email(
+            &mut transaction,
+            tera,
+            &new_subscriber.email,
+            &new_subscriber.locale,
+            base_url,
+            &subscription_token,
+        )
         .await
-        .context("Failed to store the confirmation token for a new subscriber")?;
+        .context("Failed to enqueue a confirmation email")?;
+    }
     transaction
         .commit()
         .await
         .context("Failed to commit a SQL transaction to store a new subscriber")?;
+    Ok(())
+}
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email")?;
+/// Store the subscriber, or bring an existing one back into the double opt-in flow.
+///
+/// Returns the token to send in a confirmation email, if one should be sent:
+/// - new or `pending_confirmation` addresses get (again) a confirmation email;
+/// - `confirmed` addresses only get one when they join lists they are not confirmed on;
+/// - `unsubscribed` addresses go back to `pending_confirmation`;
+/// - `bounced` and `complained` addresses are left alone: the provider would
+///   not deliver to them anyway, an admin has to reinstate them.
+async fn register_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    rules: &CanonicalEmailRules,
+    new_subscriber: &NewSubscriber,
+    list_ids: &[Uuid],
+) -> Result<Option<String>, anyhow::Error> {
+    if let Some(subscriber_id) = insert_subscriber(transaction, rules, new_subscriber)
+        .await
+        .context("Failed to insert new subscriber in the database")?
+    {
+        join_lists(transaction, subscriber_id, list_ids)
+            .await
+            .context("Failed to add a new subscriber to the mailing lists")?;
+        let subscription_token = generate_subscription_token();
+        store_token(transaction, subscriber_id, &subscription_token)
+            .await
+            .context("Failed to store the confirmation token for a new subscriber")?;
+        return Ok(Some(subscription_token));
+    }
 
-    Ok(HttpResponse::Ok().finish())
+    let ExistingSubscriber {
+        id: subscriber_id,
+        status,
+        ..
+    } = get_subscriber_by_email(transaction, rules, &new_subscriber.email)
+        .await
+        .context("Failed to retrieve an existing subscriber")?
+        .context("A subscriber conflicted on insert, but it could not be found")?;
+    let has_pending_lists = join_lists(transaction, subscriber_id, list_ids)
+        .await
+        .context("Failed to add an existing subscriber to the mailing lists")?;
+    match status.as_str() {
+        "confirmed" if !has_pending_lists => Ok(None),
+        "confirmed" | "pending_confirmation" => {
+            let subscription_token = rotate_token(transaction, subscriber_id)
+                .await
+                .context("Failed to rotate the confirmation token of a pending subscriber")?;
+            Ok(Some(subscription_token))
+        }
+        "unsubscribed" => {
+            reopen_subscription(transaction, subscriber_id, new_subscriber)
+                .await
+                .context("Failed to re-open the subscription of a former subscriber")?;
+            let subscription_token = rotate_token(transaction, subscriber_id)
+                .await
+                .context("Failed to store the confirmation token for a returning subscriber")?;
+            Ok(Some(subscription_token))
+        }
+        "bounced" | "complained" => Ok(None),
+        status => anyhow::bail!("Unexpected subscription status: {}", status),
+    }
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    #[error("The form submission was rejected. Please reload the page and try again.")]
+    SuspectedBot(#[source] BotCheckError),
+    #[error(transparent)]
+    UndeliverableEmail(#[from] UndeliverableEmail),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -86,10 +289,21 @@ impl Debug for SubscribeError {
     }
 }
 
+impl From<ListSelectionError> for SubscribeError {
+    fn from(e: ListSelectionError) -> Self {
+        match e {
+            ListSelectionError::UnknownLists(_) => SubscribeError::ValidationError(e.to_string()),
+            e => SubscribeError::UnexpectedError(e.into()),
+        }
+    }
+}
+
 impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
-            SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::ValidationError(_)
+            | SubscribeError::SuspectedBot(_)
+            | SubscribeError::UndeliverableEmail(_) => StatusCode::BAD_REQUEST,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -153,60 +367,266 @@ pub async fn store_token(
     Ok(())
 }
 
+/// The email is written in the recipient's language.
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber"
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    name = "Enqueue a confirmation email to a new subscriber"
+    skip(transaction, tera, recipient, base_url, subscription_token)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    tera: &Tera,
+    recipient: &SubscriberEmail,
+    locale: &Locale,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), anyhow::Error> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let html_body = format!(
-        "
-            Welcome to our newsletter
-            <br />
-            click <a href=\"{}\">here</a> to confirm your subscription.
-        ",
-        confirmation_link
-    );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
+    let mut context = tera::Context::new();
+    context.insert("confirmation_link", &confirmation_link);
+    enqueue_localized_email(
+        transaction,
+        tera,
+        recipient.as_ref(),
+        locale,
+        "emails/confirmation",
+        &context,
+    )
+    .await
+}
 
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+/// Render the `.subject.j2`, `.txt.j2` and `.html.j2` templates of `email` in
+/// the recipient's language, and store the result in the outbox: the background
+/// worker delivers it once the transaction is committed.
+#[tracing::instrument(skip(transaction, tera, recipient, context))]
+pub async fn enqueue_localized_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    tera: &Tera,
+    recipient: &str,
+    locale: &Locale,
+    email: &str,
+    context: &tera::Context,
+) -> Result<(), anyhow::Error> {
+    let render = |part| {
+        let name = format!("{}.{}.j2", email, part);
+        render_localized(tera, &name, locale, context)
+            .with_context(|| format!("Failed to render {}", name))
+    };
+    let subject = render("subject")?;
+    let plain_body = render("txt")?;
+    let html_body = render("html")?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            email_id,
+            recipient,
+            subject,
+            text_content,
+            html_content
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        recipient,
+        subject.trim(),
+        plain_body,
+        html_body,
+    )
+    .execute(transaction)
+    .await
+    .with_context(|| format!("Failed to store {} in the outbox", email))?;
+    Ok(())
 }
 
+/// Returns `None` if a subscriber with the same canonical address already exists.
+///
+/// Returning subscribers keep the attribution of their first signup.
 #[tracing::instrument(
     name = "Saving a new subscriber details in the database",
-    skip(new_subscriber, transaction)
+    skip(new_subscriber, transaction, rules)
 )]
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
+    rules: &CanonicalEmailRules,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
 
-    sqlx::query!(
-        "INSERT INTO subscriptions (id, email, name, subscribed_at, STATUS)
-        VALUES ($1, $2, $3, $4, 'pending_confirmation')",
+    let n_inserted_rows = sqlx::query!(
+        "INSERT INTO subscriptions (
+            id, email, canonical_email, name, subscribed_at, STATUS, attributes, locale,
+            signup_source, utm_source, utm_medium, utm_campaign, utm_term, utm_content, referrer
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, 'pending_confirmation', $6, $7,
+            $8, $9, $10, $11, $12, $13, $14
+        )
+        ON CONFLICT DO NOTHING",
         subscriber_id,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.canonical(rules),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        Json(new_subscriber.attributes.as_ref()) as _,
+        new_subscriber.locale.as_ref(),
+        new_subscriber.signup_source.source,
+        new_subscriber.signup_source.utm_source,
+        new_subscriber.signup_source.utm_medium,
+        new_subscriber.signup_source.utm_campaign,
+        new_subscriber.signup_source.utm_term,
+        new_subscriber.signup_source.utm_content,
+        new_subscriber.signup_source.referrer,
     )
-    .execute(transaction)
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+    if n_inserted_rows == 0 {
+        return Ok(None);
+    }
+    // Subscribing again is a fresh consent, which lifts the erasure.
+    sqlx::query!(
+        "DELETE FROM erased_subscribers WHERE email_hash = $1",
+        email_hash(new_subscriber.email.as_ref(), rules)
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    Ok(Some(subscriber_id))
+}
+
+pub struct ExistingSubscriber {
+    pub id: Uuid,
+    /// As first stored.
+    pub email: String,
+    pub status: String,
+    pub locale: Locale,
+}
+
+/// The subscriber under any spelling of the address.
+#[tracing::instrument(name = "Get subscriber by email", skip(transaction, rules, email))]
+pub async fn get_subscriber_by_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    rules: &CanonicalEmailRules,
+    email: &SubscriberEmail,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        SELECT id, email, status, locale FROM subscriptions
+        WHERE canonical_email = $1
+        FOR UPDATE
+        "#,
+        email.canonical(rules),
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result.map(|r| ExistingSubscriber {
+        id: r.id,
+        email: r.email,
+        status: r.status,
+        // Stored locales were valid when stored.
+        locale: Locale::parse(&r.locale).unwrap_or_default(),
+    }))
+}
+
+#[tracing::instrument(name = "Get subscriber by id", skip(transaction))]
+pub async fn get_subscriber_by_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<ExistingSubscriber>, sqlx::Error> {
+    let result = sqlx::query!(
+        "SELECT id, email, status, locale FROM subscriptions WHERE id = $1 FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result.map(|r| ExistingSubscriber {
+        id: r.id,
+        email: r.email,
+        status: r.status,
+        // Stored locales were valid when stored.
+        locale: Locale::parse(&r.locale).unwrap_or_default(),
+    }))
+}
+
+/// Add the subscriber to the lists, as `pending_confirmation` unless they are
+/// already members; lists they had left are joined again.
+///
+/// Returns whether any of these memberships still awaits confirmation.
+#[tracing::instrument(name = "Join mailing lists", skip(transaction))]
+async fn join_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<bool, sqlx::Error> {
+    let statuses = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status)
+        SELECT $1, list_id, 'pending_confirmation'
+        FROM UNNEST($2::uuid[]) AS list_id
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE
+        SET status = CASE
+            WHEN list_memberships.status = 'unsubscribed' THEN 'pending_confirmation'
+            ELSE list_memberships.status
+        END
+        RETURNING status
+        "#,
+        subscriber_id,
+        list_ids,
+    )
+    .fetch_all(transaction)
     .await?;
+    Ok(statuses.iter().any(|r| r.status == "pending_confirmation"))
+}
+
+/// Replace the subscriber's unused tokens with a fresh one, so that only the
+/// link in the most recent confirmation email works.
+#[tracing::instrument(name = "Rotate subscription token", skip(transaction))]
+pub async fn rotate_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<String, anyhow::Error> {
+    sqlx::query!(
+        "DELETE FROM subscription_tokens WHERE subscriber_id = $1 AND consumed_at IS NULL",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the previous subscription tokens")?;
+    let subscription_token = generate_subscription_token();
+    store_token(transaction, subscriber_id, &subscription_token).await?;
+    Ok(subscription_token)
+}
 
-    Ok(subscriber_id)
+#[tracing::instrument(
+    name = "Re-open the subscription of a former subscriber",
+    skip(transaction, new_subscriber)
+)]
+async fn reopen_subscription(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET
+            status = 'pending_confirmation',
+            name = $2,
+            attributes = attributes || $3,
+            locale = $4
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_subscriber.name.as_ref(),
+        Json(new_subscriber.attributes.as_ref()) as _,
+        new_subscriber.locale.as_ref(),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
 fn generate_subscription_token() -> String {
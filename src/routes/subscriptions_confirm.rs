@@ -1,39 +1,215 @@
-use super::error_chain_fmt;
-use actix_web::{web, HttpResponse, ResponseError};
+use super::{enqueue_localized_email, error_chain_fmt, get_subscriber_by_id};
+use crate::{
+    domain::Locale,
+    localization::{page_locale, render_localized},
+    startup::{ApplicationBaseUrl, HmacSecret, SubscriptionTokenTtl},
+    unsubscribe::unsubscribe_link,
+};
+use actix_web::{
+    http::header::{AcceptLanguage, ContentType},
+    web, HttpResponse, ResponseError,
+};
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
-use sqlx::PgPool;
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
 use std::fmt::Debug;
+use tera::Tera;
 use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, db_pool))]
-pub async fn confirm(
+/// Ask for an explicit confirmation: link scanners pre-fetch URLs with GET,
+/// so following the link must not confirm anybody on its own.
+#[tracing::instrument(
+    name = "Show the subscription confirmation form",
+    skip(parameters, db_pool, tera, token_ttl, accept_language)
+)]
+pub async fn confirm_form(
     db_pool: web::Data<PgPool>,
     parameters: web::Query<Parameters>,
+    tera: web::Data<Tera>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
 ) -> Result<HttpResponse, SubscriptionsConfirmError> {
-    let subscriber_id = get_subscriber_id_from_token(&db_pool, &parameters.subscription_token)
+    let (token, subscriber_locale) = lookup_token(&db_pool, &parameters.subscription_token)
         .await
         .context("Failed to get a subscriber id from a token")?
-        .ok_or_else(|| {
-            SubscriptionsConfirmError::NotFoundError("subscriber id not found".into())
-        })?;
+        .unzip();
+    let locale = page_locale(subscriber_locale, accept_language.as_deref());
+    if let Err(e) = check_token(token.as_ref(), token_ttl.0) {
+        return render_error_page(&tera, &locale, e);
+    }
+    render_page(
+        &tera,
+        "subscriptions/confirm.j2",
+        &locale,
+        tera::Context::from_serialize(&parameters.0).context("Failed to serialize context")?,
+    )
+}
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(form, db_pool, tera, base_url, hmac_secret, token_ttl, accept_language)
+)]
+pub async fn confirm(
+    db_pool: web::Data<PgPool>,
+    form: web::Form<Parameters>,
+    tera: web::Data<Tera>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    token_ttl: web::Data<SubscriptionTokenTtl>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
+) -> Result<HttpResponse, SubscriptionsConfirmError> {
+    let result = try_confirm(
+        &db_pool,
+        &tera,
+        &base_url.0,
+        &hmac_secret.0,
+        &form.subscription_token,
+        token_ttl.0,
+    )
+    .await;
+    let subscriber_locale = lookup_token(&db_pool, &form.subscription_token)
+        .await
+        .context("Failed to get a subscriber id from a token")?
+        .map(|(_, locale)| locale);
+    let locale = page_locale(subscriber_locale, accept_language.as_deref());
+    match result {
+        Ok(()) => render_page(
+            &tera,
+            "subscriptions/confirmed.j2",
+            &locale,
+            tera::Context::new(),
+        ),
+        Err(e) => render_error_page(&tera, &locale, e),
+    }
+}
 
-    confirm_subscriber(&db_pool, subscriber_id)
+/// The first confirmation of a subscriber is followed by a welcome email.
+pub async fn try_confirm(
+    db_pool: &PgPool,
+    tera: &Tera,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    subscription_token: &str,
+    token_ttl: chrono::Duration,
+) -> Result<(), SubscriptionsConfirmError> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let token = get_token(&mut transaction, subscription_token)
+        .await
+        .context("Failed to get a subscriber id from a token")?;
+    let subscriber_id = check_token(token.as_ref(), token_ttl)?;
+    let subscriber = get_subscriber_by_id(&mut transaction, subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber")?
+        .context("A subscription token belongs to no subscriber")?;
+
+    consume_token(&mut transaction, subscription_token)
+        .await
+        .context("Failed to mark the subscription token as used")?;
+    confirm_subscriber(&mut transaction, subscriber_id)
         .await
         .context("Failed to confirm subscriber")?;
+    // Confirmed subscribers joining more lists are not welcomed again.
+    if subscriber.status == "pending_confirmation" {
+        let mut context = tera::Context::new();
+        context.insert(
+            "unsubscribe_link",
+            &unsubscribe_link(base_url, hmac_secret, subscriber_id),
+        );
+        enqueue_localized_email(
+            &mut transaction,
+            tera,
+            &subscriber.email,
+            &subscriber.locale,
+            "emails/welcome",
+            &context,
+        )
+        .await
+        .context("Failed to enqueue a welcome email")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to confirm a subscriber")?;
+
+    Ok(())
+}
+
+/// Returns the id of the subscriber the token confirms, if it can still be used.
+fn check_token(
+    token: Option<&SubscriptionToken>,
+    token_ttl: chrono::Duration,
+) -> Result<Uuid, SubscriptionsConfirmError> {
+    let token = token.ok_or_else(|| {
+        SubscriptionsConfirmError::NotFoundError("This confirmation link is not valid.".into())
+    })?;
+    if token.consumed_at.is_some() {
+        return Err(SubscriptionsConfirmError::TokenAlreadyUsed);
+    }
+    if token.created_at + token_ttl < Utc::now() {
+        return Err(SubscriptionsConfirmError::TokenExpired);
+    }
+    Ok(token.subscriber_id)
+}
 
-    Ok(HttpResponse::Ok().finish())
+fn render_page(
+    tera: &Tera,
+    template: &str,
+    locale: &Locale,
+    context: tera::Context,
+) -> Result<HttpResponse, SubscriptionsConfirmError> {
+    let body = render_localized(tera, template, locale, &context)
+        .context("Failed to render the confirmation page")?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Explain what went wrong to the reader; unexpected errors are still bubbled up.
+fn render_error_page(
+    tera: &Tera,
+    locale: &Locale,
+    e: SubscriptionsConfirmError,
+) -> Result<HttpResponse, SubscriptionsConfirmError> {
+    if let SubscriptionsConfirmError::UnexpectedError(_) = e {
+        return Err(e);
+    }
+    tracing::warn!(error.message = %e, "Refused a subscription token");
+
+    // The template words the reason in the reader's language.
+    let reason = match e {
+        SubscriptionsConfirmError::TokenExpired => "expired",
+        SubscriptionsConfirmError::TokenAlreadyUsed => "used",
+        _ => "not_found",
+    };
+    let mut context = tera::Context::new();
+    context.insert("reason", reason);
+    let body = render_localized(tera, "subscriptions/confirm_error.j2", locale, &context)
+        .context("Failed to render the confirmation error page")?;
+
+    Ok(HttpResponse::build(e.status_code())
+        .content_type(ContentType::html())
+        .body(body))
 }
 
 #[derive(thiserror::Error)]
 pub enum SubscriptionsConfirmError {
     #[error("{0}")]
     NotFoundError(String),
+    #[error("This confirmation link has expired.")]
+    TokenExpired,
+    #[error("This confirmation link has already been used.")]
+    TokenAlreadyUsed,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -48,35 +224,106 @@ impl ResponseError for SubscriptionsConfirmError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             SubscriptionsConfirmError::NotFoundError(_) => StatusCode::NOT_FOUND,
+            SubscriptionsConfirmError::TokenExpired => StatusCode::GONE,
+            SubscriptionsConfirmError::TokenAlreadyUsed => StatusCode::CONFLICT,
             SubscriptionsConfirmError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(db_pool, subscriber_id))]
-pub async fn confirm_subscriber(db_pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(transaction, subscriber_id)
+)]
+pub async fn confirm_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
     sqlx::query!(
         "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
         subscriber_id
     )
-    .execute(db_pool)
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET status = 'confirmed'
+        WHERE subscriber_id = $1 AND status = 'pending_confirmation'
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
     .await?;
     Ok(())
 }
 
-#[tracing::instrument(
-    name = "Get subscriber_id from token",
-    skip(db_pool, subscription_token)
-)]
-pub async fn get_subscriber_id_from_token(
+pub struct SubscriptionToken {
+    pub subscriber_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+/// Also returns the locale of the subscriber the token belongs to.
+#[tracing::instrument(name = "Look up subscription token", skip(db_pool, subscription_token))]
+async fn lookup_token(
     db_pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id from subscription_tokens WHERE subscription_token = $1",
+) -> Result<Option<(SubscriptionToken, Locale)>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT t.subscriber_id, t.created_at, t.consumed_at, s.locale
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        "#,
         subscription_token
     )
     .fetch_optional(db_pool)
     .await?;
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(record.map(|r| {
+        let token = SubscriptionToken {
+            subscriber_id: r.subscriber_id,
+            created_at: r.created_at,
+            consumed_at: r.consumed_at,
+        };
+        // Stored locales were valid when stored.
+        (token, Locale::parse(&r.locale).unwrap_or_default())
+    }))
+}
+
+#[tracing::instrument(name = "Get subscription token", skip(transaction, subscription_token))]
+pub async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<Option<SubscriptionToken>, sqlx::Error> {
+    let result = sqlx::query_as!(
+        SubscriptionToken,
+        r#"
+        SELECT subscriber_id, created_at, consumed_at
+        FROM subscription_tokens
+        WHERE subscription_token = $1
+        FOR UPDATE
+        "#,
+        subscription_token
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(result)
+}
+
+#[tracing::instrument(
+    name = "Mark subscription token as used",
+    skip(transaction, subscription_token)
+)]
+pub async fn consume_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscription_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE subscription_tokens SET consumed_at = NOW() WHERE subscription_token = $1",
+        subscription_token
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
@@ -0,0 +1,66 @@
+use super::{
+    enqueue_confirmation_email, get_subscriber_by_email, rotate_token, ExistingSubscriber,
+    SubscribeError,
+};
+use crate::{
+    domain::{CanonicalEmailRules, SubscriberEmail},
+    startup::ApplicationBaseUrl,
+};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use tera::Tera;
+
+#[derive(serde::Deserialize)]
+pub struct ResendConfirmationFormData {
+    email: String,
+}
+
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, db_pool, rules, base_url, tera),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendConfirmationFormData>,
+    db_pool: web::Data<PgPool>,
+    rules: web::Data<CanonicalEmailRules>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    tera: web::Data<Tera>,
+) -> Result<HttpResponse, SubscribeError> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(SubscribeError::ValidationError)?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if let Some(ExistingSubscriber {
+        id, status, locale, ..
+    }) = get_subscriber_by_email(&mut transaction, &rules, &email)
+        .await
+        .context("Failed to retrieve an existing subscriber")?
+    {
+        if status == "pending_confirmation" {
+            let subscription_token = rotate_token(&mut transaction, id)
+                .await
+                .context("Failed to rotate the confirmation token of a pending subscriber")?;
+            enqueue_confirmation_email(
+                &mut transaction,
+                &tera,
+                &email,
+                &locale,
+                &base_url.0,
+                &subscription_token,
+            )
+            .await
+            .context("Failed to enqueue a confirmation email")?;
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to rotate a subscription token")?;
+
+    // Same answer for unknown, pending and confirmed addresses.
+    Ok(HttpResponse::Ok().finish())
+}
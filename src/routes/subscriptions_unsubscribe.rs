@@ -0,0 +1,179 @@
+use super::{enqueue_localized_email, error_chain_fmt, get_subscriber_by_id};
+use crate::{
+    localization::{get_subscriber_locale, page_locale, render_localized},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    unsubscribe::verify_unsubscribe_signature,
+};
+use actix_web::{
+    http::header::{AcceptLanguage, ContentType},
+    web, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Debug;
+use tera::Tera;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct UnsubscribeParameters {
+    subscriber_id: Uuid,
+    signature: String,
+}
+
+/// Ask for an explicit confirmation: link scanners pre-fetch URLs with GET,
+/// so following the link must not unsubscribe anybody on its own.
+#[tracing::instrument(
+    name = "Show the unsubscribe form",
+    skip(parameters, db_pool, hmac_secret, tera, accept_language)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    tera: web::Data<Tera>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify_unsubscribe_signature(
+        &hmac_secret.0,
+        parameters.subscriber_id,
+        &parameters.signature,
+    )
+    .map_err(UnsubscribeError::InvalidLink)?;
+
+    let subscriber_locale = get_subscriber_locale(db_pool.get_ref(), parameters.subscriber_id)
+        .await
+        .context("Failed to get the locale of the subscriber")?;
+    let locale = page_locale(subscriber_locale, accept_language.as_deref());
+    let context =
+        tera::Context::from_serialize(&parameters.0).context("Failed to serialize context")?;
+    let body = render_localized(&tera, "subscriptions/unsubscribe.j2", &locale, &context)
+        .context("Failed to render the unsubscribe form")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// Handles both the form above and RFC 8058 one-click requests, which POST
+/// `List-Unsubscribe=One-Click` to the URL found in the `List-Unsubscribe` header.
+///
+/// Confirmed subscribers are emailed a confirmation that they left.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, db_pool, base_url, hmac_secret, tera, accept_language),
+    fields(subscriber_id = %parameters.subscriber_id)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    db_pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    tera: web::Data<Tera>,
+    accept_language: Option<web::Header<AcceptLanguage>>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    verify_unsubscribe_signature(
+        &hmac_secret.0,
+        parameters.subscriber_id,
+        &parameters.signature,
+    )
+    .map_err(UnsubscribeError::InvalidLink)?;
+
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber = get_subscriber_by_id(&mut transaction, parameters.subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber")?;
+    unsubscribe_subscriber(&mut transaction, parameters.subscriber_id)
+        .await
+        .context("Failed to unsubscribe subscriber")?;
+    // Nobody is written to twice, nor at an address that bounced or complained.
+    if let Some(subscriber) = subscriber.as_ref().filter(|s| s.status == "confirmed") {
+        let mut context = tera::Context::new();
+        context.insert("subscribe_link", &base_url.0);
+        enqueue_localized_email(
+            &mut transaction,
+            &tera,
+            &subscriber.email,
+            &subscriber.locale,
+            "emails/unsubscribed",
+            &context,
+        )
+        .await
+        .context("Failed to enqueue the unsubscribe confirmation")?;
+    }
+    let subscriber_locale = subscriber.map(|s| s.locale);
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit a SQL transaction to unsubscribe a subscriber")?;
+
+    let locale = page_locale(subscriber_locale, accept_language.as_deref());
+    let body = render_localized(
+        &tera,
+        "subscriptions/unsubscribed.j2",
+        &locale,
+        &tera::Context::new(),
+    )
+    .context("Failed to render the unsubscribe confirmation")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidLink(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidLink(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Flip the subscriber, and each of their list memberships, to `unsubscribed`
+/// and drop the issues still waiting to be delivered to them.
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(transaction))]
+pub async fn unsubscribe_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE list_memberships SET status = 'unsubscribed' WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE subscriber_email IN (SELECT email FROM subscriptions WHERE id = $1)
+        "#,
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
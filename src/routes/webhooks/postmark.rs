@@ -0,0 +1,122 @@
+use crate::{
+    configuration::WebhookCredentials,
+    delivery_events::{record_delivery_event, DeliveryEvent, PostmarkEvent},
+    routes::error_chain_fmt,
+};
+use actix_web::{
+    http::header::{HeaderMap, HeaderValue},
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use reqwest::{header, StatusCode};
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::fmt::Debug;
+
+pub struct PostmarkWebhookCredentials(pub WebhookCredentials);
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("The payload could not be parsed.")]
+    MalformedPayload(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            WebhookError::MalformedPayload(_) => StatusCode::BAD_REQUEST,
+            WebhookError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::new(self.status_code());
+        if let WebhookError::AuthError(_) = self {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Basic realm="webhooks""#),
+            );
+        }
+        response
+    }
+}
+
+/// Bounces, spam complaints, deliveries and opens reported by Postmark.
+///
+/// Record types that are not tracked are acknowledged all the same, so that
+/// Postmark does not retry them.
+#[tracing::instrument(
+    name = "Receive a Postmark webhook",
+    skip(request, body, db_pool, credentials),
+    fields(record_type = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    body: web::Bytes,
+    db_pool: web::Data<PgPool>,
+    credentials: web::Data<PostmarkWebhookCredentials>,
+) -> Result<HttpResponse, WebhookError> {
+    check_basic_authentication(request.headers(), &credentials.0)
+        .map_err(WebhookError::AuthError)?;
+
+    let payload: serde_json::Value =
+        serde_json::from_slice(&body).map_err(WebhookError::MalformedPayload)?;
+    if let Some(record_type) = payload.get("RecordType").and_then(|t| t.as_str()) {
+        tracing::Span::current().record("record_type", record_type);
+    }
+    let event = serde_json::from_value::<PostmarkEvent>(payload.clone())
+        .map_err(WebhookError::MalformedPayload)?;
+    if let Some(event) = DeliveryEvent::from_postmark(event) {
+        record_delivery_event(&db_pool, &event, &payload)
+            .await
+            .context("Failed to record a delivery event")?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn check_basic_authentication(
+    headers: &HeaderMap,
+    expected: &WebhookCredentials,
+) -> Result<(), anyhow::Error> {
+    let header_value = headers
+        .get(header::AUTHORIZATION)
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("The 'Basic' credentials are not a username and a password.")?;
+
+    // Both are compared in full, so that the timing tells nothing about which one was wrong.
+    let username_matches = constant_time_eq(username.as_bytes(), expected.username.as_bytes());
+    let password_matches = constant_time_eq(
+        password.as_bytes(),
+        expected.password.expose_secret().as_bytes(),
+    );
+    if !(username_matches & password_matches) {
+        anyhow::bail!("Invalid username or password.");
+    }
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
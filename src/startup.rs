@@ -1,10 +1,25 @@
 use crate::{
     authentication::reject_anonymous_users,
-    configuration::{DatabaseSettings, Settings},
+    bot_protection::BotProtection,
+    configuration::{ApplicationSettings, DatabaseSettings, Settings, WebhookSettings},
+    domain::CanonicalEmailRules,
     email_client::EmailClient,
+    email_verification::EmailVerifier,
+    rate_limit::{rate_limit_subscriptions, RateLimiter},
     routes::{
-        admin_dashboard, change_password, change_password_form, confirm, health_check, home,
-        log_out, login, login_form, publish_newsletter, send_newsletter_form, subscribe,
+        admin_confirm_subscriber, admin_dashboard, admin_delete_subscriber,
+        admin_unsubscribe_subscriber, api_confirm, api_erase_personal_data, api_get_personal_data,
+        api_subscribe, cancel_scheduled_issue, change_password, change_password_form, confirm,
+        confirm_form, create_email_layout, create_issue, create_mailing_list, create_suppression,
+        delete_email_layout, delete_issue, delete_suppression, email_layout_page,
+        email_layouts_page, erase_personal_data, export_personal_data, export_subscribers,
+        health_check, home, import_subscribers, import_subscribers_form, issue_page, issue_preview,
+        issues_page, json_error_handler, log_out, login, login_form, mailing_lists_page,
+        mark_issue_ready, new_issue_form, personal_data_page, postmark_webhook, publish_newsletter,
+        resend_confirmation, schedule_issue, send_issue, send_newsletter_form, send_test_issue,
+        subscribe, subscriber_import_error_report, subscriber_import_page, subscriber_page,
+        subscribers_page, suppressions_page, unsubscribe, unsubscribe_form, update_email_layout,
+        update_issue, update_suppression, PostmarkWebhookCredentials,
     },
     tera::init_tera,
 };
@@ -30,6 +45,7 @@ impl Application {
 
         let db_pool = get_db_pool(&configuration.database);
         let email_client = configuration.email_client.client();
+        let canonical_email_rules = configuration.canonical_emails.rules();
 
         let address = format!(
             "{}:{}",
@@ -38,14 +54,25 @@ impl Application {
         tracing::info!("Starting listener at {}", address);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let rate_limiter =
+            RateLimiter::build(&configuration.rate_limit, &configuration.redis_uri).await?;
+        let bot_protection = BotProtection::new(
+            &configuration.bot_protection,
+            configuration.application.hmac_secret.clone(),
+        );
+        let email_verifier = EmailVerifier::build(&configuration.email_verification)?;
         let server = run(
             listener,
             db_pool,
             email_client,
             tera,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
+            configuration.application,
             configuration.redis_uri,
+            rate_limiter,
+            bot_protection,
+            email_verifier,
+            canonical_email_rules,
+            configuration.webhooks,
         )
         .await?;
 
@@ -69,21 +96,37 @@ pub fn get_db_pool(configuration: &DatabaseSettings) -> sqlx::Pool<sqlx::Postgre
 
 pub struct ApplicationBaseUrl(pub String);
 pub struct HmacSecret(pub Secret<String>);
+pub struct SubscriptionTokenTtl(pub chrono::Duration);
 
+#[allow(clippy::too_many_arguments)]
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
     email_client: EmailClient,
     tera: Tera,
-    base_url: String,
-    hmac_secret: Secret<String>,
+    application: ApplicationSettings,
     redis_uri: Secret<String>,
+    rate_limiter: RateLimiter,
+    bot_protection: BotProtection,
+    email_verifier: EmailVerifier,
+    canonical_email_rules: CanonicalEmailRules,
+    webhooks: WebhookSettings,
 ) -> Result<Server, anyhow::Error> {
     // wrap the connection in a smart pointer
     let db_pool = web::Data::new(db_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
     let tera = web::Data::new(tera);
+    let subscription_token_ttl =
+        web::Data::new(SubscriptionTokenTtl(application.subscription_token_ttl()));
+    let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+    let hmac_secret = application.hmac_secret;
+    let hmac_secret_data = web::Data::new(HmacSecret(hmac_secret.clone()));
+    let rate_limiter = web::Data::new(rate_limiter);
+    let bot_protection = web::Data::new(bot_protection);
+    let email_verifier = web::Data::new(email_verifier);
+    let canonical_email_rules = web::Data::new(canonical_email_rules);
+    let postmark_webhook_credentials =
+        web::Data::new(PostmarkWebhookCredentials(webhooks.postmark));
 
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let mesage_store = CookieMessageStore::builder(secret_key.clone()).build();
@@ -102,8 +145,34 @@ async fn run(
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
             .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(rate_limit_subscriptions))
+                    .route(web::post().to(subscribe)),
+            )
+            .route("/subscriptions/confirm", web::get().to(confirm_form))
+            .route("/subscriptions/confirm", web::post().to(confirm))
+            .service(
+                web::resource("/subscriptions/resend-confirmation")
+                    .wrap(from_fn(rate_limit_subscriptions))
+                    .route(web::post().to(resend_confirmation)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
+            .service(
+                web::scope("/api/v1")
+                    .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                    .service(
+                        web::resource("/subscriptions")
+                            .wrap(from_fn(rate_limit_subscriptions))
+                            .route(web::post().to(api_subscribe)),
+                    )
+                    .route("/subscriptions/confirm", web::post().to(api_confirm)),
+            )
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
@@ -112,12 +181,97 @@ async fn run(
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(log_out))
                     .route("/newsletters", web::post().to(publish_newsletter))
-                    .route("/newsletters", web::get().to(send_newsletter_form)),
+                    .route("/newsletters", web::get().to(send_newsletter_form))
+                    .route("/issues", web::get().to(issues_page))
+                    .route("/issues", web::post().to(create_issue))
+                    .route("/issues/new", web::get().to(new_issue_form))
+                    .route("/issues/{issue_id}", web::get().to(issue_page))
+                    .route("/issues/{issue_id}", web::post().to(update_issue))
+                    .route("/issues/{issue_id}/ready", web::post().to(mark_issue_ready))
+                    .route("/issues/{issue_id}/send", web::post().to(send_issue))
+                    .route("/issues/{issue_id}/preview", web::get().to(issue_preview))
+                    .route("/issues/{issue_id}/test", web::post().to(send_test_issue))
+                    .route(
+                        "/issues/{issue_id}/schedule",
+                        web::post().to(schedule_issue),
+                    )
+                    .route(
+                        "/issues/{issue_id}/schedule/cancel",
+                        web::post().to(cancel_scheduled_issue),
+                    )
+                    .route("/issues/{issue_id}/delete", web::post().to(delete_issue))
+                    .route("/layouts", web::get().to(email_layouts_page))
+                    .route("/layouts", web::post().to(create_email_layout))
+                    .route("/layouts/{layout_id}", web::get().to(email_layout_page))
+                    .route("/layouts/{layout_id}", web::post().to(update_email_layout))
+                    .route(
+                        "/layouts/{layout_id}/delete",
+                        web::post().to(delete_email_layout),
+                    )
+                    .route("/lists", web::get().to(mailing_lists_page))
+                    .route("/lists", web::post().to(create_mailing_list))
+                    .route("/suppressions", web::get().to(suppressions_page))
+                    .route("/suppressions", web::post().to(create_suppression))
+                    .route(
+                        "/suppressions/{suppression_id}",
+                        web::post().to(update_suppression),
+                    )
+                    .route(
+                        "/suppressions/{suppression_id}/delete",
+                        web::post().to(delete_suppression),
+                    )
+                    .route("/personal-data", web::get().to(personal_data_page))
+                    .route("/personal-data/export", web::get().to(export_personal_data))
+                    .route("/personal-data/erase", web::post().to(erase_personal_data))
+                    .service(
+                        web::scope("/api/v1")
+                            .app_data(web::JsonConfig::default().error_handler(json_error_handler))
+                            .route("/personal-data", web::get().to(api_get_personal_data))
+                            .route("/erasures", web::post().to(api_erase_personal_data)),
+                    )
+                    .route("/subscribers", web::get().to(subscribers_page))
+                    .route("/subscribers/export", web::get().to(export_subscribers))
+                    .route(
+                        "/subscribers/import",
+                        web::get().to(import_subscribers_form),
+                    )
+                    .route("/subscribers/import", web::post().to(import_subscribers))
+                    .route(
+                        "/subscribers/imports/{import_id}",
+                        web::get().to(subscriber_import_page),
+                    )
+                    .route(
+                        "/subscribers/imports/{import_id}/errors.csv",
+                        web::get().to(subscriber_import_error_report),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_page),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/confirm",
+                        web::post().to(admin_confirm_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/unsubscribe",
+                        web::post().to(admin_unsubscribe_subscriber),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}/delete",
+                        web::post().to(admin_delete_subscriber),
+                    ),
             )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
             .app_data(tera.clone())
+            .app_data(hmac_secret_data.clone())
+            .app_data(subscription_token_ttl.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_verifier.clone())
+            .app_data(canonical_email_rules.clone())
+            .app_data(postmark_webhook_credentials.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,290 @@
+//! Keeps the stored canonical addresses of the subscribers and of the
+//! suppressions in line with the provider rules.
+//!
+//! Changing the rules does not touch the stored subscribers: run
+//! `zero2prod canonicalize-subscribers` afterwards. Merging subscribers cannot
+//! be undone, so look at what `--dry-run` reports first. The tombstones of
+//! erased addresses are hashes, which cannot be recomputed.
+use crate::{
+    configuration::Settings,
+    domain::{CanonicalEmailRules, SubscriberEmail},
+    startup::get_db_pool,
+};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+const USAGE: &str = "Usage: zero2prod canonicalize-subscribers [--dry-run]";
+
+/// `zero2prod canonicalize-subscribers`, to run after changing the provider rules.
+#[derive(Debug, PartialEq)]
+pub struct CanonicalizeCommand {
+    /// Report what would change, without changing anything.
+    pub dry_run: bool,
+}
+
+impl CanonicalizeCommand {
+    /// Parse the arguments following the name of the command.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut dry_run = false;
+        for arg in args {
+            match arg.as_str() {
+                "--dry-run" => dry_run = true,
+                _ => return Err(format!("Unexpected argument {}.\n{}", arg, USAGE)),
+            }
+        }
+        Ok(Self { dry_run })
+    }
+}
+
+pub async fn run_canonicalize_command(
+    configuration: Settings,
+    command: CanonicalizeCommand,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_db_pool(&configuration.database);
+    let rules = configuration.canonical_emails.rules();
+    let report = canonicalize_subscribers(&db_pool, &rules, command.dry_run).await?;
+
+    for merge in &report.merges {
+        println!(
+            "{} merged into {}",
+            merge.duplicate_email, merge.keeper_email
+        );
+    }
+    println!(
+        "{} subscriber(s) merged, {} canonical address(es) updated, {} duplicate suppression(s) removed, {} suppression(s) updated{}.",
+        report.merges.len(),
+        report.n_updated,
+        report.n_suppressions_removed,
+        report.n_suppressions_updated,
+        if command.dry_run {
+            " (dry run: nothing was changed)"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+/// What canonicalizing the subscribers changed, or would change.
+#[derive(Debug, Default)]
+pub struct CanonicalizationReport {
+    pub merges: Vec<Merge>,
+    pub n_updated: usize,
+    pub n_suppressions_removed: usize,
+    pub n_suppressions_updated: usize,
+}
+
+#[derive(Debug)]
+pub struct Merge {
+    pub keeper_email: String,
+    pub duplicate_email: String,
+}
+
+struct StoredSubscriber {
+    id: Uuid,
+    email: String,
+    canonical_email: String,
+}
+
+/// Recompute the canonical address of every subscriber, and merge those
+/// which turn out to reach the same mailbox.
+///
+/// The subscriber already holding the canonical address is kept, the oldest
+/// one otherwise. A mailbox suppressed under several spellings keeps its
+/// oldest suppression. The tables are locked meanwhile, so that no signup
+/// slips in under a stale canonical form. A dry run rolls everything back.
+#[tracing::instrument(name = "Canonicalize subscribers", skip(db_pool, rules))]
+pub async fn canonicalize_subscribers(
+    db_pool: &PgPool,
+    rules: &CanonicalEmailRules,
+    dry_run: bool,
+) -> Result<CanonicalizationReport, anyhow::Error> {
+    let mut transaction = db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!("LOCK TABLE subscriptions, suppressions IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut transaction)
+        .await
+        .context("Failed to lock the subscribers and the suppressions")?;
+    let subscribers = sqlx::query_as!(
+        StoredSubscriber,
+        "SELECT id, email, canonical_email FROM subscriptions ORDER BY subscribed_at, id"
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch the subscribers")?;
+
+    let mut groups: Vec<(String, Vec<StoredSubscriber>)> = Vec::new();
+    let mut group_of: HashMap<String, usize> = HashMap::new();
+    for subscriber in subscribers {
+        // Addresses which no longer parse keep the form they were stored with.
+        let canonical_email = match SubscriberEmail::parse(subscriber.email.clone()) {
+            Ok(email) => email.canonical(rules),
+            Err(_) => continue,
+        };
+        let group = *group_of.entry(canonical_email.clone()).or_insert_with(|| {
+            groups.push((canonical_email, Vec::new()));
+            groups.len() - 1
+        });
+        groups[group].1.push(subscriber);
+    }
+
+    let mut report = CanonicalizationReport::default();
+    let mut ids = Vec::new();
+    let mut canonical_emails = Vec::new();
+    for (canonical_email, mut members) in groups {
+        let keeper = members
+            .iter()
+            .position(|member| member.canonical_email == canonical_email)
+            .unwrap_or(0);
+        let keeper = members.remove(keeper);
+        for duplicate in members {
+            sqlx::query!(
+                "SELECT FROM merge_subscribers($1, $2)",
+                keeper.id,
+                duplicate.id
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to merge a subscriber into another one")?;
+            report.merges.push(Merge {
+                keeper_email: keeper.email.clone(),
+                duplicate_email: duplicate.email,
+            });
+        }
+        if keeper.canonical_email != canonical_email {
+            ids.push(keeper.id);
+            canonical_emails.push(canonical_email);
+        }
+    }
+    report.n_updated = ids.len();
+    // Set apart first, so that two subscribers swapping their canonical
+    // addresses do not collide on the unique index halfway through.
+    sqlx::query!(
+        "UPDATE subscriptions SET canonical_email = id::text WHERE id = ANY($1)",
+        &ids,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to set the stale canonical addresses apart")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET canonical_email = t.canonical_email
+        FROM UNNEST($1::uuid[], $2::text[]) AS t(id, canonical_email)
+        WHERE subscriptions.id = t.id
+        "#,
+        &ids,
+        &canonical_emails,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the canonical addresses")?;
+    canonicalize_suppressions(&mut transaction, rules, &mut report).await?;
+
+    if dry_run {
+        transaction
+            .rollback()
+            .await
+            .context("Failed to roll back the SQL transaction of a dry run")?;
+    } else {
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit a SQL transaction to canonicalize the subscribers")?;
+        tracing::info!(
+            n_merged = report.merges.len(),
+            n_updated = report.n_updated,
+            n_suppressions_removed = report.n_suppressions_removed,
+            n_suppressions_updated = report.n_suppressions_updated,
+            "Canonical addresses updated to the provider rules"
+        );
+    }
+    Ok(report)
+}
+
+async fn canonicalize_suppressions(
+    transaction: &mut Transaction<'_, Postgres>,
+    rules: &CanonicalEmailRules,
+    report: &mut CanonicalizationReport,
+) -> Result<(), anyhow::Error> {
+    let suppressions = sqlx::query!(
+        r#"
+        SELECT suppression_id, email, canonical_email
+        FROM suppressions
+        ORDER BY created_at, suppression_id
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the suppressions")?;
+
+    let mut seen = HashSet::new();
+    let mut duplicate_ids = Vec::new();
+    let mut ids = Vec::new();
+    let mut canonical_emails = Vec::new();
+    for suppression in suppressions {
+        let canonical_email = match SubscriberEmail::parse(suppression.email) {
+            Ok(email) => email.canonical(rules),
+            Err(_) => suppression.canonical_email.clone(),
+        };
+        if !seen.insert(canonical_email.clone()) {
+            duplicate_ids.push(suppression.suppression_id);
+        } else if canonical_email != suppression.canonical_email {
+            ids.push(suppression.suppression_id);
+            canonical_emails.push(canonical_email);
+        }
+    }
+    report.n_suppressions_removed = duplicate_ids.len();
+    report.n_suppressions_updated = ids.len();
+    sqlx::query!(
+        "DELETE FROM suppressions WHERE suppression_id = ANY($1)",
+        &duplicate_ids,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the duplicate suppressions")?;
+    sqlx::query!(
+        "UPDATE suppressions SET canonical_email = suppression_id::text WHERE suppression_id = ANY($1)",
+        &ids,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to set the stale canonical addresses of the suppressions apart")?;
+    sqlx::query!(
+        r#"
+        UPDATE suppressions
+        SET canonical_email = t.canonical_email
+        FROM UNNEST($1::uuid[], $2::text[]) AS t(suppression_id, canonical_email)
+        WHERE suppressions.suppression_id = t.suppression_id
+        "#,
+        &ids,
+        &canonical_emails,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the canonical addresses of the suppressions")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CanonicalizeCommand;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn only_the_dry_run_option_is_accepted() {
+        assert_ok_eq!(
+            CanonicalizeCommand::parse(vec![]),
+            CanonicalizeCommand { dry_run: false }
+        );
+        assert_ok_eq!(
+            CanonicalizeCommand::parse(vec!["--dry-run".into()]),
+            CanonicalizeCommand { dry_run: true }
+        );
+        assert_err!(CanonicalizeCommand::parse(vec!["--force".into()]));
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use tokio::io::AsyncReadExt;
+
+use super::{ImportOptions, SubscriberImport};
+use crate::{
+    configuration::Settings, mailing_lists::resolve_mailing_lists, startup::get_db_pool,
+    tera::init_tera,
+};
+
+const USAGE: &str = "Usage: zero2prod import-subscribers <file.csv> [--list <slug>]... \
+    [--consent-source <source>] [--report <errors.csv>]";
+
+/// `zero2prod import-subscribers`, for the imports too large for an upload.
+#[derive(Debug, PartialEq)]
+pub struct ImportCommand {
+    pub path: PathBuf,
+    pub lists: Vec<String>,
+    pub consent_source: Option<String>,
+    /// Where to write the rejected rows; they are printed otherwise.
+    pub report_path: Option<PathBuf>,
+}
+
+impl ImportCommand {
+    /// Parse the arguments following the name of the command.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.into_iter();
+        let mut path = None;
+        let mut lists = Vec::new();
+        let mut consent_source = None;
+        let mut report_path = None;
+        while let Some(arg) = args.next() {
+            let mut value = |option: &str| {
+                args.next()
+                    .ok_or_else(|| format!("{} requires a value.\n{}", option, USAGE))
+            };
+            match arg.as_str() {
+                "--list" => lists.push(value("--list")?),
+                "--consent-source" => consent_source = Some(value("--consent-source")?),
+                "--report" => report_path = Some(PathBuf::from(value("--report")?)),
+                option if option.starts_with("--") => {
+                    return Err(format!("Unknown option {}.\n{}", option, USAGE))
+                }
+                _ if path.is_none() => path = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument {}.\n{}", arg, USAGE)),
+            }
+        }
+        if consent_source
+            .as_deref()
+            .is_some_and(|source| source.trim().is_empty())
+        {
+            return Err("The consent source cannot be empty.".into());
+        }
+        Ok(Self {
+            path: path.ok_or_else(|| format!("The CSV file is missing.\n{}", USAGE))?,
+            lists,
+            consent_source,
+            report_path,
+        })
+    }
+}
+
+pub async fn run_import_command(
+    configuration: Settings,
+    command: ImportCommand,
+) -> Result<(), anyhow::Error> {
+    let db_pool = get_db_pool(&configuration.database);
+    let rules = configuration.canonical_emails.rules();
+    let list_ids = resolve_mailing_lists(&db_pool, &command.lists).await?;
+    let mut file = tokio::fs::File::open(&command.path)
+        .await
+        .with_context(|| format!("Failed to open {}", command.path.display()))?;
+
+    let tera = init_tera();
+    let mut import = SubscriberImport::new(
+        &db_pool,
+        &rules,
+        &tera,
+        &configuration.application.base_url,
+        ImportOptions {
+            list_ids,
+            consent_source: command.consent_source,
+        },
+    );
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n_read = file
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read {}", command.path.display()))?;
+        if n_read == 0 {
+            break;
+        }
+        import.feed(&buffer[..n_read]).await?;
+    }
+    let report = import.finish().await?;
+
+    println!(
+        "{} row(s): {} created, {} updated, {} rejected.",
+        report.n_rows,
+        report.n_created,
+        report.n_updated,
+        report.rejected_rows.len()
+    );
+    if !report.rejected_rows.is_empty() {
+        let error_report = report.error_report()?;
+        match command.report_path {
+            Some(report_path) => {
+                tokio::fs::write(&report_path, error_report)
+                    .await
+                    .with_context(|| format!("Failed to write {}", report_path.display()))?;
+                println!("The rejected rows are in {}.", report_path.display());
+            }
+            None => print!("{}", error_report),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImportCommand;
+    use claims::{assert_err, assert_ok_eq};
+    use std::path::PathBuf;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn every_option_is_parsed() {
+        assert_ok_eq!(
+            ImportCommand::parse(args(&[
+                "audience.csv",
+                "--list",
+                "weekly",
+                "--consent-source",
+                "Conference signup sheet",
+                "--list",
+                "newsletter",
+                "--report",
+                "errors.csv",
+            ])),
+            ImportCommand {
+                path: PathBuf::from("audience.csv"),
+                lists: vec!["weekly".into(), "newsletter".into()],
+                consent_source: Some("Conference signup sheet".into()),
+                report_path: Some(PathBuf::from("errors.csv")),
+            }
+        );
+    }
+
+    #[test]
+    fn the_file_is_required() {
+        assert_err!(ImportCommand::parse(args(&["--list", "weekly"])));
+    }
+
+    #[test]
+    fn options_require_a_value() {
+        assert_err!(ImportCommand::parse(args(&["audience.csv", "--list"])));
+        assert_err!(ImportCommand::parse(args(&[
+            "audience.csv",
+            "--consent-source",
+            " "
+        ])));
+    }
+
+    #[test]
+    fn unknown_arguments_are_rejected() {
+        assert_err!(ImportCommand::parse(args(&["audience.csv", "--force"])));
+        assert_err!(ImportCommand::parse(args(&["a.csv", "b.csv"])));
+    }
+}
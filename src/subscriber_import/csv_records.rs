@@ -0,0 +1,118 @@
+use csv_core::{ReadRecordResult, Reader};
+
+/// A CSV parser fed with chunks of bytes as they arrive, so that uploads are
+/// processed without holding the whole file in memory.
+pub struct CsvRecords {
+    reader: Reader,
+    output: Vec<u8>,
+    n_output: usize,
+    ends: Vec<usize>,
+    n_ends: usize,
+}
+
+impl Default for CsvRecords {
+    fn default() -> Self {
+        Self {
+            reader: Reader::new(),
+            output: vec![0; 1024],
+            n_output: 0,
+            ends: vec![0; 16],
+            n_ends: 0,
+        }
+    }
+}
+
+impl CsvRecords {
+    /// Parse `input`, returning the records it completed.
+    pub fn feed(&mut self, input: &[u8]) -> Vec<Vec<String>> {
+        let mut records = Vec::new();
+        self.read(input, &mut records);
+        records
+    }
+
+    /// Flush the last record, if the file does not end with a line break.
+    pub fn finish(&mut self) -> Vec<Vec<String>> {
+        let mut records = Vec::new();
+        // An empty input marks the end of the data.
+        self.read(&[], &mut records);
+        records
+    }
+
+    fn read(&mut self, mut input: &[u8], records: &mut Vec<Vec<String>>) {
+        loop {
+            let (result, n_input, n_output, n_ends) = self.reader.read_record(
+                input,
+                &mut self.output[self.n_output..],
+                &mut self.ends[self.n_ends..],
+            );
+            input = &input[n_input..];
+            self.n_output += n_output;
+            self.n_ends += n_ends;
+            match result {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return,
+                ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    records.push(self.take_record());
+                }
+            }
+        }
+    }
+
+    fn take_record(&mut self) -> Vec<String> {
+        let mut start = 0;
+        let fields = self.ends[..self.n_ends]
+            .iter()
+            .map(|&end| {
+                let field = String::from_utf8_lossy(&self.output[start..end]).into_owned();
+                start = end;
+                field
+            })
+            .collect();
+        self.n_output = 0;
+        self.n_ends = 0;
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CsvRecords;
+
+    fn parse_in_chunks(data: &[u8], chunk_size: usize) -> Vec<Vec<String>> {
+        let mut parser = CsvRecords::default();
+        let mut records: Vec<_> = data
+            .chunks(chunk_size)
+            .flat_map(|chunk| parser.feed(chunk))
+            .collect();
+        records.extend(parser.finish());
+        records
+    }
+
+    #[test]
+    fn records_split_across_chunks_are_reassembled() {
+        let data = b"email,name\r\nursula@gmail.com,\"Le Guin, Ursula\"\nocta@example.com,Octavia";
+        for chunk_size in [1, 2, 7, 1024] {
+            assert_eq!(
+                parse_in_chunks(data, chunk_size),
+                vec![
+                    vec!["email", "name"],
+                    vec!["ursula@gmail.com", "Le Guin, Ursula"],
+                    vec!["octa@example.com", "Octavia"],
+                ],
+                "Failed with chunks of {} bytes",
+                chunk_size
+            );
+        }
+    }
+
+    #[test]
+    fn long_fields_and_many_columns_are_supported() {
+        let long_name = "a".repeat(5000);
+        let header = (0..40).map(|i| format!("c{}", i)).collect::<Vec<_>>();
+        let data = format!("{}\n{}\n", header.join(","), long_name);
+        let records = parse_in_chunks(data.as_bytes(), 100);
+        assert_eq!(records[0].len(), 40);
+        assert_eq!(records[1], vec![long_name]);
+    }
+}
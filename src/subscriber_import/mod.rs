@@ -0,0 +1,506 @@
+mod cli;
+mod csv_records;
+
+pub use cli::{run_import_command, ImportCommand};
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use tera::Tera;
+use uuid::Uuid;
+
+use crate::{
+    domain::{CanonicalEmailRules, Locale, NewSubscriber, SubscriberEmail},
+    personal_data::email_hash,
+    routes::{enqueue_confirmation_email, error_chain_fmt, rotate_token},
+};
+use csv_records::CsvRecords;
+
+/// Rows upserted per transaction.
+const BATCH_SIZE: usize = 500;
+
+/// How the imported subscribers join the newsletter.
+pub struct ImportOptions {
+    pub list_ids: Vec<Uuid>,
+    /// How the subscribers agreed to receive the newsletter, if they already
+    /// did elsewhere: they are confirmed without going through the double opt-in.
+    /// Otherwise they are sent a confirmation email.
+    pub consent_source: Option<String>,
+}
+
+/// The outcome of an import, row by row.
+#[derive(Default, Debug)]
+pub struct ImportReport {
+    pub n_rows: usize,
+    pub n_created: usize,
+    pub n_updated: usize,
+    pub rejected_rows: Vec<RejectedRow>,
+}
+
+#[derive(Debug)]
+pub struct RejectedRow {
+    /// Position in the file, the header being row 1, as in a spreadsheet.
+    pub row: usize,
+    pub email: String,
+    pub reason: String,
+}
+
+impl ImportReport {
+    /// The rejected rows as a CSV file, to fix them and import them again.
+    pub fn error_report(&self) -> Result<String, anyhow::Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(["row", "email", "error"])?;
+        for rejected in &self.rejected_rows {
+            writer.write_record([
+                rejected.row.to_string().as_str(),
+                &rejected.email,
+                &rejected.reason,
+            ])?;
+        }
+        Ok(String::from_utf8(writer.into_inner()?)?)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("The file must start with a header row with an `email` and a `name` column.")]
+    MissingColumns,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Where the fields are in each record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl Columns {
+    fn parse(header: &[String]) -> Result<Self, ImportError> {
+        let position = |column: &str| {
+            header.iter().position(|h| {
+                h.trim_start_matches('\u{feff}')
+                    .trim()
+                    .eq_ignore_ascii_case(column)
+            })
+        };
+        match (position("email"), position("name")) {
+            (Some(email), Some(name)) => Ok(Self { email, name }),
+            _ => Err(ImportError::MissingColumns),
+        }
+    }
+}
+
+/// A CSV import in progress: feed it the file as it is read, then finish it.
+///
+/// Rows are validated as they come and upserted in batches, each in its own
+/// transaction: an import interrupted by a failure keeps the batches already
+/// committed, and can be run again.
+pub struct SubscriberImport<'a> {
+    db_pool: &'a PgPool,
+    rules: &'a CanonicalEmailRules,
+    tera: &'a Tera,
+    base_url: &'a str,
+    options: ImportOptions,
+    parser: CsvRecords,
+    columns: Option<Columns>,
+    n_records: usize,
+    /// The row each address was first seen on.
+    seen: HashMap<String, usize>,
+    batch: Vec<(usize, NewSubscriber)>,
+    report: ImportReport,
+}
+
+impl<'a> SubscriberImport<'a> {
+    pub fn new(
+        db_pool: &'a PgPool,
+        rules: &'a CanonicalEmailRules,
+        tera: &'a Tera,
+        base_url: &'a str,
+        options: ImportOptions,
+    ) -> Self {
+        Self {
+            db_pool,
+            rules,
+            tera,
+            base_url,
+            options,
+            parser: CsvRecords::default(),
+            columns: None,
+            n_records: 0,
+            seen: HashMap::new(),
+            batch: Vec::with_capacity(BATCH_SIZE),
+            report: ImportReport::default(),
+        }
+    }
+
+    pub async fn feed(&mut self, chunk: &[u8]) -> Result<(), ImportError> {
+        for record in self.parser.feed(chunk) {
+            self.add_record(record)?;
+            if self.batch.len() >= BATCH_SIZE {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> Result<ImportReport, ImportError> {
+        for record in self.parser.finish() {
+            self.add_record(record)?;
+        }
+        if self.columns.is_none() {
+            return Err(ImportError::MissingColumns);
+        }
+        self.flush().await?;
+        Ok(self.report)
+    }
+
+    fn add_record(&mut self, record: Vec<String>) -> Result<(), ImportError> {
+        self.n_records += 1;
+        let row = self.n_records;
+        let columns = match self.columns {
+            Some(columns) => columns,
+            None => {
+                self.columns = Some(Columns::parse(&record)?);
+                return Ok(());
+            }
+        };
+        if record.iter().all(|field| field.trim().is_empty()) {
+            return Ok(());
+        }
+
+        self.report.n_rows += 1;
+        let field = |i: usize| record.get(i).map(|f| f.trim()).unwrap_or_default();
+        let email = field(columns.email).to_owned();
+        let name = field(columns.name).to_owned();
+        let new_subscriber = match NewSubscriber::parse(email.clone(), name) {
+            Ok(new_subscriber) => new_subscriber,
+            Err(errors) => {
+                let reason = errors
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                self.reject(row, email, reason);
+                return Ok(());
+            }
+        };
+        let canonical_email = new_subscriber.email.canonical(self.rules);
+        if let Some(first_row) = self.seen.get(&canonical_email) {
+            let reason = format!("Duplicate of row {}.", first_row);
+            self.reject(row, email, reason);
+            return Ok(());
+        }
+        self.seen.insert(canonical_email, row);
+        self.batch.push((row, new_subscriber));
+        Ok(())
+    }
+
+    fn reject(&mut self, row: usize, email: String, reason: String) {
+        self.report
+            .rejected_rows
+            .push(RejectedRow { row, email, reason });
+    }
+
+    #[tracing::instrument(name = "Import a batch of subscribers", skip(self), fields(n_rows = self.batch.len()))]
+    async fn flush(&mut self) -> Result<(), ImportError> {
+        let mut batch = std::mem::take(&mut self.batch);
+        let mut transaction = self
+            .db_pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?;
+        let erased = get_erased_emails(&mut transaction, self.rules, &batch)
+            .await
+            .context("Failed to look for erased addresses in a batch of subscribers")?;
+        batch.retain(|(row, new_subscriber)| {
+            if !erased.contains(new_subscriber.email.as_ref()) {
+                return true;
+            }
+            self.report.rejected_rows.push(RejectedRow {
+                row: *row,
+                email: new_subscriber.email.to_string(),
+                reason: "This address was erased at the request of its owner: \
+                    it cannot be imported again."
+                    .into(),
+            });
+            false
+        });
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let upserted = upsert_subscribers(&mut transaction, self.rules, &batch, &self.options)
+            .await
+            .context("Failed to upsert a batch of subscribers")?;
+        let subscriber_ids: Vec<Uuid> = upserted.iter().map(|r| r.id).collect();
+        join_lists(&mut transaction, &subscriber_ids, &self.options)
+            .await
+            .context("Failed to add a batch of subscribers to the mailing lists")?;
+        if self.options.consent_source.is_none() {
+            let emails: HashMap<String, &SubscriberEmail> = batch
+                .iter()
+                .map(|(_, s)| (s.email.canonical(self.rules), &s.email))
+                .collect();
+            for (subscriber_id, canonical_email, locale) in
+                get_pending_subscribers(&mut transaction, &subscriber_ids, &self.options.list_ids)
+                    .await
+                    .context("Failed to fetch the subscribers awaiting confirmation")?
+            {
+                let email = emails
+                    .get(&canonical_email)
+                    .context("A subscriber awaiting confirmation is not in the batch")?;
+                let subscription_token = rotate_token(&mut transaction, subscriber_id).await?;
+                enqueue_confirmation_email(
+                    &mut transaction,
+                    self.tera,
+                    email,
+                    &locale,
+                    self.base_url,
+                    &subscription_token,
+                )
+                .await
+                .context("Failed to enqueue a confirmation email")?;
+            }
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit a batch of imported subscribers")?;
+
+        let upserted: HashMap<&str, bool> = upserted
+            .iter()
+            .map(|r| (r.canonical_email.as_str(), r.created))
+            .collect();
+        for (row, new_subscriber) in batch {
+            match upserted.get(new_subscriber.email.canonical(self.rules).as_str()) {
+                Some(true) => self.report.n_created += 1,
+                Some(false) => self.report.n_updated += 1,
+                None => self.reject(
+                    row,
+                    new_subscriber.email.to_string(),
+                    "This address has unsubscribed or bounced: it cannot be imported again.".into(),
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The addresses of the batch whose owner had them erased.
+async fn get_erased_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    rules: &CanonicalEmailRules,
+    batch: &[(usize, NewSubscriber)],
+) -> Result<HashSet<String>, sqlx::Error> {
+    let hashes: HashMap<String, &str> = batch
+        .iter()
+        .map(|(_, s)| (email_hash(s.email.as_ref(), rules), s.email.as_ref()))
+        .collect();
+    let keys: Vec<String> = hashes.keys().cloned().collect();
+    let erased = sqlx::query!(
+        "SELECT email_hash FROM erased_subscribers WHERE email_hash = ANY($1)",
+        &keys
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(erased
+        .into_iter()
+        .filter_map(|r| hashes.get(&r.email_hash).map(|e| e.to_string()))
+        .collect())
+}
+
+struct UpsertedSubscriber {
+    id: Uuid,
+    canonical_email: String,
+    created: bool,
+}
+
+/// Insert the new addresses and refresh the names of the known ones.
+///
+/// Known addresses keep the spelling they were first stored with.
+///
+/// Pending subscribers are confirmed if consent was given elsewhere.
+/// Unsubscribed, bounced and complaining addresses are left untouched, and not returned.
+async fn upsert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    rules: &CanonicalEmailRules,
+    batch: &[(usize, NewSubscriber)],
+    options: &ImportOptions,
+) -> Result<Vec<UpsertedSubscriber>, sqlx::Error> {
+    let ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = batch.iter().map(|(_, s)| s.email.to_string()).collect();
+    let canonical_emails: Vec<String> = batch
+        .iter()
+        .map(|(_, s)| s.email.canonical(rules))
+        .collect();
+    let names: Vec<String> = batch
+        .iter()
+        .map(|(_, s)| s.name.as_ref().to_owned())
+        .collect();
+    let status = match options.consent_source {
+        Some(_) => "confirmed",
+        None => "pending_confirmation",
+    };
+    sqlx::query_as!(
+        UpsertedSubscriber,
+        r#"
+        INSERT INTO subscriptions (
+            id, email, canonical_email, name, subscribed_at, status, consent_source, consented_at
+        )
+        SELECT
+            id, email, canonical_email, name, NOW(), $5, $6::text,
+            CASE WHEN $6::text IS NULL THEN NULL ELSE NOW() END
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[])
+            AS t(id, email, canonical_email, name)
+        ON CONFLICT (canonical_email) DO UPDATE
+        SET
+            name = EXCLUDED.name,
+            status = CASE
+                WHEN subscriptions.status = 'pending_confirmation' THEN EXCLUDED.status
+                ELSE subscriptions.status
+            END,
+            consent_source = CASE
+                WHEN subscriptions.status = 'pending_confirmation' THEN EXCLUDED.consent_source
+                ELSE subscriptions.consent_source
+            END,
+            consented_at = CASE
+                WHEN subscriptions.status = 'pending_confirmation' THEN EXCLUDED.consented_at
+                ELSE subscriptions.consented_at
+            END
+        WHERE subscriptions.status IN ('pending_confirmation', 'confirmed')
+        RETURNING id, canonical_email AS "canonical_email!", (xmax = 0) AS "created!"
+        "#,
+        &ids,
+        &emails,
+        &canonical_emails,
+        &names,
+        status,
+        options.consent_source,
+    )
+    .fetch_all(transaction)
+    .await
+}
+
+/// Add the subscribers to the lists; lists they had left are not joined again.
+async fn join_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    options: &ImportOptions,
+) -> Result<(), sqlx::Error> {
+    let status = match options.consent_source {
+        Some(_) => "confirmed",
+        None => "pending_confirmation",
+    };
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (subscriber_id, list_id, status)
+        SELECT subscriber_id, list_id, $3
+        FROM UNNEST($1::uuid[]) AS s(subscriber_id)
+        CROSS JOIN UNNEST($2::uuid[]) AS l(list_id)
+        ON CONFLICT (subscriber_id, list_id) DO UPDATE
+        SET status = CASE
+            WHEN list_memberships.status = 'pending_confirmation' THEN EXCLUDED.status
+            ELSE list_memberships.status
+        END
+        "#,
+        subscriber_ids,
+        &options.list_ids,
+        status,
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// The subscribers with a membership of the lists awaiting confirmation.
+async fn get_pending_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    list_ids: &[Uuid],
+) -> Result<Vec<(Uuid, String, Locale)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT s.id, s.canonical_email AS "canonical_email!", s.locale
+        FROM subscriptions s
+        WHERE
+            s.id = ANY($1)
+            AND EXISTS (
+                SELECT 1 FROM list_memberships m
+                WHERE
+                    m.subscriber_id = s.id
+                    AND m.list_id = ANY($2)
+                    AND m.status = 'pending_confirmation'
+            )
+        "#,
+        subscriber_ids,
+        list_ids,
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(rows
+        .into_iter()
+        // Stored locales were valid when stored.
+        .map(|r| {
+            let locale = Locale::parse(&r.locale).unwrap_or_default();
+            (r.id, r.canonical_email, locale)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Columns, ImportError, ImportReport, RejectedRow};
+    use claims::{assert_matches, assert_ok_eq};
+
+    fn header(columns: &[&str]) -> Vec<String> {
+        columns.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn columns_are_found_whatever_their_order_and_case() {
+        assert_ok_eq!(
+            Columns::parse(&header(&["Name", "company", " EMAIL "])),
+            Columns { email: 2, name: 0 }
+        );
+        assert_ok_eq!(
+            Columns::parse(&header(&["\u{feff}email", "name"])),
+            Columns { email: 0, name: 1 }
+        );
+    }
+
+    #[test]
+    fn a_header_without_email_or_name_is_rejected() {
+        assert_matches!(
+            Columns::parse(&header(&["email", "full_name"])),
+            Err(ImportError::MissingColumns)
+        );
+        assert_matches!(
+            Columns::parse(&header(&["ursula@gmail.com", "Ursula"])),
+            Err(ImportError::MissingColumns)
+        );
+    }
+
+    #[test]
+    fn the_error_report_quotes_fields_as_needed() {
+        let report = ImportReport {
+            rejected_rows: vec![RejectedRow {
+                row: 3,
+                email: "ursula, le guin".into(),
+                reason: "ursula, le guin is not a valid subscriber email.".into(),
+            }],
+            ..ImportReport::default()
+        };
+        assert_eq!(
+            report.error_report().unwrap(),
+            "row,email,error\n3,\"ursula, le guin\",\"ursula, le guin is not a valid subscriber email.\"\n"
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use sqlx::PgExecutor;
+
+use crate::domain::{CanonicalEmailRules, SubscriberEmail};
+
+/// Whether the address is on the suppression list, and must not be emailed.
+/// Any spelling of a suppressed address is suppressed.
+#[tracing::instrument(name = "Check the suppression list", skip(executor, rules))]
+pub async fn is_suppressed(
+    executor: impl PgExecutor<'_>,
+    email: &SubscriberEmail,
+    rules: &CanonicalEmailRules,
+) -> Result<bool, sqlx::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM suppressions WHERE canonical_email = $1
+        ) AS "suppressed!"
+        "#,
+        email.canonical(rules)
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(r.suppressed)
+}
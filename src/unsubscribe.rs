@@ -0,0 +1,109 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Build the link a subscriber can follow to leave the newsletter.
+///
+/// The link carries the subscriber id together with an HMAC tag, so that
+/// nobody can unsubscribe someone else by guessing their id.
+pub fn unsubscribe_link(
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+) -> String {
+    let signature = sign(hmac_secret, subscriber_id);
+    format!(
+        "{}/subscriptions/unsubscribe?subscriber_id={}&signature={}",
+        base_url, subscriber_id, signature
+    )
+}
+
+pub fn verify_unsubscribe_signature(
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+    signature: &str,
+) -> Result<(), anyhow::Error> {
+    let signature = hex::decode(signature).context("The signature is not a valid hex string")?;
+    mac(hmac_secret, subscriber_id)
+        .verify_slice(&signature)
+        .context("The signature does not match the subscriber id")?;
+    Ok(())
+}
+
+fn sign(hmac_secret: &Secret<String>, subscriber_id: Uuid) -> String {
+    hex::encode(mac(hmac_secret, subscriber_id).finalize().into_bytes())
+}
+
+fn mac(hmac_secret: &Secret<String>, subscriber_id: Uuid) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    // The secret also signs flash messages: scope the tag to this use case.
+    mac.update(b"unsubscribe:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, unsubscribe_link, verify_unsubscribe_signature};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-long-and-very-secret-key".into())
+    }
+
+    #[test]
+    fn a_signature_produced_for_a_subscriber_is_accepted() {
+        let subscriber_id = Uuid::new_v4();
+        let signature = sign(&secret(), subscriber_id);
+        assert_ok!(verify_unsubscribe_signature(
+            &secret(),
+            subscriber_id,
+            &signature
+        ));
+    }
+
+    #[test]
+    fn a_signature_for_another_subscriber_is_rejected() {
+        let signature = sign(&secret(), Uuid::new_v4());
+        assert_err!(verify_unsubscribe_signature(
+            &secret(),
+            Uuid::new_v4(),
+            &signature
+        ));
+    }
+
+    #[test]
+    fn a_signature_made_with_another_secret_is_rejected() {
+        let subscriber_id = Uuid::new_v4();
+        let signature = sign(&Secret::new("another-secret".into()), subscriber_id);
+        assert_err!(verify_unsubscribe_signature(
+            &secret(),
+            subscriber_id,
+            &signature
+        ));
+    }
+
+    #[test]
+    fn a_malformed_signature_is_rejected() {
+        assert_err!(verify_unsubscribe_signature(
+            &secret(),
+            Uuid::new_v4(),
+            "not-hex"
+        ));
+    }
+
+    #[test]
+    fn the_link_points_to_the_unsubscribe_endpoint() {
+        let subscriber_id = Uuid::new_v4();
+        let link = unsubscribe_link("http://127.0.0.1", &secret(), subscriber_id);
+        assert!(link.starts_with(&format!(
+            "http://127.0.0.1/subscriptions/unsubscribe?subscriber_id={}&signature=",
+            subscriber_id
+        )));
+    }
+}
@@ -0,0 +1,368 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    when_sending_an_email, TestApp,
+};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+/// Store a subscriber directly, bypassing the validation and the rate limits of the form
+async fn insert_subscriber(app: &TestApp, name: &str, email: &str, status: &str) -> Uuid {
+    let subscriber_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status)
+        VALUES ($1, $2, lower($2), $3, NOW(), $4)
+        "#,
+        subscriber_id,
+        email,
+        name,
+        status,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    subscriber_id
+}
+
+async fn subscriber_status(app: &TestApp, subscriber_id: Uuid) -> Option<String> {
+    sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap()
+    .map(|r| r.status)
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = Uuid::new_v4();
+
+    // Act & Assert
+    assert_is_redirect_to(&app.get_subscribers("").await, "/login");
+    assert_is_redirect_to(&app.get_subscriber(subscriber_id).await, "/login");
+    for action in ["confirm", "unsubscribe", "delete"] {
+        let response = app.post_subscriber_action(subscriber_id, action).await;
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_by_email_name_and_status() {
+    // Arrange
+    let app = spawn_app().await;
+    insert_subscriber(&app, "Ursula Le Guin", "ursula@gmail.com", "confirmed").await;
+    insert_subscriber(
+        &app,
+        "Octavia Butler",
+        "octavia@example.com",
+        "pending_confirmation",
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    // Act & Assert
+    let html = app.get_subscribers_html("").await;
+    assert!(html.contains("ursula@gmail.com") && html.contains("octavia@example.com"));
+
+    let html = app.get_subscribers_html("q=GUIN").await;
+    assert!(html.contains("ursula@gmail.com") && !html.contains("octavia@example.com"));
+
+    let html = app.get_subscribers_html("q=example.com").await;
+    assert!(!html.contains("ursula@gmail.com") && html.contains("octavia@example.com"));
+
+    let html = app
+        .get_subscribers_html("status=pending_confirmation")
+        .await;
+    assert!(!html.contains("ursula@gmail.com") && html.contains("octavia@example.com"));
+
+    // LIKE wildcards are matched literally
+    let html = app.get_subscribers_html("q=%25").await;
+    assert!(html.contains("0 subscriber(s)"));
+
+    let response = app.get_subscribers("status=banned").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..51 {
+        insert_subscriber(
+            &app,
+            "Ursula",
+            &format!("ursula{}@gmail.com", i),
+            "confirmed",
+        )
+        .await;
+    }
+    app.test_user.login(&app).await;
+
+    // Act
+    let first_page = app.get_subscribers_html("").await;
+    let second_page = app.get_subscribers_html("page=2").await;
+
+    // Assert
+    assert!(first_page.contains("Page 1 of 2"));
+    assert_eq!(first_page.matches("<tr>").count(), 1 + 50);
+    assert!(second_page.contains("Page 2 of 2"));
+    assert_eq!(second_page.matches("<tr>").count(), 1 + 1);
+}
+
+#[tokio::test]
+async fn pages_too_far_to_be_reached_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers(&format!("page={}", i64::MAX)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_subscriber_page_shows_the_status_history_and_delivered_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Earthsea news",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    let html = app.get_subscriber_html(subscriber_id).await;
+
+    // Assert
+    let (_, history) = html.split_once("<h2>Status history</h2>").unwrap();
+    let pending = history.find(": pending_confirmation</li>").unwrap();
+    let confirmed = history.find(": confirmed</li>").unwrap();
+    assert!(pending < confirmed);
+    assert!(html.contains("Earthsea news"));
+}
+
+#[tokio::test]
+async fn subscriber_details_are_escaped() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = insert_subscriber(
+        &app,
+        "<script>alert(1)</script>",
+        "ursula@gmail.com",
+        "confirmed",
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let list_html = app.get_subscribers_html("").await;
+    let detail_html = app.get_subscriber_html(subscriber_id).await;
+
+    // Assert
+    assert!(!list_html.contains("<script>alert(1)</script>"));
+    assert!(!detail_html.contains("<script>alert(1)</script>"));
+}
+
+#[tokio::test]
+async fn an_unknown_subscriber_returns_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act & Assert
+    let response = app.get_subscriber(Uuid::new_v4()).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.post_subscriber_action(Uuid::new_v4(), "confirm").await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn admins_can_confirm_and_unsubscribe_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act - Part 1 - Confirm
+    let response = app.post_subscriber_action(subscriber_id, "confirm").await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    let html = app.get_subscriber_html(subscriber_id).await;
+    assert!(html.contains("The subscriber has been confirmed."));
+    assert_eq!(
+        subscriber_status(&app, subscriber_id).await.unwrap(),
+        "confirmed"
+    );
+    // The link sent by email is no longer needed
+    let response = app
+        .api_client
+        .get(confirmation_links.html)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+
+    // Act - Part 2 - Unsubscribe
+    let response = app
+        .post_subscriber_action(subscriber_id, "unsubscribe")
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/subscribers/{}", subscriber_id));
+    assert_eq!(
+        subscriber_status(&app, subscriber_id).await.unwrap(),
+        "unsubscribed"
+    );
+    let memberships = sqlx::query!(
+        "SELECT status FROM list_memberships WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(memberships.iter().all(|m| m.status == "unsubscribed"));
+}
+
+#[tokio::test]
+async fn admins_can_delete_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let response = app.post_subscriber_action(subscriber_id, "delete").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers");
+    let html = app.get_subscribers_html("").await;
+    assert!(html.contains("The subscriber has been deleted."));
+    assert!(subscriber_status(&app, subscriber_id).await.is_none());
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscription_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_subscribers_export("csv").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscribers_are_exported_as_csv_with_their_memberships() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    insert_subscriber(&app, "Le Guin, Ursula", "ursula@gmail.com", "unsubscribed").await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = response.text().await.unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at,lists");
+    assert_eq!(lines.len(), 1 + 2);
+    assert!(lines
+        .iter()
+        .any(|l| l.contains(",confirmed,") && l.ends_with(",newsletter:confirmed")));
+    assert!(lines
+        .iter()
+        .any(|l| l.contains(",ursula@gmail.com,\"Le Guin, Ursula\",unsubscribed,")));
+}
+
+#[tokio::test]
+async fn the_export_streams_every_subscriber_as_json_lines() {
+    // Arrange
+    let app = spawn_app().await;
+    // More than a batch
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status)
+        SELECT
+            gen_random_uuid(), 'ursula' || i || '@gmail.com', 'ursula' || i || '@gmail.com',
+            'Ursula', NOW(), 'confirmed'
+        FROM generate_series(1, 1203) AS i
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("ndjson").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"].to_str().unwrap(),
+        "application/x-ndjson"
+    );
+    let ndjson = response.text().await.unwrap();
+    let emails: std::collections::HashSet<_> = ndjson
+        .lines()
+        .map(|l| {
+            let subscriber: serde_json::Value = serde_json::from_str(l).unwrap();
+            subscriber["email"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(emails.len(), 1203);
+}
+
+#[tokio::test]
+async fn an_unknown_export_format_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_subscribers_export("xlsx").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,194 @@
+use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+#[tokio::test]
+async fn api_subscribe_returns_a_202_for_valid_json() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(202, response.status().as_u16());
+    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn api_subscribe_reports_every_invalid_field() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({"name": "", "email": "definitely-not-an-email"});
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0]["code"], "invalid_field");
+    assert_eq!(errors[0]["field"], "email");
+    assert_eq!(errors[1]["field"], "name");
+}
+
+#[tokio::test]
+async fn api_subscribe_reports_undeliverable_addresses_on_the_email_field() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({"name": "le guin", "email": "ursula@yopmail.com"});
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "email");
+    assert!(body["errors"][0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("disposable"));
+}
+
+#[tokio::test]
+async fn api_subscribe_stores_the_subscriber_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "attributes": {"company": "Earthsea Ltd", "seats": 3},
+    });
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(202, response.status().as_u16());
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({"company": "Earthsea Ltd", "seats": 3})
+    );
+}
+
+#[tokio::test]
+async fn api_subscribe_rejects_invalid_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "attributes": {"address": {"city": "Havnor"}},
+    });
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "attributes");
+}
+
+#[tokio::test]
+async fn api_subscribe_returns_a_400_with_a_json_error_for_malformed_bodies() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (serde_json::json!({"name": "le guin"}), "missing the email"),
+        (
+            serde_json::json!({"email": "ursula_le_guin@gmail.com"}),
+            "missing the name",
+        ),
+        (serde_json::json!([]), "not an object"),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_api_subscriptions(&body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["errors"][0]["code"], "malformed_request");
+    }
+}
+
+#[tokio::test]
+async fn api_confirm_confirms_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+    app.post_api_subscriptions(&body).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_link = app.get_confirmation_links(email_request).html;
+    let token = confirmation_link
+        .query_pairs()
+        .find(|(k, _)| k == "subscription_token")
+        .unwrap()
+        .1
+        .into_owned();
+
+    // Act
+    let response = app
+        .post_api_confirm(&serde_json::json!({ "subscription_token": token }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+
+    // A second attempt reports the token as already used
+    let response = app
+        .post_api_confirm(&serde_json::json!({ "subscription_token": token }))
+        .await;
+    assert_eq!(409, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["code"], "token_already_used");
+}
+
+#[tokio::test]
+async fn api_confirm_returns_a_404_for_unknown_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_api_confirm(&serde_json::json!({ "subscription_token": "not-a-real-token" }))
+        .await;
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["code"], "token_not_found");
+}
@@ -0,0 +1,233 @@
+use crate::helpers::{spawn_app_with, when_sending_an_email, TestApp};
+use wiremock::ResponseTemplate;
+use zero2prod::bot_protection::is_valid_proof_of_work;
+
+async fn spawn_protected_app(min_submit_seconds: u64, proof_of_work_difficulty: u8) -> TestApp {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.enabled = true;
+        c.bot_protection.min_submit_seconds = min_submit_seconds;
+        c.bot_protection.proof_of_work_difficulty = proof_of_work_difficulty;
+    })
+    .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app
+}
+
+/// The form token the home page embeds in the subscribe form.
+async fn get_form_token(app: &TestApp) -> String {
+    let html = app.get_home_html().await;
+    let marker = r#"name="form_token" value=""#;
+    let start = html.find(marker).expect("No form token in the page") + marker.len();
+    let end = start + html[start..].find('"').unwrap();
+    html[start..end].to_owned()
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+#[tokio::test]
+async fn subscribing_with_the_form_token_is_accepted() {
+    // Arrange
+    let app = spawn_protected_app(0, 0).await;
+    let form_token = get_form_token(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "{}&website=&form_token={}",
+            BODY,
+            urlencoding::encode(&form_token)
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_filled_honeypot_is_silently_dropped() {
+    // Arrange
+    let app = spawn_protected_app(0, 0).await;
+    let form_token = get_form_token(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "{}&website=https%3A%2F%2Fspam.example.com&form_token={}",
+            BODY,
+            urlencoding::encode(&form_token)
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 0);
+    assert!(app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn submissions_without_a_valid_form_token_are_rejected() {
+    // Arrange
+    let app = spawn_protected_app(0, 0).await;
+    let form_token = get_form_token(&app).await;
+    let (issued_at, _) = form_token.split_once('.').unwrap();
+    let test_cases = vec![
+        (BODY.to_string(), "no form token"),
+        (format!("{}&form_token=", BODY), "an empty form token"),
+        (
+            format!("{}&form_token={}.deadbeef", BODY, issued_at),
+            "a forged form token",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not reject a submission with {}.",
+            description
+        );
+    }
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn submissions_faster_than_a_human_are_rejected() {
+    // Arrange
+    let app = spawn_protected_app(60, 0).await;
+    let form_token = get_form_token(&app).await;
+
+    // Act
+    let response = app
+        .post_subscriptions(format!(
+            "{}&form_token={}",
+            BODY,
+            urlencoding::encode(&form_token)
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_proof_of_work_is_required_when_configured() {
+    // Arrange
+    let app = spawn_protected_app(0, 8).await;
+    let form_token = get_form_token(&app).await;
+    let (valid_nonce, invalid_nonce) = {
+        let mut nonces = (0u64..).map(|n| n.to_string());
+        let valid = nonces
+            .clone()
+            .find(|nonce| is_valid_proof_of_work(&form_token, EMAIL, nonce, 8))
+            .unwrap();
+        let invalid = nonces
+            .find(|nonce| !is_valid_proof_of_work(&form_token, EMAIL, nonce, 8))
+            .unwrap();
+        (valid, invalid)
+    };
+    let body = |nonce: &str| {
+        format!(
+            "{}&form_token={}&pow_nonce={}",
+            BODY,
+            urlencoding::encode(&form_token),
+            nonce
+        )
+    };
+
+    // Act - Part 1 - Wrong nonce
+    let response = app.post_subscriptions(body(&invalid_nonce)).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 2 - Solved
+    let response = app.post_subscriptions(body(&valid_nonce)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_form_cannot_be_submitted_twice() {
+    // Arrange
+    let app = spawn_protected_app(0, 8).await;
+    let form_token = get_form_token(&app).await;
+    let body = |email: &str| {
+        let nonce = (0u64..)
+            .map(|n| n.to_string())
+            .find(|nonce| is_valid_proof_of_work(&form_token, email, nonce, 8))
+            .unwrap();
+        format!(
+            "name=le%20guin&email={}&form_token={}&pow_nonce={}",
+            urlencoding::encode(email),
+            urlencoding::encode(&form_token),
+            nonce
+        )
+    };
+    let response = app.post_subscriptions(body(EMAIL)).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 1 - Same token, same nonce
+    let response = app.post_subscriptions(body(EMAIL)).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 2 - Same token, solved again for another address
+    let response = app.post_subscriptions(body("ursula@example.com")).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Assert
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn invalid_submissions_do_not_use_up_the_form() {
+    // Arrange
+    let app = spawn_protected_app(0, 0).await;
+    let form_token = get_form_token(&app).await;
+    let body = |email: &str| {
+        format!(
+            "name=le%20guin&email={}&form_token={}",
+            email,
+            urlencoding::encode(&form_token)
+        )
+    };
+
+    // Act - Part 1 - Typo in the address
+    let response = app.post_subscriptions(body("ursula_le_guin")).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 2 - Typo in the domain
+    let response = app
+        .post_subscriptions(body("ursula_le_guin%40no-mail-server.example"))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Act - Part 3 - Fixed
+    let response = app
+        .post_subscriptions(body("ursula_le_guin%40gmail.com"))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert
+    assert_eq!(subscriber_count(&app).await, 1);
+}
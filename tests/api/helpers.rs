@@ -1,21 +1,33 @@
 use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use fake::{
+    faker::{internet::en::SafeEmail, name::en::Name},
+    Fake,
+};
 use once_cell::sync::Lazy;
 use rand::{
     rngs::{OsRng, StdRng},
     Rng, SeedableRng,
 };
 use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use tera::Tera;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockBuilder, MockServer, ResponseTemplate,
+};
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{
+        get_configuration, DatabaseSettings, MailDomainResolverKind, RateLimitBackend, Settings,
+        WebhookCredentials,
+    },
+    domain::CanonicalEmailRules,
     email_client::EmailClient,
-    issue_delivery_worker::{try_execute_task, ExecutionOutcome},
+    issue_delivery_worker::{
+        try_execute_outbox_task, try_execute_task, try_publish_scheduled_issue, ExecutionOutcome,
+    },
     startup::{get_db_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
-    tera::init_tera,
 };
 
 static TRACING: Lazy<()> = Lazy::new(|| {
@@ -23,8 +35,6 @@ static TRACING: Lazy<()> = Lazy::new(|| {
     init_subscriber(subscriber);
 });
 
-static TEMPLATES: Lazy<Tera> = Lazy::new(|| init_tera());
-
 pub struct ConfirmationLinks {
     pub html: Url,
     pub plain_text: Url,
@@ -37,14 +47,30 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
-    pub tera: Tera,
     pub email_client: EmailClient,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub canonical_email_rules: CanonicalEmailRules,
+    pub postmark_webhook: WebhookCredentials,
 }
 
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_resend_confirmation(&self, body: String) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                &self.address
+            ))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -52,6 +78,45 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_api_subscriptions(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/api/v1/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_confirm(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/api/v1/subscriptions/confirm", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Follow a confirmation link, then press the confirmation button
+    pub async fn confirm_subscription(&self, confirmation_link: Url) -> reqwest::Response {
+        reqwest::get(confirmation_link.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        let subscription_token = confirmation_link
+            .query_pairs()
+            .find(|(key, _)| key == "subscription_token")
+            .unwrap()
+            .1
+            .into_owned();
+        self.api_client
+            .post(format!("{}/subscriptions/confirm", &self.address))
+            .form(&[("subscription_token", subscription_token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         // Parse the body as JSON
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
@@ -72,18 +137,39 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 
+    /// Extract the unsubscribe link from the `List-Unsubscribe` header of an issue
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> Url {
+        let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+        let header = body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|h| h["Name"] == "List-Unsubscribe")
+            .unwrap();
+        let raw_link = header["Value"]
+            .as_str()
+            .unwrap()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+
+        let mut unsubscribe_link = Url::parse(raw_link).unwrap();
+        assert_eq!(unsubscribe_link.host_str().unwrap(), "127.0.0.1");
+        unsubscribe_link.set_port(Some(self.port)).unwrap();
+        unsubscribe_link
+    }
+
     pub async fn post_publish_newsletter<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", &self.address))
+            .post(format!("{}/admin/newsletters", &self.address))
             .form(&body)
             .send()
             .await
@@ -92,7 +178,30 @@ impl TestApp {
 
     pub async fn get_publish_newsletter_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/admin/newsletters", &self.address))
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_issues<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/issues{}", &self.address, path))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issues_html(&self, path: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/issues{}", &self.address, path))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -106,16 +215,27 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
+            .post(format!("{}/login", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
+    pub async fn get_home_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -126,7 +246,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -138,7 +258,7 @@ impl TestApp {
 
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -153,16 +273,245 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request")
     }
 
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_list<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers(&self, query: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/subscribers?{}", &self.address, query))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscribers_html(&self, query: &str) -> String {
+        self.get_subscribers(query).await.text().await.unwrap()
+    }
+
+    pub async fn get_subscribers_export(&self, format: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/export?format={}",
+                &self.address, format
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber(&self, subscriber_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber_html(&self, subscriber_id: Uuid) -> String {
+        self.get_subscriber(subscriber_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is one of `confirm`, `unsubscribe` or `delete`
+    pub async fn post_subscriber_action(
+        &self,
+        subscriber_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/subscribers/{}/{}",
+                &self.address, subscriber_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_import_subscribers_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/subscribers/import", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_import_subscribers(
+        &self,
+        form: reqwest::multipart::Form,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Follow the redirect at the end of an import.
+    pub async fn get_location(&self, response: &reqwest::Response) -> reqwest::Response {
+        let location = response
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        self.api_client
+            .get(format!("{}{}", &self.address, location))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/suppressions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions_html(&self) -> String {
+        self.get_suppressions().await.text().await.unwrap()
+    }
+
+    /// `path` is relative to `/admin/suppressions`.
+    pub async fn post_suppressions<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/suppressions{}", &self.address, path))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// `path` is relative to `/admin/layouts`.
+    pub async fn get_layouts_html(&self, path: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/layouts{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is relative to `/admin/layouts`.
+    pub async fn post_layouts<Body>(&self, path: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/layouts{}", &self.address, path))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_personal_data_export(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/personal-data/export", &self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_personal_data_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/personal-data", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_erase_personal_data(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/personal-data/erase", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_personal_data(&self, email: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/api/v1/personal-data", &self.address))
+            .query(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_api_erasure(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/api/v1/erasures", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_postmark_webhook(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.postmark_webhook.username,
+                Some(self.postmark_webhook.password.expose_secret()),
+            )
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -170,11 +519,37 @@ impl TestApp {
 
     pub async fn dispatch_all_pending_emails(&self) {
         let mut rng = StdRng::from_seed(OsRng.gen());
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_outbox_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.canonical_email_rules,
+                &mut rng,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client, &mut rng)
-                    .await
-                    .unwrap()
+                try_publish_scheduled_issue(&self.db_pool).await.unwrap()
+            {
+                break;
+            }
+        }
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.base_url,
+                &self.hmac_secret,
+                &self.canonical_email_rules,
+                &mut rng,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
@@ -230,8 +605,12 @@ impl TestUser {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after tweaking its configuration, e.g. to lower limits
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
-    let tera = Lazy::force(&TEMPLATES);
 
     let email_server = MockServer::start().await;
 
@@ -244,6 +623,18 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         // Use the mock server as email API
         c.email_client.base_url = email_server.uri();
+        // Keep the rate limit counters of each test separate
+        c.rate_limit.backend = RateLimitBackend::InMemory;
+        // Tests post the subscribe form directly, without loading it first
+        c.bot_protection.enabled = false;
+        // Resolve the domains used in tests without hitting the network
+        c.email_verification.resolver = MailDomainResolverKind::Static;
+        c.email_verification.static_mail_servers =
+            ["gmail.com", "example.com", "example.net", "example.org"]
+                .into_iter()
+                .map(|domain| (domain.to_string(), vec![format!("mx.{}", domain)]))
+                .collect();
+        configure(&mut c);
         c
     };
 
@@ -256,7 +647,7 @@ pub async fn spawn_app() -> TestApp {
         .expect("Failed to build application");
     let port = application.port();
     let address = format!("http://127.0.0.1:{}", application.port());
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -272,8 +663,11 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client,
-        tera: tera.to_owned(),
         email_client: configuration.email_client.client(),
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+        canonical_email_rules: configuration.canonical_emails.rules(),
+        postmark_webhook: configuration.webhooks.postmark,
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -305,6 +699,67 @@ async fn configure_database(settings: &DatabaseSettings) -> PgPool {
     db_pool
 }
 
+// Short-hand for a common mocking setup
+pub fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email")).and(method("POST"))
+}
+
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    // We are working with multiple subscribers now,
+    // their details must be randomised to avoid conflicts!
+    let name: String = Name().fake();
+    let email: String = SafeEmail().fake();
+    let body = serde_urlencoded::to_string(serde_json::json!({
+        "name": name,
+        "email": email
+    }))
+    .unwrap();
+
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.test_user.login(app).await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+/// Use the public API of the application under test to create
+/// a confirmed subscriber
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_links = create_unconfirmed_subscriber(app).await;
+
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .named("Welcome the confirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("location").unwrap(), location);
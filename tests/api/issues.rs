@@ -0,0 +1,802 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email, TestApp,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::{matchers::any, Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::{try_publish_scheduled_issue, ExecutionOutcome};
+
+fn draft(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "text_content": "Hello {{ subscriber.name }}",
+        "html_content": "<p>Hello {{ subscriber.name }}</p>",
+    })
+}
+
+/// Save a new draft, returning its id.
+async fn create_draft(app: &TestApp, body: &serde_json::Value) -> Uuid {
+    let response = app.post_issues("", body).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .strip_prefix("/admin/issues/")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+async fn issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+async fn send(app: &TestApp, issue_id: Uuid, idempotency_key: &str) -> reqwest::Response {
+    app.post_issues(
+        &format!("/{}/send", issue_id),
+        &serde_json::json!({ "idempotency_key": idempotency_key }),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_issues() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let list_response = app
+        .api_client
+        .get(format!("{}/admin/issues", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let create_response = app.post_issues("", &draft("Title")).await;
+
+    // Assert
+    assert_is_redirect_to(&list_response, "/login");
+    assert_is_redirect_to(&create_response, "/login");
+}
+
+#[tokio::test]
+async fn saving_a_draft_does_not_send_it() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let issue_id = create_draft(&app, &draft("Work in progress")).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page.contains("The draft has been saved."));
+    assert!(html_page.contains("&lt;p&gt;Hello {{ subscriber.name }}&lt;&#x2F;p&gt;"));
+    let html_page = app.get_issues_html("").await;
+    assert!(html_page.contains("Work in progress"));
+}
+
+#[tokio::test]
+async fn a_draft_can_be_edited_over_several_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    sqlx::query!(
+        "INSERT INTO lists (list_id, slug, name) VALUES ($1, 'rust', 'Rust')",
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let issue_id = create_draft(&app, &draft("First try")).await;
+
+    // Act
+    let response = app
+        .post_issues(
+            &format!("/{}", issue_id),
+            &[
+                ("title", "Second try"),
+                ("text_content", "Better"),
+                ("html_content", "<p>Better</p>"),
+                ("lists", "rust"),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let saved = sqlx::query!(
+        r#"
+        SELECT i.title, i.text_content, i.status, l.slug
+        FROM newsletter_issues i
+        JOIN newsletter_issue_lists il ON il.newsletter_issue_id = i.newsletter_issue_id
+        JOIN lists l ON l.list_id = il.list_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].title, "Second try");
+    assert_eq!(saved[0].text_content, "Better");
+    assert_eq!(saved[0].status, "draft");
+    assert_eq!(saved[0].slug, "rust");
+}
+
+#[tokio::test]
+async fn drafts_must_be_marked_as_ready_before_being_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Not reviewed")).await;
+
+    // Act
+    let response = send(&app, issue_id, &Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page.contains("Only issues marked as ready can be sent."));
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+}
+
+#[tokio::test]
+async fn drafts_with_invalid_merge_tags_cannot_be_marked_as_ready() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(
+        &app,
+        &serde_json::json!({
+            "title": "Broken",
+            "text_content": "Hello {{ subscriber.name",
+            "html_content": "<p>Hello</p>",
+        }),
+    )
+    .await;
+
+    // Act
+    let response = app.post_issues(&format!("/{}/ready", issue_id), &()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page.contains("The issue contains invalid merge tags"));
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn editing_a_ready_issue_turns_it_back_into_a_draft() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Reviewed")).await;
+    app.post_issues(&format!("/{}/ready", issue_id), &()).await;
+    assert_eq!(issue_status(&app, issue_id).await, "ready");
+
+    // Act
+    app.post_issues(&format!("/{}", issue_id), &draft("Reviewed, then edited"))
+        .await;
+
+    // Assert
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn ready_issues_are_sent_once_and_then_marked_as_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Ready to go")).await;
+    app.post_issues(&format!("/{}/ready", issue_id), &()).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let idempotency_key = Uuid::new_v4().to_string();
+
+    // Act - Part 1 - Send, twice
+    let response = send(&app, issue_id, &idempotency_key).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+    let response = send(&app, issue_id, &idempotency_key).await;
+    assert_is_redirect_to(&response, "/admin/issues");
+
+    // Assert - Part 1
+    let html_page = app.get_issues_html("").await;
+    assert!(
+        html_page.contains("The newsletter issue has been accepted - emails will go out shortly.")
+    );
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+
+    // Act - Part 2 - Deliver
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn issues_that_went_out_cannot_be_edited_or_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Already out")).await;
+    app.post_issues(&format!("/{}/ready", issue_id), &()).await;
+    send(&app, issue_id, &Uuid::new_v4().to_string()).await;
+    // Nobody is subscribed: there was nothing to deliver
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+
+    // Act
+    app.post_issues(&format!("/{}", issue_id), &draft("Rewriting history"))
+        .await;
+    let edit_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    app.post_issues(&format!("/{}/delete", issue_id), &()).await;
+    let delete_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+
+    // Assert
+    assert!(edit_page.contains("This issue has already gone out."));
+    assert!(delete_page.contains("This issue has already gone out."));
+    assert!(delete_page.contains("Already out"));
+}
+
+#[tokio::test]
+async fn drafts_can_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Bad idea")).await;
+
+    // Act
+    let response = app.post_issues(&format!("/{}/delete", issue_id), &()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/issues");
+    let html_page = app.get_issues_html("").await;
+    assert!(html_page.contains("Bad idea has been deleted."));
+    let response = app
+        .api_client
+        .get(format!("{}/admin/issues/{}", &app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn issues_published_right_away_are_marked_as_sent_once_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    let status_before = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(status_before, "sending");
+    let status_after = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status;
+    assert_eq!(status_after, "sent");
+}
+
+/// Write an issue and mark it as ready.
+async fn create_ready_issue(app: &TestApp, title: &str) -> Uuid {
+    let issue_id = create_draft(app, &draft(title)).await;
+    app.post_issues(&format!("/{}/ready", issue_id), &()).await;
+    issue_id
+}
+
+async fn schedule(app: &TestApp, issue_id: Uuid, send_at: &str) -> reqwest::Response {
+    app.post_issues(
+        &format!("/{}/schedule", issue_id),
+        &serde_json::json!({ "send_at": send_at }),
+    )
+    .await
+}
+
+fn in_one_hour() -> String {
+    (Utc::now() + Duration::hours(1))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+/// Pretend the scheduled time has come.
+async fn fast_forward(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = NOW() - interval '1 minute' WHERE send_at IS NOT NULL"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn the_publish_form_can_schedule_an_issue_for_later() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("Nothing is sent before the scheduled time")
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Monday issue",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": "2099-03-02T09:00",
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_publish_newsletter_html().await;
+    assert!(html_page.contains(
+        "The newsletter issue has been scheduled - emails will go out at 2099-03-02 09:00 UTC."
+    ));
+    let issue = sqlx::query!("SELECT status, send_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "scheduled");
+    assert_eq!(
+        issue.send_at.unwrap().to_rfc3339(),
+        "2099-03-02T09:00:00+00:00"
+    );
+}
+
+#[tokio::test]
+async fn scheduled_issues_go_out_when_their_time_comes() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+    schedule(&app, issue_id, &in_one_hour()).await;
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    fast_forward(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_fanned_out_once_by_concurrent_workers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+    schedule(&app, issue_id, &in_one_hour()).await;
+    fast_forward(&app).await;
+
+    // Act
+    let outcomes =
+        futures_util::future::join_all((0..4).map(|_| try_publish_scheduled_issue(&app.db_pool)))
+            .await;
+
+    // Assert
+    let n_fan_outs = outcomes
+        .into_iter()
+        .filter(|outcome| matches!(outcome, Ok(ExecutionOutcome::TaskCompleted)))
+        .count();
+    assert_eq!(n_fan_outs, 1);
+    let n_queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 1);
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+    schedule(&app, issue_id, "2099-03-02T09:00").await;
+
+    // Act
+    let response = schedule(&app, issue_id, "2099-03-06T09:00").await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page.contains("Goes out at 2099-03-06 09:00 UTC."));
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+}
+
+#[tokio::test]
+async fn cancelled_schedules_do_not_go_out() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+    schedule(&app, issue_id, &in_one_hour()).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_issues(&format!("/{}/schedule/cancel", issue_id), &())
+        .await;
+    fast_forward(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page.contains("The issue is no longer scheduled."));
+    assert_eq!(issue_status(&app, issue_id).await, "ready");
+}
+
+#[tokio::test]
+async fn schedules_cannot_be_changed_once_the_issue_went_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+    schedule(&app, issue_id, &in_one_hour()).await;
+    fast_forward(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    app.post_issues(&format!("/{}/schedule/cancel", issue_id), &())
+        .await;
+    let cancel_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    schedule(&app, issue_id, &in_one_hour()).await;
+    let reschedule_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+
+    // Assert
+    assert!(cancel_page.contains("This issue has already gone out."));
+    assert!(reschedule_page.contains("This issue has already gone out."));
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn scheduled_issues_cannot_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+    schedule(&app, issue_id, &in_one_hour()).await;
+
+    // Act
+    app.post_issues(&format!("/{}", issue_id), &draft("Last minute change"))
+        .await;
+
+    // Assert
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page.contains("This issue is scheduled: cancel the schedule first."));
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_ready_issue(&app, "Monday issue").await;
+
+    // Act
+    schedule(&app, issue_id, "2023-01-02T09:00").await;
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    let response = app
+        .post_publish_newsletter(&serde_json::json!({
+            "title": "Monday issue",
+            "text_content": "Newsletter body as plain text",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "send_at": "2023-01-02T09:00",
+        }))
+        .await;
+
+    // Assert
+    assert!(html_page.contains("The send time must be in the future."));
+    assert_eq!(issue_status(&app, issue_id).await, "ready");
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+async fn preview_html(app: &TestApp, issue_id: Uuid, query: &str) -> String {
+    app.get_issues_html(&format!("/{}/preview{}", issue_id, query))
+        .await
+}
+
+async fn send_test(app: &TestApp, issue_id: Uuid, recipients: &str) -> reqwest::Response {
+    app.post_issues(
+        &format!("/{}/test", issue_id),
+        &serde_json::json!({ "recipients": recipients }),
+    )
+    .await
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n
+}
+
+#[tokio::test]
+async fn the_preview_shows_both_bodies_personalized_for_a_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = sqlx::query!("UPDATE subscriptions SET name = 'Ursula' RETURNING email")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Monday issue")).await;
+
+    // Act
+    let sample_preview = preview_html(&app, issue_id, "").await;
+    let subscriber_preview = preview_html(&app, issue_id, &format!("?email={}", email)).await;
+    let unknown_preview = preview_html(&app, issue_id, "?email=nobody%40example.com").await;
+
+    // Assert
+    assert!(sample_preview.contains(r#"srcdoc="&lt;p&gt;Hello Newsletter Reader&lt;&#x2F;p&gt;""#));
+    assert!(sample_preview.contains("<pre>Hello Newsletter Reader</pre>"));
+    assert!(subscriber_preview.contains(r#"srcdoc="&lt;p&gt;Hello Ursula&lt;&#x2F;p&gt;""#));
+    assert!(subscriber_preview.contains("<pre>Hello Ursula</pre>"));
+    assert!(unknown_preview.contains("There is no subscriber with this address"));
+    assert!(unknown_preview.contains("<pre>Hello Newsletter Reader</pre>"));
+}
+
+#[tokio::test]
+async fn the_preview_of_an_unknown_issue_is_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/admin/issues/{}/preview",
+            &app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn test_sends_go_out_right_away_without_being_enqueued() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Monday issue")).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = send_test(
+        &app,
+        issue_id,
+        "editor@example.com,\r\nproofreader@example.com",
+    )
+    .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(
+        html_page.contains("A test has been sent to editor@example.com, proofreader@example.com.")
+    );
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    for request in &requests {
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["Subject"], "[Test] Monday issue");
+        assert_eq!(body["TextBody"], "Hello Newsletter Reader");
+    }
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+    assert_eq!(issue_status(&app, issue_id).await, "draft");
+}
+
+#[tokio::test]
+async fn test_sends_skip_suppressed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_suppressions(
+        "",
+        &serde_json::json!({ "email": "bounced@example.com", "reason": "Hard bounce" }),
+    )
+    .await;
+    let issue_id = create_draft(&app, &draft("Monday issue")).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    send_test(&app, issue_id, "bounced@example.com editor@example.com").await;
+
+    // Assert
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+    assert!(html_page
+        .contains("bounced@example.com is on the suppression list: no test was sent to it."));
+    assert!(html_page.contains("A test has been sent to editor@example.com."));
+}
+
+#[tokio::test]
+async fn test_sends_are_refused_for_invalid_or_too_many_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = create_draft(&app, &draft("Monday issue")).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let too_many = (0..6)
+        .map(|i| format!("editor{}@example.com", i))
+        .collect::<Vec<_>>()
+        .join(",");
+    let test_cases = vec![
+        ("", "Enter the addresses to send a test to."),
+        (
+            "editor@example.com, not-an-email",
+            "not-an-email is not a valid subscriber email.",
+        ),
+        (too_many.as_str(), "Send a test to 5 addresses at most."),
+    ];
+
+    for (recipients, error_message) in test_cases {
+        // Act
+        send_test(&app, issue_id, recipients).await;
+
+        // Assert
+        let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+        assert!(
+            html_page.contains(error_message),
+            "The page did not show '{}' for recipients '{}'.",
+            error_message,
+            recipients
+        );
+    }
+}
+
+#[tokio::test]
+async fn drafts_written_in_markdown_keep_their_source() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let issue_id = create_draft(
+        &app,
+        &serde_json::json!({
+            "title": "Monday issue",
+            "markdown_content": "Hello **{{ subscriber.name }}**",
+        }),
+    )
+    .await;
+    let html_page = app.get_issues_html(&format!("/{}", issue_id)).await;
+
+    // Assert
+    let saved =
+        sqlx::query!("SELECT markdown_content, html_content, text_content FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        saved.markdown_content.as_deref(),
+        Some("Hello **{{ subscriber.name }}**")
+    );
+    assert_eq!(
+        saved.html_content,
+        "<p>Hello <strong>{{ subscriber.name }}</strong></p>\n"
+    );
+    assert_eq!(saved.text_content, "Hello *{{ subscriber.name }}*");
+    assert!(html_page.contains("Hello **{{ subscriber.name }}**</textarea>"));
+    assert!(!html_page.contains(r#"name="html_content""#));
+}
+
+#[tokio::test]
+async fn markdown_merge_tags_cannot_undo_the_sanitization() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_issues(
+            "",
+            &serde_json::json!({
+                "title": "Monday issue",
+                "markdown_content": "Hello {{ subscriber.company | safe }}",
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let n_issues = sqlx::query!("SELECT COUNT(*) AS \"n!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+}
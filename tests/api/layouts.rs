@@ -0,0 +1,296 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email, TestApp,
+};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+use zero2prod::email_layouts::{STARTER_HTML_TEMPLATE, STARTER_TEXT_TEMPLATE};
+
+fn layout(name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "html_template": STARTER_HTML_TEMPLATE,
+        "text_template": STARTER_TEXT_TEMPLATE,
+        "postal_address": "1 Main Street, Springfield",
+    })
+}
+
+/// Create a layout through the form, returning its id.
+async fn create_layout(app: &TestApp, body: &serde_json::Value) -> Uuid {
+    let response = app.post_layouts("", body).await;
+    assert_eq!(response.status().as_u16(), 303);
+    response
+        .headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .strip_prefix("/admin/layouts/")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn issue(layout_id: Uuid) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Monday issue",
+        "markdown_content": "Hello {{ subscriber.name }}",
+        "layout_id": layout_id.to_string(),
+        "idempotency_key": Uuid::new_v4().to_string(),
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_layouts() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_layouts("", &layout("Branded")).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_new_layout_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, &layout("Branded")).await;
+
+    // Act
+    let mut edited = layout("Branded, dark");
+    edited["postal_address"] = "2 Main Street, Springfield".into();
+    let response = app.post_layouts(&format!("/{}", layout_id), &edited).await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/admin/layouts/{}", layout_id));
+    let html_page = app.get_layouts_html(&format!("/{}", layout_id)).await;
+    assert!(html_page.contains("The layout has been saved."));
+    assert!(html_page.contains("2 Main Street, Springfield"));
+    let html_page = app.get_layouts_html("").await;
+    assert!(html_page.contains("Branded, dark"));
+}
+
+#[tokio::test]
+async fn layouts_without_a_footer_or_with_a_taken_name_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_layout(&app, &layout("Branded")).await;
+    let mut without_unsubscribe_link = layout("Plain");
+    without_unsubscribe_link["text_template"] = "{{ content }}\n{{ postal_address }}".into();
+    let mut without_postal_address = layout("Plain");
+    without_postal_address["postal_address"] = "  ".into();
+    let mut broken = layout("Plain");
+    broken["html_template"] = "{{ content | safe }".into();
+    let test_cases = vec![
+        (
+            without_unsubscribe_link,
+            "The text layout must include the unsubscribe link",
+        ),
+        (
+            without_postal_address,
+            "Enter the postal address shown in the footer.",
+        ),
+        (broken, "The HTML layout cannot be rendered"),
+        (
+            layout("Branded"),
+            "A layout named &#x27;Branded&#x27; already exists.",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_layouts("", &body).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/layouts");
+        let html_page = app.get_layouts_html("").await;
+        assert!(
+            html_page.contains(error_message),
+            "The error for '{}' was not shown",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn issues_are_sent_wrapped_in_their_layout() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET name = 'Ursula'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, &layout("Branded")).await;
+
+    // Act
+    let response = app.post_publish_newsletter(&issue(layout_id)).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let unsubscribe_link = body["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["Name"] == "List-Unsubscribe")
+        .unwrap()["Value"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<h1>Monday issue</h1>"));
+    assert!(html_body.contains("<p>Hello Ursula</p>"));
+    assert!(html_body.contains("1 Main Street, Springfield"));
+    assert!(html_body.contains(&format!(
+        "<a href=\"{}\">Unsubscribe</a>",
+        unsubscribe_link.replace('&', "&amp;")
+    )));
+    assert!(html_body.contains(r#"<body style="font-family: Georgia, serif;color: #222222;">"#));
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with("Hello Ursula\n"));
+    assert!(text_body.contains(&format!("Unsubscribe: {}", unsubscribe_link)));
+}
+
+#[tokio::test]
+async fn unknown_layouts_cannot_be_picked() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_publish_newsletter(&issue(Uuid::new_v4())).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn layouts_used_by_issues_cannot_be_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let used_layout_id = create_layout(&app, &layout("Branded")).await;
+    let unused_layout_id = create_layout(&app, &layout("Plain")).await;
+    let mut draft = issue(used_layout_id);
+    draft.as_object_mut().unwrap().remove("idempotency_key");
+    app.post_issues("", &draft).await;
+
+    // Act - Part 1 - Delete the layout used by the draft
+    let response = app
+        .post_layouts(&format!("/{}/delete", used_layout_id), &())
+        .await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, &format!("/admin/layouts/{}", used_layout_id));
+    let html_page = app.get_layouts_html(&format!("/{}", used_layout_id)).await;
+    assert!(html_page.contains("This layout is used by issues: it cannot be deleted."));
+
+    // Act - Part 2 - Delete the unused layout
+    let response = app
+        .post_layouts(&format!("/{}/delete", unused_layout_id), &())
+        .await;
+
+    // Assert - Part 2
+    assert_is_redirect_to(&response, "/admin/layouts");
+    let html_page = app.get_layouts_html("").await;
+    assert!(html_page.contains("The layout &#x27;Plain&#x27; has been deleted."));
+    assert!(html_page.contains("Branded"));
+}
+
+#[tokio::test]
+async fn previews_show_the_issue_in_its_layout() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, &layout("Branded")).await;
+    let mut draft = issue(layout_id);
+    draft.as_object_mut().unwrap().remove("idempotency_key");
+    let response = app.post_issues("", &draft).await;
+    let issue_page = response
+        .headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    // Act
+    let html_page = app
+        .get_issues_html(&format!(
+            "{}/preview",
+            issue_page.strip_prefix("/admin/issues").unwrap()
+        ))
+        .await;
+
+    // Assert
+    assert!(html_page.contains("Hello Newsletter Reader\n\n--\n1 Main Street, Springfield"));
+    // The sample reader's unsubscribe link is for nobody
+    assert!(html_page.contains(&format!("subscriber_id={}", Uuid::nil())));
+}
+
+#[tokio::test]
+async fn issues_are_not_sent_without_their_footer_when_the_layout_breaks() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, &layout("Branded")).await;
+    // Broken behind the back of the validation
+    sqlx::query!(
+        "UPDATE email_layouts SET html_template = '{{ content | safe }}{{ no_such_variable }}'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act - Part 1
+    let response = app.post_publish_newsletter(&issue(layout_id)).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 1 - The delivery is retried later
+    let n_retries = sqlx::query!("SELECT n_retries FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n_retries;
+    assert_eq!(n_retries, 1);
+
+    // Act - Part 2 - The layout is still broken after many attempts
+    sqlx::query!("UPDATE issue_delivery_queue SET n_retries = 20, execute_after = NOW()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2 - The delivery is given up
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "n!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 0);
+}
@@ -0,0 +1,240 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, when_sending_an_email, TestApp};
+use wiremock::ResponseTemplate;
+
+async fn create_list(app: &TestApp, slug: &str, name: &str) {
+    let response = app
+        .post_create_list(&serde_json::json!({"slug": slug, "name": name}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+}
+
+/// Subscribe `email` to the lists through the form, then follow the confirmation link
+async fn subscribe_and_confirm(app: &TestApp, email: &str, lists: &[&str]) {
+    let mut body = format!("name=le%20guin&email={}", urlencoding::encode(email));
+    for list in lists {
+        body.push_str(&format!("&lists={}", list));
+    }
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    // Out of the way: the welcome email
+    app.dispatch_all_pending_emails().await;
+}
+
+fn recipients(requests: &[wiremock::Request]) -> Vec<String> {
+    requests
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_create_list(&serde_json::json!({"slug": "weekly", "name": "Weekly"}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_new_list_shows_up_on_the_lists_page() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    create_list(&app, "weekly-digest", "Weekly digest").await;
+
+    // Assert
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The list 'Weekly digest' has been created."));
+    assert!(html_page.contains("weekly-digest"));
+}
+
+#[tokio::test]
+async fn invalid_or_duplicate_slugs_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Invalid slug
+    create_list(&app, "Not A Slug", "Whatever").await;
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The slug must be 1 to 64 characters long"));
+
+    // Act - Part 2 - Duplicate slug
+    create_list(&app, "newsletter", "Another newsletter").await;
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("A list with the slug 'newsletter' already exists."));
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_returns_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&lists=does-not-exist";
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_api_reports_unknown_lists_as_a_field_error() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "name": "le guin",
+        "email": "ursula_le_guin@gmail.com",
+        "lists": ["does-not-exist"],
+    });
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(422, response.status().as_u16());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["errors"][0]["field"], "lists");
+}
+
+#[tokio::test]
+async fn subscribing_without_picking_a_list_joins_the_default_list() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let membership = sqlx::query!(
+        r#"
+        SELECT l.slug, m.status
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the list membership.");
+    assert_eq!(membership.slug, "newsletter");
+    assert_eq!(membership.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn issues_are_only_delivered_to_the_members_of_the_chosen_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_list(&app, "weekly", "Weekly").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    subscribe_and_confirm(&app, "weekly-reader@example.com", &["weekly"]).await;
+    subscribe_and_confirm(&app, "newsletter-reader@example.com", &["newsletter"]).await;
+    subscribe_and_confirm(&app, "both@example.com", &["newsletter", "weekly"]).await;
+    let n_confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+        "lists": "weekly",
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let requests = app.email_server.received_requests().await.unwrap();
+    let mut recipients = recipients(&requests[n_confirmation_emails..]);
+    recipients.sort();
+    assert_eq!(
+        recipients,
+        vec!["both@example.com", "weekly-reader@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn a_confirmed_subscriber_must_confirm_the_lists_they_join_later() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_list(&app, "weekly", "Weekly").await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    subscribe_and_confirm(&app, "ursula_le_guin@gmail.com", &["newsletter"]).await;
+
+    // Act
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&lists=weekly";
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let statuses = sqlx::query!(
+        r#"
+        SELECT l.slug, m.status
+        FROM list_memberships m
+        JOIN lists l ON l.list_id = m.list_id
+        ORDER BY l.slug
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(statuses[0].slug, "newsletter");
+    assert_eq!(statuses[0].status, "confirmed");
+    assert_eq!(statuses[1].slug, "weekly");
+    assert_eq!(statuses[1].status, "pending_confirmation");
+    // A second confirmation email was sent for the new list, after the welcome email
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 3);
+    let confirmation_links = app.get_confirmation_links(&requests[2]);
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    // They are not welcomed again
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 3);
+    let status = sqlx::query!(
+        "SELECT status FROM list_memberships m JOIN lists l USING (list_id) WHERE l.slug = 'weekly'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status;
+    assert_eq!(status, "confirmed");
+}
@@ -0,0 +1,324 @@
+use crate::helpers::{spawn_app, when_sending_an_email, TestApp};
+use reqwest::Url;
+use wiremock::ResponseTemplate;
+use zero2prod::unsubscribe::unsubscribe_link;
+
+/// Subscribe through the form, as a browser set up for `accept_language` would.
+async fn subscribe(
+    app: &TestApp,
+    body: &[(&str, &str)],
+    accept_language: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Accept-Language", accept_language)
+        .form(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn stored_locale(app: &TestApp) -> String {
+    sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .locale
+}
+
+async fn last_email(app: &TestApp) -> (wiremock::Request, serde_json::Value) {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = serde_json::from_slice(&email_request.body).unwrap();
+    (email_request, body)
+}
+
+const URSULA: [(&str, &str); 2] = [("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+
+#[tokio::test]
+async fn the_confirmation_email_is_written_in_the_browser_language() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = subscribe(&app, &URSULA, "de-DE, fr-CA;q=0.8, en;q=0.5").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_locale(&app).await, "fr-CA");
+    let (email_request, body) = last_email(&app).await;
+    assert_eq!(body["Subject"], "Bienvenue !");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("pour confirmer votre inscription"));
+    assert!(body["HtmlBody"].as_str().unwrap().contains("cliquez"));
+    // The links work the same in every language
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn the_locale_picked_in_the_form_wins_over_the_browser_language() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let mut body = URSULA.to_vec();
+    body.push(("locale", "FR"));
+
+    // Act
+    subscribe(&app, &body, "en-US").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(stored_locale(&app).await, "fr");
+    assert_eq!(last_email(&app).await.1["Subject"], "Bienvenue !");
+}
+
+#[tokio::test]
+async fn locales_without_translations_fall_back_to_english() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let mut body = URSULA.to_vec();
+    body.push(("locale", "de-AT"));
+
+    // Act
+    subscribe(&app, &body, "de-AT").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    // The locale is kept, for when a translation shows up
+    assert_eq!(stored_locale(&app).await, "de-AT");
+    assert_eq!(last_email(&app).await.1["Subject"], "Welcome!");
+}
+
+#[tokio::test]
+async fn unsupported_browser_languages_default_to_english() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    subscribe(&app, &URSULA, "de, *;q=0.5").await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(stored_locale(&app).await, "en");
+    assert_eq!(last_email(&app).await.1["Subject"], "Welcome!");
+}
+
+#[tokio::test]
+async fn an_invalid_locale_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let mut body = URSULA.to_vec();
+    body.push(("locale", "not a locale"));
+
+    // Act
+    let response = subscribe(&app, &body, "fr").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_api_takes_a_locale() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "locale": "fr-BE"
+        }))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    assert_eq!(stored_locale(&app).await, "fr-BE");
+    assert_eq!(last_email(&app).await.1["Subject"], "Bienvenue !");
+}
+
+#[tokio::test]
+async fn the_confirmation_pages_are_in_the_subscriber_language() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app, &URSULA, "fr").await;
+    app.dispatch_all_pending_emails().await;
+    let (email_request, _) = last_email(&app).await;
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+
+    // Act - Follow the link, from a browser in another language
+    let form_page = app
+        .api_client
+        .get(confirmation_link.clone())
+        .header("Accept-Language", "en")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let confirmed_page = app
+        .confirm_subscription(confirmation_link)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(form_page.contains(r#"<html lang="fr">"#));
+    assert!(form_page.contains("Confirmer mon inscription"));
+    assert!(confirmed_page.contains("Votre inscription est confirmée"));
+}
+
+#[tokio::test]
+async fn the_welcome_and_unsubscribe_emails_are_in_the_subscriber_language() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app, &URSULA, "fr").await;
+    app.dispatch_all_pending_emails().await;
+    let (email_request, _) = last_email(&app).await;
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+
+    // Act - Part 1 - Confirm
+    app.confirm_subscription(confirmation_link)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 1
+    let (email_request, body) = last_email(&app).await;
+    assert_eq!(body["Subject"], "Votre inscription est confirmée !");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Vous pouvez vous désinscrire à tout moment"));
+
+    // Act - Part 2 - Unsubscribe with the link of the welcome email
+    let unsubscribe_link = app.get_confirmation_links(&email_request).html;
+    app.api_client
+        .post(unsubscribe_link)
+        .header("Accept-Language", "en")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2
+    let (_, body) = last_email(&app).await;
+    assert_eq!(body["Subject"], "Votre désinscription est confirmée");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains("Réinscrivez-vous"));
+}
+
+#[tokio::test]
+async fn error_pages_for_unknown_links_follow_the_browser_language() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!(
+            "{}/subscriptions/confirm?subscription_token=unknowntoken",
+            &app.address
+        ))
+        .header("Accept-Language", "fr-FR, en;q=0.5")
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Ce lien de confirmation n'est pas valide."));
+}
+
+#[tokio::test]
+async fn the_unsubscribe_pages_are_in_the_subscriber_language() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app, &URSULA, "fr").await;
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let mut link = Url::parse(&unsubscribe_link(
+        &app.base_url,
+        &app.hmac_secret,
+        subscriber_id,
+    ))
+    .unwrap();
+    link.set_port(Some(app.port)).unwrap();
+
+    // Act
+    let form_page = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let unsubscribed_page = app
+        .api_client
+        .post(link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(form_page.contains("Me désinscrire"));
+    assert!(unsubscribed_page.contains("Vous êtes désinscrit"));
+}
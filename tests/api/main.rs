@@ -1,8 +1,22 @@
 mod admin_dashboard;
+mod admin_subscribers;
+mod api_subscriptions;
+mod bot_protection;
 mod change_password;
 mod health_check;
 mod helpers;
+mod issues;
+mod layouts;
+mod lists;
+mod localization;
 mod login;
 mod newsletter;
+mod personal_data;
+mod rate_limit;
+mod signup_attribution;
+mod subscriber_imports;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod webhooks;
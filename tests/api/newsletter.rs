@@ -1,19 +1,10 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
-use fake::{
-    faker::{internet::en::SafeEmail, name::en::Name},
-    Fake,
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    when_sending_an_email,
 };
 use std::time::Duration;
 use tokio::time::sleep;
-use wiremock::{
-    matchers::{any, method, path},
-    Mock, MockBuilder, ResponseTemplate,
-};
-
-// Short-hand for a common mocking setup
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
-}
+use wiremock::{matchers::any, Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -52,12 +43,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // Arrange
     let app = spawn_app().await;
-    let confirmation_link = create_unconfirmed_subscriber(&app).await;
-    reqwest::get(confirmation_link.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
+    create_confirmed_subscriber(&app).await;
 
     when_sending_an_email()
         .respond_with(ResponseTemplate::new(200))
@@ -87,55 +73,6 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     app.dispatch_all_pending_emails().await;
 }
 
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    // We are working with multiple subscribers now,
-    // their details must be randomised to avoid conflicts!
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email
-    }))
-    .unwrap();
-
-    let _mock_guard = when_sending_an_email()
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-    app.test_user.login(&app).await;
-
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(&email_request)
-}
-
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
-
-    reqwest::get(confirmation_links.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
-
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     // Arrange
@@ -283,3 +220,154 @@ async fn failed_requests_get_retried() {
     app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have sent the newsletter email **once**
 }
+
+#[tokio::test]
+async fn merge_tags_are_rendered_for_each_recipient() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "name": "Ursula",
+        "email": "ursula_le_guin@gmail.com",
+        "attributes": {"company": "Earthsea Ltd", "plan": "pro"},
+    });
+    app.post_api_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.confirm_subscription(app.get_confirmation_links(email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ subscriber.name }} from {{ subscriber.company }} ({{ subscriber.city }})",
+        "html_content": "<p>Your plan: {{ subscriber.plan }}, seats: {{ subscriber.seats | default(value=1) }}</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["TextBody"], "Hi Ursula from Earthsea Ltd ()");
+    assert_eq!(body["HtmlBody"], "<p>Your plan: pro, seats: 1</p>");
+}
+
+#[tokio::test]
+async fn newsletters_with_invalid_merge_tags_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "text_content": "Hi {{ subscriber.name",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_written_in_markdown_are_sent_as_html_and_text() {
+    // Arrange
+    let app = spawn_app().await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = serde_json::json!({
+        "name": "Ursula",
+        "email": "ursula_le_guin@gmail.com",
+    });
+    app.post_api_subscriptions(&body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.confirm_subscription(app.get_confirmation_links(email_request).html)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.test_user.login(&app).await;
+    let markdown = "# This week\n\nHi {{ subscriber.name }}, the *news*:\n\n\
+        | Crate | Version |\n|-------|---------|\n| sqlx | 0.6 |\n\n\
+        More to come[^soon].\n\n[^soon]: Next Monday.\n\n<script>alert(1)</script>";
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "markdown_content": markdown,
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["TextBody"],
+        "This week\n=========\n\nHi Ursula, the _news_:\n\n\
+        Crate | Version\n------|--------\nsqlx  | 0.6\n\n\
+        More to come[1].\n\n[1]: Next Monday."
+    );
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains("<h1>This week</h1>"));
+    assert!(html_body.contains("<p>Hi Ursula, the <em>news</em>:</p>"));
+    assert!(html_body.contains("<td>sqlx</td>"));
+    assert!(!html_body.contains("script"));
+    let saved = sqlx::query!("SELECT markdown_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.markdown_content.as_deref(), Some(markdown));
+}
+
+#[tokio::test]
+async fn markdown_and_bodies_written_by_hand_cannot_be_mixed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "markdown_content": "Newsletter body as *Markdown*",
+        "text_content": "Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    });
+    let response = app.post_publish_newsletter(&newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
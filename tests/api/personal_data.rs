@@ -0,0 +1,237 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_an_email, TestApp,
+};
+use reqwest::multipart::{Form, Part};
+use wiremock::ResponseTemplate;
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+/// Publish an issue, leaving its deliveries in the queue.
+async fn publish_newsletter(app: &TestApp) {
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Earthsea news",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    }))
+    .await;
+}
+
+async fn count(app: &TestApp, query: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(query)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_handle_data_subject_requests() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act & Assert
+    assert_is_redirect_to(
+        &app.get_personal_data_export("ursula@gmail.com").await,
+        "/login",
+    );
+    assert_is_redirect_to(
+        &app.post_erase_personal_data("ursula@gmail.com").await,
+        "/login",
+    );
+    assert_is_redirect_to(
+        &app.get_api_personal_data("ursula@gmail.com").await,
+        "/login",
+    );
+    assert_is_redirect_to(
+        &app.post_api_erasure(&serde_json::json!({ "email": "ursula@gmail.com" }))
+            .await,
+        "/login",
+    );
+}
+
+#[tokio::test]
+async fn the_export_bundles_everything_held_about_an_address() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    publish_newsletter(&app).await;
+
+    // Act
+    let response = app.get_personal_data_export(&email.to_uppercase()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Disposition"]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let bundle: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(bundle["subscriber"]["email"], email.as_str());
+    assert_eq!(bundle["subscriber"]["status"], "confirmed");
+    assert_eq!(bundle["status_history"].as_array().unwrap().len(), 2);
+    assert_eq!(bundle["lists"][0]["list"], "newsletter");
+    assert_eq!(bundle["queued_issues"][0]["title"], "Earthsea news");
+    let tokens = bundle["subscription_tokens"].as_array().unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert!(tokens[0].get("subscription_token").is_none());
+}
+
+#[tokio::test]
+async fn erasure_removes_every_trace_of_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    publish_newsletter(&app).await;
+
+    // Act
+    let response = app.post_erase_personal_data(&email).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/personal-data");
+    let html = app.get_personal_data_html().await;
+    assert!(html.contains("The subscriber and their data have been erased."));
+    assert!(!html.contains(&email));
+    for table in [
+        "subscriptions",
+        "subscription_tokens",
+        "list_memberships",
+        "subscription_status_changes",
+        "issue_delivery_queue",
+        "email_outbox",
+    ] {
+        let n_rows = count(&app, &format!("SELECT COUNT(*) FROM {}", table)).await;
+        assert_eq!(n_rows, 0, "{} still has rows", table);
+    }
+    let tombstone = sqlx::query!("SELECT email_hash FROM erased_subscribers")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email_hash;
+    assert_eq!(tombstone.len(), 64);
+    assert!(!tombstone.contains(&email));
+}
+
+#[tokio::test]
+async fn erased_addresses_cannot_be_imported_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_erase_personal_data("Ursula.LeGuin@gmail.com")
+        .await;
+
+    // Act
+    let form = Form::new().part(
+        "file",
+        Part::text("email,name\nursulaleguin@gmail.com,Ursula\n")
+            .file_name("audience.csv")
+            .mime_str("text/csv")
+            .unwrap(),
+    );
+    let response = app.post_import_subscribers(form).await;
+
+    // Assert
+    let html = app.get_location(&response).await.text().await.unwrap();
+    assert!(html.contains("Rejected: 1"));
+    assert_eq!(count(&app, "SELECT COUNT(*) FROM subscriptions").await, 0);
+}
+
+#[tokio::test]
+async fn subscribing_again_lifts_the_erasure() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_erase_personal_data("Ursula.LeGuin@gmail.com")
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursulaleguin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    assert_eq!(
+        count(&app, "SELECT COUNT(*) FROM erased_subscribers").await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn data_subject_requests_can_be_handled_through_the_api() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+
+    // Act - Part 1 - Access
+    let response = app.get_api_personal_data(&email).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let bundle: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(bundle["subscriber"]["email"], email.as_str());
+
+    // Act - Part 2 - Erasure
+    let response = app
+        .post_api_erasure(&serde_json::json!({ "email": email }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let erasure: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(erasure["subscriber_deleted"], true);
+
+    // Act - Part 3 - Nothing is left
+    let bundle: serde_json::Value = app
+        .get_api_personal_data(&email)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(bundle["subscriber"].is_null());
+
+    // Act - Part 4 - The address is required
+    let response = app
+        .post_api_erasure(&serde_json::json!({ "email": " " }))
+        .await;
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn the_export_covers_what_is_held_under_other_spellings() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!(
+        "UPDATE subscriptions SET email = 'Ursula.Le.Guin@gmail.com', canonical_email = 'ursulaleguin@gmail.com'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    publish_newsletter(&app).await;
+    app.post_suppressions(
+        "",
+        &serde_json::json!({ "email": "Ursula.Le.Guin@gmail.com", "reason": "Legal request" }),
+    )
+    .await;
+
+    // Act
+    let response = app
+        .get_personal_data_export("ursulaleguin+news@gmail.com")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let bundle: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(bundle["subscriber"]["email"], "Ursula.Le.Guin@gmail.com");
+    assert_eq!(bundle["queued_issues"][0]["title"], "Earthsea news");
+    assert_eq!(bundle["suppressions"][0]["reason"], "Legal request");
+}
@@ -0,0 +1,140 @@
+use crate::helpers::{spawn_app_with, when_sending_an_email};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+use zero2prod::configuration::RateLimitBackend;
+
+#[tokio::test]
+async fn subscribing_the_same_email_too_often_returns_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_email.max_requests = 2;
+    })
+    .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // Act
+    for _ in 0..2 {
+        let response = app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    // The address is what counts, not how it is written
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 3600);
+}
+
+#[tokio::test]
+async fn resending_confirmations_counts_against_the_email_limit() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_email.max_requests = 1;
+    })
+    .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn a_client_sending_too_many_requests_gets_a_429() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip.max_requests = 3;
+    })
+    .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for i in 0..3 {
+        let body = format!("name=le%20guin&email=ursula{}%40gmail.com", i);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app
+        .post_api_subscriptions(&serde_json::json!({
+            "name": "le guin",
+            "email": "another_ursula@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn rate_limited_requests_do_not_reach_the_handler() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_email.max_requests = 1;
+    })
+    .await;
+    let body = serde_json::json!({"name": "le guin", "email": "ursula_le_guin@gmail.com"});
+    app.post_api_subscriptions(&body).await;
+    sqlx::query!("DELETE FROM email_outbox")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 429);
+    let n_queued_emails = sqlx::query!("SELECT count(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued_emails, 0);
+}
+
+#[tokio::test]
+async fn the_redis_backend_enforces_the_limits() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.rate_limit.backend = RateLimitBackend::Redis;
+        c.rate_limit.per_email.max_requests = 1;
+        // Redis outlives the test: do not let this IP's counter leak into other runs
+        c.rate_limit.per_ip.window_seconds = 1;
+    })
+    .await;
+    // A fresh address for each run, for the same reason
+    let email = format!("{}@example.com", Uuid::new_v4());
+    let body = serde_json::json!({"name": "le guin", "email": email});
+
+    // Act
+    let response1 = app.post_api_subscriptions(&body).await;
+    let response2 = app.post_api_subscriptions(&body).await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 202);
+    assert_eq!(response2.status().as_u16(), 429);
+}
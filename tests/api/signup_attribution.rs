@@ -0,0 +1,212 @@
+use crate::helpers::{spawn_app, TestApp};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn subscribe(app: &TestApp, query: &str, body: &str, referrer: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/subscriptions{}", &app.address, query))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Referer", referrer)
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn utm_parameters_are_stored_with_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = subscribe(
+        &app,
+        "?utm_source=mastodon&utm_medium=social&utm_campaign=launch&utm_term=rust&utm_content=toot",
+        BODY,
+        "https://fosstodon.org/@zero2prod",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(
+        "SELECT signup_source, utm_source, utm_medium, utm_campaign, utm_term, utm_content, referrer
+        FROM subscriptions"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.signup_source, None);
+    assert_eq!(saved.utm_source.as_deref(), Some("mastodon"));
+    assert_eq!(saved.utm_medium.as_deref(), Some("social"));
+    assert_eq!(saved.utm_campaign.as_deref(), Some("launch"));
+    assert_eq!(saved.utm_term.as_deref(), Some("rust"));
+    assert_eq!(saved.utm_content.as_deref(), Some("toot"));
+    assert_eq!(
+        saved.referrer.as_deref(),
+        Some("https://fosstodon.org/@zero2prod")
+    );
+}
+
+#[tokio::test]
+async fn the_form_fields_win_over_the_query_string() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = format!(
+        "{}&source=footer-embed&utm_campaign=%20%20&referrer=https%3A%2F%2Fblog.example.com%2Fpost",
+        BODY
+    );
+
+    // Act
+    subscribe(
+        &app,
+        "?source=ignored&utm_campaign=spring",
+        &body,
+        "https://embed.example.com/",
+    )
+    .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT signup_source, utm_campaign, referrer FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.signup_source.as_deref(), Some("footer-embed"));
+    // Blank fields do not hide the query string
+    assert_eq!(saved.utm_campaign.as_deref(), Some("spring"));
+    assert_eq!(
+        saved.referrer.as_deref(),
+        Some("https://blog.example.com/post")
+    );
+}
+
+#[tokio::test]
+async fn our_own_pages_are_not_recorded_as_referrers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    subscribe(&app, "", BODY, &format!("{}/", app.address)).await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT referrer FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.referrer, None);
+}
+
+#[tokio::test]
+async fn returning_subscribers_keep_their_first_attribution() {
+    // Arrange
+    let app = spawn_app().await;
+    subscribe(&app, "?utm_source=newsletter-swap", BODY, "").await;
+
+    // Act
+    subscribe(&app, "?utm_source=twitter", BODY, "").await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT utm_source FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.utm_source.as_deref(), Some("newsletter-swap"));
+}
+
+#[tokio::test]
+async fn the_home_page_carries_the_attribution_over_to_the_form() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(format!(
+            "{}/?utm_source=podcast&utm_campaign=%22ep12%22",
+            &app.address
+        ))
+        .header("Referer", "https://podcasts.example.com/episodes/12")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains(r#"<input type="hidden" name="utm_source" value="podcast" />"#));
+    assert!(html_page
+        .contains(r#"<input type="hidden" name="utm_campaign" value="&quot;ep12&quot;" />"#));
+    assert!(html_page.contains(
+        r#"<input type="hidden" name="referrer" value="https:&#x2F;&#x2F;podcasts.example.com&#x2F;episodes&#x2F;12" />"#
+    ));
+    assert!(!html_page.contains(r#"name="utm_medium""#));
+}
+
+#[tokio::test]
+async fn the_dashboard_counts_signups_by_source() {
+    // Arrange
+    let app = spawn_app().await;
+    for (i, query, referrer) in [
+        (1, "?source=footer-embed", ""),
+        (2, "?source=footer-embed&utm_source=mastodon", ""),
+        (3, "?utm_source=mastodon", ""),
+        (4, "", "https://news.ycombinator.com/item?id=1"),
+        (5, "", ""),
+    ] {
+        let body = format!("name=reader&email=reader{}%40gmail.com", i);
+        subscribe(&app, query, &body, referrer).await;
+    }
+    sqlx::query!("UPDATE subscriptions SET status = 'confirmed' WHERE utm_source = 'mastodon'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+
+    // Assert
+    let html_page: String = html_page.split_whitespace().collect();
+    let row = |source: &str, n_signups: u32, n_confirmed: u32| {
+        format!(
+            "<td>{}</td><td>{}</td><td>{}</td>",
+            source, n_signups, n_confirmed
+        )
+    };
+    assert!(html_page.contains(&row("footer-embed", 2, 1)));
+    assert!(html_page.contains(&row("mastodon", 1, 1)));
+    assert!(html_page.contains(&row("news.ycombinator.com", 1, 0)));
+    assert!(html_page.contains(&row("<em>Directorunknown</em>", 1, 0)));
+}
+
+#[tokio::test]
+async fn mangled_campaign_links_are_parsed_leniently() {
+    // Arrange
+    let app = spawn_app().await;
+    let query = "?utm_source=a&utm_source=b&utm_medium=%FF&utm_campaign=spring";
+
+    // Act - Part 1 - Load the landing page
+    let response = app
+        .api_client
+        .get(format!("{}/{}", &app.address, query))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Subscribe
+    let response = subscribe(&app, query, BODY, "").await;
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT utm_source, utm_medium, utm_campaign FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.utm_source.as_deref(), Some("a"));
+    assert_eq!(saved.utm_medium, None);
+    assert_eq!(saved.utm_campaign.as_deref(), Some("spring"));
+}
@@ -0,0 +1,298 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, when_sending_an_email, TestApp};
+use reqwest::multipart::{Form, Part};
+use wiremock::ResponseTemplate;
+
+fn csv_form(csv: &str) -> Form {
+    Form::new().part(
+        "file",
+        Part::text(csv.to_owned())
+            .file_name("audience.csv")
+            .mime_str("text/csv")
+            .unwrap(),
+    )
+}
+
+/// The options must come before the file, as they do in the admin form.
+fn pre_confirmed_csv_form(consent_source: &str, csv: &str) -> Form {
+    Form::new()
+        .text("pre_confirmed", "on")
+        .text("consent_source", consent_source.to_owned())
+        .part(
+            "file",
+            Part::text(csv.to_owned())
+                .file_name("audience.csv")
+                .mime_str("text/csv")
+                .unwrap(),
+        )
+}
+
+async fn subscribers(app: &TestApp) -> Vec<(String, String, String, Option<String>)> {
+    sqlx::query!("SELECT email, name, status, consent_source FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.email, r.name, r.status, r.consent_source))
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(csv_form("email,name\nursula@gmail.com,Ursula\n"))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn imported_subscribers_receive_a_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(csv_form(
+            "Name,Email\nUrsula,ursula@gmail.com\nOctavia,octavia@example.com\n",
+        ))
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    let html = app.get_location(&response).await.text().await.unwrap();
+    assert!(html.contains("Created: 2"));
+    assert!(!html.contains("errors.csv"));
+    assert_eq!(
+        subscribers(&app).await,
+        vec![
+            (
+                "octavia@example.com".into(),
+                "Octavia".into(),
+                "pending_confirmation".into(),
+                None
+            ),
+            (
+                "ursula@gmail.com".into(),
+                "Ursula".into(),
+                "pending_confirmation".into(),
+                None
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pre_confirmed_subscribers_record_their_consent_source() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_import_subscribers(pre_confirmed_csv_form(
+        "Conference signup sheet",
+        "email,name\nursula@gmail.com,Ursula\n",
+    ))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        subscribers(&app).await,
+        vec![(
+            "ursula@gmail.com".into(),
+            "Ursula".into(),
+            "confirmed".into(),
+            Some("Conference signup sheet".into())
+        )]
+    );
+}
+
+#[tokio::test]
+async fn pre_confirmed_imports_require_a_consent_source() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(pre_confirmed_csv_form(
+            " ",
+            "email,name\nursula@gmail.com,Ursula\n",
+        ))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let html = app.get_import_subscribers_html().await;
+    assert!(html.contains("<i>Record how the subscribers gave their consent"));
+    assert!(subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn a_file_without_an_email_column_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(csv_form("address,name\nursula@gmail.com,Ursula\n"))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/subscribers/import");
+    let html = app.get_import_subscribers_html().await;
+    assert!(html.contains("<i>"));
+    assert!(subscribers(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn rejected_rows_are_listed_in_the_error_report() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status)
+        VALUES ($1, 'octavia@example.com', 'octavia@example.com', 'Octavia', NOW(), 'unsubscribed')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = app
+        .post_import_subscribers(csv_form(
+            "email,name\n\
+             ursula@gmail.com,Ursula\n\
+             not-an-email,Nobody\n\
+             ursula@gmail.com,Ursula again\n\
+             octavia@example.com,Octavia\n",
+        ))
+        .await;
+
+    // Assert
+    let html = app.get_location(&response).await.text().await.unwrap();
+    assert!(html.contains("Rows: 4"));
+    assert!(html.contains("Created: 1"));
+    assert!(html.contains("Rejected: 3"));
+    let (_, report_path) = html
+        .split_once("href=\"/admin/subscribers/imports/")
+        .unwrap();
+    let (report_path, _) = report_path.split_once('"').unwrap();
+    let report_path = format!("/admin/subscribers/imports/{}", report_path);
+    assert!(report_path.ends_with("/errors.csv"));
+    let report = app
+        .api_client
+        .get(format!("{}{}", app.address, report_path))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(report.status().as_u16(), 200);
+    assert!(report.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/csv"));
+    let report = report.text().await.unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 1 + 3);
+    assert!(lines[1].starts_with("3,not-an-email,"));
+    assert!(lines[2].starts_with("4,ursula@gmail.com,"));
+    assert!(lines[3].starts_with("5,octavia@example.com,"));
+}
+
+#[tokio::test]
+async fn importing_known_addresses_updates_them() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_import_subscribers(csv_form("email,name\nursula@gmail.com,Ursula\n"))
+        .await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(pre_confirmed_csv_form(
+            "Signed paper form",
+            "email,name\nursula@gmail.com,Ursula K. Le Guin\n",
+        ))
+        .await;
+
+    // Assert
+    let html = app.get_location(&response).await.text().await.unwrap();
+    assert!(html.contains("Created: 0"));
+    assert!(html.contains("Updated: 1"));
+    assert_eq!(
+        subscribers(&app).await,
+        vec![(
+            "ursula@gmail.com".into(),
+            "Ursula K. Le Guin".into(),
+            "confirmed".into(),
+            Some("Signed paper form".into())
+        )]
+    );
+}
+
+#[tokio::test]
+async fn other_spellings_of_an_address_are_recognised() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_import_subscribers(csv_form("email,name\nUrsula.LeGuin@gmail.com,Ursula\n"))
+        .await;
+
+    // Act
+    let response = app
+        .post_import_subscribers(csv_form(
+            "email,name\n\
+             ursulaleguin@gmail.com,Ursula K. Le Guin\n\
+             URSULA.LEGUIN+news@GMAIL.com,Ursula again\n",
+        ))
+        .await;
+
+    // Assert
+    let html = app.get_location(&response).await.text().await.unwrap();
+    assert!(html.contains("Created: 0"));
+    assert!(html.contains("Updated: 1"));
+    assert!(html.contains("Rejected: 1"));
+    assert_eq!(
+        subscribers(&app).await,
+        vec![(
+            "Ursula.LeGuin@gmail.com".into(),
+            "Ursula K. Le Guin".into(),
+            "pending_confirmation".into(),
+            None
+        )]
+    );
+}
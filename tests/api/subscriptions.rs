@@ -1,8 +1,12 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::{
+    domain::{CanonicalEmailRules, ProviderRule},
+    subscriber_canonicalization::canonicalize_subscribers,
+};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -19,6 +23,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 
     // Act
     let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 200);
@@ -40,6 +45,7 @@ async fn subscribe_persists_the_new_subscriber() {
 
     // Act
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     let saved = sqlx::query!("select email, name, status from subscriptions")
@@ -99,6 +105,40 @@ async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_returns_a_400_for_undeliverable_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "name=Ursula&email=ursula%40mailinator.com",
+            "a disposable email provider",
+        ),
+        (
+            "name=Ursula&email=ursula%40no-mail-server.example",
+            "a domain without mail servers",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_subscriptions(body.into()).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for an address on {}.",
+            description
+        )
+    }
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert!(saved.is_none());
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_email_data() {
     // Arrange
@@ -114,6 +154,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_email_data() {
 
     // Act
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     // Mock asserts on drop
@@ -133,6 +174,7 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
 
     // Act
     app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
 
     // Assert
     // Get the first intercepted request
@@ -158,3 +200,344 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_sends_the_confirmation_email_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response1 = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let response2 = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+
+    // Only the most recent link can be used
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let second_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, second_links.html);
+    let response = reqwest::get(first_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+    let response = reqwest::get(second_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    let n_subscribers = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 1);
+}
+
+#[tokio::test]
+async fn subscribing_a_confirmed_subscriber_succeeds_without_sending_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // The confirmation and the welcome emails
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribing_again_after_unsubscribing_reopens_the_double_opt_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_even_if_the_email_provider_is_down() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Subscribe while the email provider fails
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 200);
+    let outbox = sqlx::query!("SELECT n_retries FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.n_retries, 1);
+
+    // Act - Part 2 - The email provider recovers
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE email_outbox SET execute_after = NOW()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+    let n_pending = sqlx::query!("SELECT count(*) AS \"count!\" FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_pending, 0);
+}
+
+#[tokio::test]
+async fn another_spelling_of_a_known_address_is_the_same_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for body in [
+        "name=le%20guin&email=Ursula.Le.Guin%40Gmail.com",
+        "name=le%20guin&email=ursulaleguin%2Bnews%40gmail.com",
+    ] {
+        let response = app.post_subscriptions(body.into()).await;
+        app.dispatch_all_pending_emails().await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, canonical_email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    // The address is stored as it was first typed
+    assert_eq!(saved[0].email, "Ursula.Le.Guin@Gmail.com");
+    assert_eq!(saved[0].canonical_email, "ursulaleguin@gmail.com");
+}
+
+#[tokio::test]
+async fn gmail_spellings_are_distinct_subscribers_without_the_provider_rule() {
+    // Arrange
+    let app = spawn_app_with(|c| c.canonical_emails.provider_rules.clear()).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for body in [
+        "name=le%20guin&email=Ursula.Le.Guin%40Gmail.com",
+        "name=le%20guin&email=ursulaleguin%2Bnews%40gmail.com",
+    ] {
+        let response = app.post_subscriptions(body.into()).await;
+        app.dispatch_all_pending_emails().await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let saved = sqlx::query!("SELECT canonical_email FROM subscriptions ORDER BY canonical_email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let canonical_emails: Vec<_> = saved.into_iter().map(|r| r.canonical_email).collect();
+    assert_eq!(
+        canonical_emails,
+        ["ursula.le.guin@gmail.com", "ursulaleguin+news@gmail.com"]
+    );
+}
+
+#[tokio::test]
+async fn enabling_a_provider_rule_merges_the_subscribers_it_folds_together() {
+    // Arrange
+    let app = spawn_app_with(|c| c.canonical_emails.provider_rules.clear()).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    for body in [
+        "name=le%20guin&email=Ursula.Le.Guin%40Gmail.com",
+        "name=le%20guin&email=ursulaleguin%2Bnews%40gmail.com",
+    ] {
+        app.post_subscriptions(body.into())
+            .await
+            .error_for_status()
+            .unwrap();
+        app.dispatch_all_pending_emails().await;
+    }
+    // Only the second spelling is confirmed
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    app.confirm_subscription(confirmation_links.html)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let rules = CanonicalEmailRules::new(vec![ProviderRule::Gmail]);
+
+    // Act - Part 1 - Dry run
+    let report = canonicalize_subscribers(&app.db_pool, &rules, true)
+        .await
+        .unwrap();
+
+    // Assert - Part 1
+    assert_eq!(report.merges.len(), 1);
+    assert_eq!(report.merges[0].keeper_email, "Ursula.Le.Guin@Gmail.com");
+    assert_eq!(
+        report.merges[0].duplicate_email,
+        "ursulaleguin+news@gmail.com"
+    );
+    let n_subscribers = sqlx::query!("SELECT COUNT(*) AS \"n!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_subscribers, 2);
+
+    // Act - Part 2
+    canonicalize_subscribers(&app.db_pool, &rules, false)
+        .await
+        .unwrap();
+
+    // Assert - Part 2
+    let saved = sqlx::query!("SELECT email, canonical_email, status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    // The oldest spelling is kept, with the decision taken under the other one
+    assert_eq!(saved[0].email, "Ursula.Le.Guin@Gmail.com");
+    assert_eq!(saved[0].canonical_email, "ursulaleguin@gmail.com");
+    assert_eq!(saved[0].status, "confirmed");
+}
+
+#[tokio::test]
+async fn subscribe_accepts_internationalized_domains() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.email_verification.static_mail_servers.insert(
+            "xn--bcher-kva.de".into(),
+            vec!["mx.xn--bcher-kva.de".into()],
+        );
+    })
+    .await;
+    let body = "name=le%20guin&email=ursula%40B%C3%BCcher.de";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email, canonical_email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula@Bücher.de");
+    assert_eq!(saved.canonical_email, "ursula@xn--bcher-kva.de");
+}
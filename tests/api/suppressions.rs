@@ -0,0 +1,331 @@
+use crate::helpers::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    spawn_app_with, when_sending_an_email, TestApp,
+};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+async fn suppression_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT suppression_id FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .suppression_id
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Earthsea news",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    })
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_suppressions() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let page = app.get_suppressions().await;
+    let create = app
+        .post_suppressions(
+            "",
+            &serde_json::json!({ "email": "ursula@gmail.com", "reason": "Legal request" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&page, "/login");
+    assert_is_redirect_to(&create, "/login");
+}
+
+#[tokio::test]
+async fn suppressions_can_be_created_updated_and_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Create
+    let response = app
+        .post_suppressions(
+            "",
+            &serde_json::json!({ "email": "ursula@gmail.com", "reason": "Legal request" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("ursula@gmail.com has been suppressed."));
+    assert!(html_page.contains("Legal request"));
+    assert!(html_page.contains(&format!("admin: {}", app.test_user.username)));
+
+    // Act - Update
+    let suppression_id = suppression_id(&app).await;
+    let response = app
+        .post_suppressions(
+            &format!("/{}", suppression_id),
+            &serde_json::json!({ "reason": "Role account" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("Role account"));
+    assert!(!html_page.contains(r#"value="Legal request""#));
+
+    // Act - Delete
+    let response = app
+        .post_suppressions(&format!("/{}/delete", suppression_id), &())
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("ursula@gmail.com is no longer suppressed."));
+    let n_suppressions = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM suppressions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_suppressions, 0);
+}
+
+#[tokio::test]
+async fn an_address_is_suppressed_once_regardless_of_case() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let body = serde_json::json!({ "email": "ursula@gmail.com", "reason": "Legal request" });
+    app.post_suppressions("", &body).await;
+
+    // Act
+    let response = app
+        .post_suppressions(
+            "",
+            &serde_json::json!({ "email": "Ursula@Gmail.com", "reason": "Legal request" }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("Ursula@Gmail.com is already suppressed."));
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "email": "not-an-email", "reason": "Legal request" }),
+            "not a valid subscriber email",
+        ),
+        (
+            serde_json::json!({ "email": "ursula@gmail.com", "reason": "  " }),
+            "Give a reason to suppress the address.",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_suppressions("", &body).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/suppressions");
+        let html_page = app.get_suppressions_html().await;
+        assert!(html_page.contains(error_message), "{}", html_page);
+    }
+}
+
+#[tokio::test]
+async fn unknown_suppressions_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let suppression_id = Uuid::new_v4();
+
+    // Act
+    let update = app
+        .post_suppressions(
+            &format!("/{}", suppression_id),
+            &serde_json::json!({ "reason": "Role account" }),
+        )
+        .await;
+    let delete = app
+        .post_suppressions(&format!("/{}/delete", suppression_id), &())
+        .await;
+
+    // Assert
+    assert_eq!(update.status().as_u16(), 404);
+    assert_eq!(delete.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_are_not_sent_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    app.post_suppressions(
+        "",
+        &serde_json::json!({ "email": email.to_uppercase(), "reason": "Legal request" }),
+    )
+    .await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn an_address_suppressed_after_enqueueing_is_not_sent_the_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    app.post_publish_newsletter(&newsletter_request_body())
+        .await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_suppressions(
+        "",
+        &serde_json::json!({ "email": email, "reason": "Legal request" }),
+    )
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_confirmation_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_subscriber(&app).await;
+    let email = subscriber_email(&app).await;
+    app.post_suppressions(
+        "",
+        &serde_json::json!({ "email": email, "reason": "Legal request" }),
+    )
+    .await;
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Ask for the confirmation email again
+    app.post_resend_confirmation(serde_urlencoded::to_string([("email", &email)]).unwrap())
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_outbox = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_outbox, 0);
+    // Mock verifies on Drop that we haven't sent the confirmation email
+}
+
+#[tokio::test]
+async fn other_spellings_of_a_suppressed_address_are_not_emailed() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.email_verification.static_mail_servers.insert(
+            "xn--bcher-kva.de".into(),
+            vec!["mx.xn--bcher-kva.de".into()],
+        );
+    })
+    .await;
+    app.test_user.login(&app).await;
+    for email in ["ursula.le.guin@gmail.com", "ursula@Bücher.de"] {
+        app.post_suppressions(
+            "",
+            &serde_json::json!({ "email": email, "reason": "Legal request" }),
+        )
+        .await;
+    }
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Suppress a spelling again
+    let response = app
+        .post_suppressions(
+            "",
+            &serde_json::json!({ "email": "UrsulaLeGuin@gmail.com", "reason": "Legal request" }),
+        )
+        .await;
+
+    // Assert - Part 1
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("UrsulaLeGuin@gmail.com is already suppressed."));
+
+    // Act - Part 2 - Sign up under other spellings
+    for email in ["ursulaleguin+news@gmail.com", "ursula@xn--bcher-kva.de"] {
+        app.post_subscriptions(
+            serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap(),
+        )
+        .await;
+    }
+    app.dispatch_all_pending_emails().await;
+
+    // Assert - Part 2
+    let n_outbox = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_outbox, 0);
+    // Mock verifies on Drop that we haven't sent the confirmation emails
+}
@@ -0,0 +1,259 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, when_sending_an_email, TestApp};
+use wiremock::ResponseTemplate;
+
+async fn subscriber(app: &TestApp) -> (String, String) {
+    let r = sqlx::query!("SELECT email, status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (r.email, r.status)
+}
+
+async fn n_delivery_events(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM delivery_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+fn bounce(email: &str, bounce_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "MessageStream": "outbound",
+        "ID": 4323372036854775807_i64,
+        "Type": bounce_type,
+        "TypeCode": 1,
+        "Name": "Hard bounce",
+        "Tag": "",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "ServerID": 23,
+        "Description": "The server was unable to deliver your message (ex: unknown user, mailbox not found).",
+        "Details": "Test bounce details",
+        "Email": email,
+        "From": "sender@example.com",
+        "BouncedAt": "2023-02-13T16:33:54.9070259Z",
+        "DumpAvailable": true,
+        "Inactive": true,
+        "CanActivate": true,
+        "Subject": "Earthsea news",
+        "Metadata": {}
+    })
+}
+
+#[tokio::test]
+async fn webhooks_require_the_configured_credentials() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = bounce("ursula@gmail.com", "HardBounce");
+
+    for credentials in [None, Some(("postmark", "wrong-password"))] {
+        // Act
+        let mut request = app
+            .api_client
+            .post(format!("{}/webhooks/postmark", &app.address))
+            .json(&body);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="webhooks""#
+        );
+    }
+    assert_eq!(n_delivery_events(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_stops_the_newsletter_for_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&bounce(&email.to_uppercase(), "HardBounce"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber(&app).await.1, "bounced");
+    assert_eq!(n_delivery_events(&app).await, 1);
+
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_publish_newsletter(&serde_json::json!({
+        "title": "Earthsea news",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_spam_complaint_stops_the_newsletter_for_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+    let mut complaint = bounce(&email, "SpamComplaint");
+    complaint["RecordType"] = "SpamComplaint".into();
+
+    // Act
+    let response = app.post_postmark_webhook(&complaint).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber(&app).await.1, "complained");
+}
+
+#[tokio::test]
+async fn a_soft_bounce_is_recorded_without_stopping_the_newsletter() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&bounce(&email, "SoftBounce"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber(&app).await.1, "confirmed");
+    assert_eq!(n_delivery_events(&app).await, 1);
+}
+
+#[tokio::test]
+async fn redelivered_webhooks_are_recorded_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let delivery = serde_json::json!({
+        "RecordType": "Delivery",
+        "ServerID": 23,
+        "MessageStream": "outbound",
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "Recipient": "ursula@gmail.com",
+        "Tag": "",
+        "DeliveredAt": "2023-02-13T16:33:54.9070259Z",
+        "Details": "Test delivery webhook details",
+        "Metadata": {}
+    });
+
+    // Act
+    for _ in 0..2 {
+        let response = app.post_postmark_webhook(&delivery).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    assert_eq!(n_delivery_events(&app).await, 1);
+}
+
+#[tokio::test]
+async fn every_open_is_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let open = serde_json::json!({
+        "RecordType": "Open",
+        "MessageStream": "outbound",
+        "FirstOpen": true,
+        "Platform": "WebMail",
+        "ReadSeconds": 5,
+        "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+        "ReceivedAt": "2023-02-13T16:33:54.9070259Z",
+        "Recipient": "ursula@gmail.com"
+    });
+
+    // Act
+    for _ in 0..2 {
+        app.post_postmark_webhook(&open).await;
+    }
+
+    // Assert
+    let event_types: Vec<String> = sqlx::query!("SELECT event_type FROM delivery_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.event_type)
+        .collect();
+    assert_eq!(event_types, vec!["open", "open"]);
+}
+
+#[tokio::test]
+async fn unknown_record_types_are_acknowledged_and_malformed_ones_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let click = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Click",
+            "Recipient": "ursula@gmail.com"
+        }))
+        .await;
+    let malformed = app
+        .post_postmark_webhook(&serde_json::json!({ "RecordType": "Bounce" }))
+        .await;
+
+    // Assert
+    assert_eq!(click.status().as_u16(), 200);
+    assert_eq!(malformed.status().as_u16(), 400);
+    assert_eq!(n_delivery_events(&app).await, 0);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_keep_their_status_on_bounce() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    app.post_postmark_webhook(&bounce(&email, "HardBounce"))
+        .await;
+
+    // Assert
+    assert_eq!(subscriber(&app).await.1, "unsubscribed");
+}
+
+#[tokio::test]
+async fn a_bounced_address_subscribing_again_gets_no_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let (email, _) = subscriber(&app).await;
+    app.post_postmark_webhook(&bounce(&email, "HardBounce"))
+        .await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let body =
+        serde_urlencoded::to_string([("name", "le guin"), ("email", email.as_str())]).unwrap();
+    let response = app.post_subscriptions(body).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber(&app).await.1, "bounced");
+}